@@ -1,36 +1,52 @@
 use clap::ArgMatches;
-use prefixed_api_key::PrefixedApiKey;
+use prefixed_api_key::rand::rngs::OsRng;
+use prefixed_api_key::sha2::digest::FixedOutputReset;
+use prefixed_api_key::sha2::{Digest, Sha224, Sha256, Sha384, Sha512, Sha512_224, Sha512_256};
 use prefixed_api_key::PrefixedApiKeyController;
+use prefixed_api_key::{KeyFormats, PrefixedApiKey};
 
-pub fn check(matches: &ArgMatches) {
+pub fn check(matches: &ArgMatches) -> Result<(), String> {
     let token = matches
         .get_one::<String>("TOKEN")
-        .map(String::as_str)
-        .expect("Token is required");
+        .ok_or("token is required")?;
     let hash = matches
         .get_one::<String>("HASH")
-        .map(String::as_str)
-        .expect("Hash is required");
+        .ok_or("hash is required")?;
+    let digest_name = matches
+        .get_one::<String>("digest")
+        .ok_or("digest is required")?;
 
-    // Generate configs
-    let digest_name = matches.get_one::<String>("digest").map(String::as_str);
+    // Parse with every known key format so keys with environment or version segments work
+    let pak = KeyFormats::new()
+        .parse(token)
+        .map_err(|err| format!("token was incorrectly formatted: {}", err))?;
 
+    let result = match digest_name.as_str() {
+        "sha224" => check_hash::<Sha224>(&pak, hash),
+        "sha256" => check_hash::<Sha256>(&pak, hash),
+        "sha384" => check_hash::<Sha384>(&pak, hash),
+        "sha512" => check_hash::<Sha512>(&pak, hash),
+        "sha512_224" => check_hash::<Sha512_224>(&pak, hash),
+        "sha512_256" => check_hash::<Sha512_256>(&pak, hash),
+        other => return Err(format!("unsupported digest type: {}", other)),
+    }?;
+    println!("Match:\t{}", result);
+    Ok(())
+}
+
+fn check_hash<D: Digest + FixedOutputReset>(
+    pak: &PrefixedApiKey,
+    hash: &str,
+) -> Result<bool, String> {
     // Can't create a controller without an rng source, even though we won't be using it here
     // so we're just going to use the OsRng source 🤷‍♂️
     // TODO: Provide a way to hash/check tokens without requiring an RNG
-    let mut builder = PrefixedApiKeyController::configure()
+    let controller = PrefixedApiKeyController::<OsRng, D>::configure()
         .prefix("".to_owned())
-        .rng_osrng()
-        .default_lengths();
+        .rng(OsRng)
+        .default_lengths()
+        .finalize()
+        .map_err(|err| format!("failed to create pak controller: {}", err))?;
 
-    builder = match digest_name.unwrap() {
-        "sha256" => builder.digest_sha256(),
-        _ => panic!("unsupported digest type"),
-    };
-
-    let controller = builder.finalize().expect("failed to create pak controller");
-
-    let pak: PrefixedApiKey = token.try_into().expect("token was incorrectly formatted");
-    let result = controller.check_hash(&pak, hash);
-    println!("Match:\t{}", result);
+    Ok(controller.check_hash(pak, hash))
 }
@@ -1,49 +1,85 @@
 use clap::ArgMatches;
+use prefixed_api_key::rand::rngs::{OsRng, StdRng, ThreadRng};
+use prefixed_api_key::rand::{RngCore, SeedableRng};
+use prefixed_api_key::sha2::digest::FixedOutputReset;
+use prefixed_api_key::sha2::{Digest, Sha224, Sha256, Sha384, Sha512, Sha512_224, Sha512_256};
 use prefixed_api_key::PrefixedApiKeyController;
 
-pub fn generate(matches: &ArgMatches) {
-    let prefix = matches.get_one::<String>("PREFIX").map(String::as_str);
+/// The key settings which don't depend on the rng or digest
+struct KeyOptions {
+    prefix: String,
+    short_length: usize,
+    short_prefix: Option<String>,
+    long_length: usize,
+}
+
+pub fn generate(matches: &ArgMatches) -> Result<(), String> {
+    let prefix = matches
+        .get_one::<String>("PREFIX")
+        .ok_or("prefix is required")?;
 
     // Generate configs
     let rng_name = matches
         .get_one::<String>("rng")
-        .map(String::as_str)
-        .expect("RNG name is required");
+        .ok_or("rng name is required")?;
     let digest_name = matches
         .get_one::<String>("digest")
-        .map(String::as_str)
-        .expect("Digest name is required");
+        .ok_or("digest name is required")?;
     let short_length = matches
-        .get_one::<String>("short-token-length")
-        .map(|v| v.parse::<usize>().expect("invalid usize value"))
-        .expect("Short token length is required");
-    let short_prefix = matches
-        .get_one::<String>("short-token-prefix")
-        .map(String::as_str);
+        .get_one::<usize>("short-token-length")
+        .ok_or("short token length is required")?;
+    let short_prefix = matches.get_one::<String>("short-token-prefix");
     let long_length = matches
-        .get_one::<String>("long-token-length")
-        .map(|v| v.parse::<usize>().expect("invalid usize value"))
-        .expect("Long token length is required");
-
-    let mut builder = PrefixedApiKeyController::configure()
-        .prefix(prefix.unwrap().to_string())
-        .rng_osrng()
-        .short_token_length(short_length)
-        .short_token_prefix(short_prefix.map(|v| v.to_owned()))
-        .long_token_length(long_length);
-
-    builder = match rng_name {
-        "osrng" => builder.rng_osrng(),
-        _ => panic!("unsupported rng type"),
-    };
+        .get_one::<usize>("long-token-length")
+        .ok_or("long token length is required")?;
 
-    builder = match digest_name {
-        "sha256" => builder.digest_sha256(),
-        _ => panic!("unsupported digest type"),
+    let options = KeyOptions {
+        prefix: prefix.to_owned(),
+        short_length: *short_length,
+        short_prefix: short_prefix.cloned(),
+        long_length: *long_length,
     };
 
-    let controller = builder.finalize().expect("failed to create pak controller");
+    match rng_name.as_str() {
+        "osrng" => generate_with_rng(OsRng, digest_name, options),
+        "threadrng" => generate_with_rng(ThreadRng::default(), digest_name, options),
+        "stdrng" => generate_with_rng(StdRng::from_entropy(), digest_name, options),
+        other => Err(format!("unsupported rng type: {}", other)),
+    }
+}
+
+fn generate_with_rng<R: RngCore + Clone>(
+    rng: R,
+    digest_name: &str,
+    options: KeyOptions,
+) -> Result<(), String> {
+    match digest_name {
+        "sha224" => generate_key::<R, Sha224>(rng, options),
+        "sha256" => generate_key::<R, Sha256>(rng, options),
+        "sha384" => generate_key::<R, Sha384>(rng, options),
+        "sha512" => generate_key::<R, Sha512>(rng, options),
+        "sha512_224" => generate_key::<R, Sha512_224>(rng, options),
+        "sha512_256" => generate_key::<R, Sha512_256>(rng, options),
+        other => Err(format!("unsupported digest type: {}", other)),
+    }
+}
+
+fn generate_key<R: RngCore + Clone, D: Digest + FixedOutputReset>(
+    rng: R,
+    options: KeyOptions,
+) -> Result<(), String> {
+    let controller = PrefixedApiKeyController::<R, D>::configure()
+        .prefix(options.prefix)
+        .rng(rng)
+        .short_token_length(options.short_length)
+        .short_token_prefix(options.short_prefix)
+        .long_token_length(options.long_length)
+        .finalize()
+        .map_err(|err| format!("failed to create pak controller: {}", err))?;
 
-    let (pak, hash) = controller.generate_key_and_hash();
-    println!("PAK:\t{}\nHash:\t{}", pak.to_string(), hash);
+    let (pak, record) = controller
+        .try_generate_key_and_record()
+        .map_err(|err| format!("failed to generate key: {}", err))?;
+    println!("PAK:\t{}\nHash:\t{}", pak.to_string(), record.hash);
+    Ok(())
 }
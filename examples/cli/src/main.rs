@@ -1,49 +1,52 @@
-use clap::{arg, Arg, Command};
+use clap::builder::PossibleValuesParser;
+use clap::{arg, value_parser, Arg, Command};
+use std::process;
 
 mod command_check;
 mod command_generate;
 mod config;
+mod options;
 
 use command_check::check;
 use command_generate::generate;
 use config::{load_config, Config};
+use options::{
+    DEFAULT_DIGEST, DEFAULT_LONG_TOKEN_LENGTH, DEFAULT_RNG, DEFAULT_SHORT_TOKEN_LENGTH, DIGESTS,
+    RNGS,
+};
 
-fn cli(config: &mut Config) -> Command {
+fn cli(config: &mut Config) -> Command<'_> {
     // rng config
-    let mut rng_arg = Arg::new("rng")
+    let rng_arg = Arg::new("rng")
         .short('r')
         .long("rng")
         .takes_value(true)
-        .help("The rng source [Options: osrng]");
-
-    if config.rng.is_some() {
-        let rng_default = config.rng.as_ref().unwrap();
-        rng_arg = rng_arg.default_value(rng_default)
-    }
+        .value_parser(PossibleValuesParser::new(RNGS))
+        .help("The rng source")
+        .default_value(config.rng.as_deref().unwrap_or(DEFAULT_RNG));
 
     // digest config
-    let mut digest_arg = Arg::new("digest")
+    let digest_arg = Arg::new("digest")
         .short('d')
         .long("digest")
         .takes_value(true)
-        .help("The hashing digest [Options: sha256]");
-
-    if config.digest.is_some() {
-        let digest_default = config.digest.as_ref().unwrap();
-        digest_arg = digest_arg.default_value(digest_default)
-    }
+        .value_parser(PossibleValuesParser::new(DIGESTS))
+        .help("The hashing digest")
+        .default_value(config.digest.as_deref().unwrap_or(DEFAULT_DIGEST));
 
     // short token length config
-    let mut short_length_arg = Arg::new("short-token-length")
+    let short_length_arg = Arg::new("short-token-length")
         .short('s')
         .long("short-length")
         .takes_value(true)
-        .help("Length of the short token");
-
-    if config.short_token_length.is_some() {
-        let short_length_default = config.short_token_length_str.as_ref().unwrap();
-        short_length_arg = short_length_arg.default_value(short_length_default)
-    }
+        .value_parser(value_parser!(usize))
+        .help("Length of the short token")
+        .default_value(
+            config
+                .short_token_length_str
+                .as_deref()
+                .unwrap_or(DEFAULT_SHORT_TOKEN_LENGTH),
+        );
 
     // short token prefix config
     let mut short_prefix_arg = Arg::new("short-token-prefix")
@@ -52,22 +55,23 @@ fn cli(config: &mut Config) -> Command {
         .takes_value(true)
         .help("Prefix string for the short token");
 
-    if config.short_token_prefix.is_some() {
-        let short_prefix_default = config.short_token_prefix.as_ref().unwrap();
+    if let Some(short_prefix_default) = &config.short_token_prefix {
         short_prefix_arg = short_prefix_arg.default_value(short_prefix_default)
     }
 
     // long token length config
-    let mut long_length_arg = Arg::new("long-token-length")
+    let long_length_arg = Arg::new("long-token-length")
         .short('l')
         .long("long-length")
         .takes_value(true)
-        .help("Length of the long token");
-
-    if config.long_token_length.is_some() {
-        let long_length_default = config.long_token_length_str.as_ref().unwrap();
-        long_length_arg = long_length_arg.default_value(long_length_default)
-    }
+        .value_parser(value_parser!(usize))
+        .help("Length of the long token")
+        .default_value(
+            config
+                .long_token_length_str
+                .as_deref()
+                .unwrap_or(DEFAULT_LONG_TOKEN_LENGTH),
+        );
 
     Command::new("pakcli")
         .about("An example utility for creating/validating Prefixed API Keys")
@@ -108,9 +112,15 @@ fn main() {
 
     let matches = cli(&mut config).get_matches();
 
-    match matches.subcommand() {
+    let result = match matches.subcommand() {
         Some(("check", sub_matches)) => check(sub_matches),
         Some(("generate", sub_matches)) => generate(sub_matches),
-        _ => unreachable!(),
+        Some((name, _)) => Err(format!("unknown command: {}", name)),
+        None => unreachable!(),
+    };
+
+    if let Err(err) = result {
+        eprintln!("error: {}", err);
+        process::exit(1);
     }
 }
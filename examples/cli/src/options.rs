@@ -0,0 +1,21 @@
+/// The digests the cli can hash long tokens with
+pub const DIGESTS: [&str; 6] = [
+    "sha224",
+    "sha256",
+    "sha384",
+    "sha512",
+    "sha512_224",
+    "sha512_256",
+];
+
+/// The rng sources the cli can generate keys with
+pub const RNGS: [&str; 3] = ["osrng", "threadrng", "stdrng"];
+
+/// The rng and digest used when neither the config file nor the flags pick one
+pub const DEFAULT_RNG: &str = "osrng";
+pub const DEFAULT_DIGEST: &str = "sha256";
+
+/// The token lengths used when neither the config file nor the flags set them,
+/// matching the library's `default_lengths`
+pub const DEFAULT_SHORT_TOKEN_LENGTH: &str = "8";
+pub const DEFAULT_LONG_TOKEN_LENGTH: &str = "24";
@@ -0,0 +1,324 @@
+use std::future::{ready, Future, Ready};
+use std::pin::Pin;
+use std::sync::Arc;
+
+use actix_web::body::EitherBody;
+use actix_web::dev::{forward_ready, Payload, Service, ServiceRequest, ServiceResponse, Transform};
+use actix_web::http::header::{self, HeaderValue};
+use actix_web::http::StatusCode;
+use actix_web::{Error, FromRequest, HttpMessage, HttpRequest, HttpResponse, ResponseError};
+
+use crate::authenticator::{
+    authenticate_request, www_authenticate, AuthRejection, KeyAuthenticator, SharedAuthenticator,
+};
+use crate::extract::{CredentialExtractor, CredentialSource};
+use crate::store::ApiKeyRecord;
+
+/// An [actix-web](https://docs.rs/actix-web) middleware which authenticates every
+/// request's api key before passing it on to the wrapped service. It's the actix
+/// equivalent of [PakAuthLayer](crate::PakAuthLayer).
+///
+/// By default the key is read from the `Authorization: Bearer <key>` header. On success
+/// the authenticated [ApiKeyRecord] is inserted into the request's extensions, and
+/// failures are rejected with an [AuthRejection] response.
+///
+/// The middleware is also what the [ActixPakAuth] extractor uses to authenticate
+/// requests, so register it with `App::app_data` when using the extractor without
+/// wrapping the app in the middleware.
+///
+/// Like the other integrations, it authenticates on the worker's thread, so only use
+/// stores and blocklists which don't block (see [KeyAuthenticator]).
+///
+/// Requires the "actix" feature, which needs Rust 1.88 or newer
+#[derive(Clone)]
+pub struct ActixPakAuthMiddleware {
+    authenticator: SharedAuthenticator,
+    extractor: CredentialExtractor,
+}
+
+impl ActixPakAuthMiddleware {
+    pub fn new<A: KeyAuthenticator + Send + Sync + 'static>(
+        authenticator: A,
+    ) -> ActixPakAuthMiddleware {
+        ActixPakAuthMiddleware::from_arc(Arc::new(authenticator))
+    }
+
+    /// Creates the middleware from an authenticator which is shared with other parts
+    /// of the application.
+    pub fn from_arc(authenticator: SharedAuthenticator) -> ActixPakAuthMiddleware {
+        ActixPakAuthMiddleware {
+            authenticator,
+            extractor: CredentialExtractor::bearer(),
+        }
+    }
+
+    /// Reads the key from the entire value of the given header, like `X-API-Key`,
+    /// instead of the `Authorization: Bearer` header.
+    pub fn header(mut self, name: String) -> Self {
+        self.extractor = CredentialExtractor::new(vec![CredentialSource::Header(name)]);
+        self
+    }
+
+    /// Reads the key from the extractor's sources, like cookies or the query string,
+    /// instead of the `Authorization: Bearer` header.
+    pub fn extractor(mut self, extractor: CredentialExtractor) -> Self {
+        self.extractor = extractor;
+        self
+    }
+
+    fn authenticate(&self, request: &HttpRequest) -> Result<ApiKeyRecord, AuthRejection> {
+        authenticate_request(&*self.authenticator, &self.extractor, request)
+    }
+}
+
+impl<S, B> Transform<S, ServiceRequest> for ActixPakAuthMiddleware
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Transform = ActixPakAuthService<S>;
+    type InitError = ();
+    type Future = Ready<Result<Self::Transform, Self::InitError>>;
+
+    fn new_transform(&self, service: S) -> Self::Future {
+        ready(Ok(ActixPakAuthService {
+            service,
+            middleware: self.clone(),
+        }))
+    }
+}
+
+/// The service created by [ActixPakAuthMiddleware].
+pub struct ActixPakAuthService<S> {
+    service: S,
+    middleware: ActixPakAuthMiddleware,
+}
+
+impl<S, B> Service<ServiceRequest> for ActixPakAuthService<S>
+where
+    S: Service<ServiceRequest, Response = ServiceResponse<B>, Error = Error>,
+    S::Future: 'static,
+    B: 'static,
+{
+    type Response = ServiceResponse<EitherBody<B>>;
+    type Error = Error;
+    type Future = Pin<Box<dyn Future<Output = Result<Self::Response, Self::Error>>>>;
+
+    forward_ready!(service);
+
+    fn call(&self, request: ServiceRequest) -> Self::Future {
+        match self.middleware.authenticate(request.request()) {
+            Ok(record) => {
+                request.extensions_mut().insert(record);
+                let response = self.service.call(request);
+                Box::pin(async move { Ok(response.await?.map_into_left_body()) })
+            }
+            Err(rejection) => {
+                let response = request
+                    .into_response(rejection.error_response())
+                    .map_into_right_body();
+                Box::pin(async move { Ok(response) })
+            }
+        }
+    }
+}
+
+/// An actix-web extractor which authenticates the request's api key, giving handlers
+/// the authenticated record converted into `T`. It's the actix equivalent of
+/// [PakAuth](crate::PakAuth).
+///
+/// If an [ActixPakAuthMiddleware] already authenticated the request, its record is
+/// reused. Otherwise the key is authenticated using the middleware registered as app
+/// data. Requests which fail authentication are rejected with an [AuthRejection].
+///
+/// If the middleware neither wraps the app nor is registered as app data, the
+/// misconfiguration is logged and the request is rejected with
+/// [Internal](AuthRejection::Internal), so callers only see a generic error.
+///
+/// Requires the "actix" feature, which needs Rust 1.88 or newer
+#[derive(Debug, Clone)]
+pub struct ActixPakAuth<T = ApiKeyRecord>(pub T);
+
+impl<T: From<ApiKeyRecord>> FromRequest for ActixPakAuth<T> {
+    type Error = AuthRejection;
+    type Future = Ready<Result<Self, Self::Error>>;
+
+    fn from_request(request: &HttpRequest, _: &mut Payload) -> Self::Future {
+        if let Some(record) = request.extensions().get::<ApiKeyRecord>() {
+            return ready(Ok(ActixPakAuth(record.clone().into())));
+        }
+
+        let result = match request.app_data::<ActixPakAuthMiddleware>() {
+            Some(middleware) => middleware.authenticate(request),
+            None => {
+                log::error!(
+                    "ActixPakAuth requires ActixPakAuthMiddleware to wrap the app or be \
+                     registered with App::app_data"
+                );
+                Err(AuthRejection::Internal)
+            }
+        };
+        ready(result.map(|record| ActixPakAuth(record.into())))
+    }
+}
+
+impl ResponseError for AuthRejection {
+    fn status_code(&self) -> StatusCode {
+        if self.is_client_error() {
+            StatusCode::UNAUTHORIZED
+        } else {
+            StatusCode::INTERNAL_SERVER_ERROR
+        }
+    }
+
+    fn error_response(&self) -> HttpResponse {
+        let mut response = HttpResponse::build(self.status_code());
+        if self.is_client_error() {
+            if let Ok(value) = HeaderValue::from_str(&www_authenticate(None, *self)) {
+                response.insert_header((header::WWW_AUTHENTICATE, value));
+            }
+        }
+
+        response
+            .content_type("application/json")
+            .body(self.json_body())
+    }
+}
+
+#[cfg(test)]
+mod actix_auth_tests {
+    use std::sync::Arc;
+
+    use actix_web::http::header::{AUTHORIZATION, WWW_AUTHENTICATE};
+    use actix_web::http::StatusCode;
+    use actix_web::{test, web, App, HttpMessage, HttpRequest};
+    use rand::rngs::OsRng;
+    use sha2::Sha256;
+
+    use super::{ActixPakAuth, ActixPakAuthMiddleware, AuthRejection};
+    use crate::{
+        ApiKeyRecord, Authenticator, Blocklist, KeyStore, MemoryBlocklist, MemoryKeyStore,
+        PrefixedApiKey, PrefixedApiKeyController,
+    };
+
+    struct Owner(String);
+
+    impl From<ApiKeyRecord> for Owner {
+        fn from(record: ApiKeyRecord) -> Self {
+            Owner(record.owner_id.unwrap_or_default())
+        }
+    }
+
+    async fn whoami(ActixPakAuth(owner): ActixPakAuth<Owner>) -> String {
+        owner.0
+    }
+
+    async fn from_extensions(request: HttpRequest) -> String {
+        request
+            .extensions()
+            .get::<ApiKeyRecord>()
+            .and_then(|record| record.owner_id.clone())
+            .unwrap_or_default()
+    }
+
+    fn setup() -> (ActixPakAuthMiddleware, PrefixedApiKey, Arc<MemoryBlocklist>) {
+        let controller =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        let (pak, record) = controller.generate_key_and_record();
+        let store = MemoryKeyStore::new();
+        store
+            .put(record.with_owner_id("owner-1".to_owned()))
+            .unwrap();
+        let blocklist = Arc::new(MemoryBlocklist::new());
+        let authenticator = Authenticator::new(controller, store, blocklist.clone());
+        (ActixPakAuthMiddleware::new(authenticator), pak, blocklist)
+    }
+
+    #[actix_web::test]
+    async fn extractor_uses_app_data() {
+        let (middleware, pak, blocklist) = setup();
+        let app = test::init_service(
+            App::new()
+                .app_data(middleware)
+                .route("/whoami", web::get().to(whoami)),
+        )
+        .await;
+
+        let request = test::TestRequest::get()
+            .uri("/whoami")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", pak.to_string())))
+            .to_request();
+        let body = test::call_and_read_body(&app, request).await;
+        assert_eq!(body, "owner-1");
+
+        blocklist
+            .revoke(pak.short_token(), "leaked", "alice")
+            .unwrap();
+        let request = test::TestRequest::get()
+            .uri("/whoami")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", pak.to_string())))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        let body = test::read_body(response).await;
+        assert_eq!(
+            body,
+            r#"{"error":"revoked_api_key","message":"the api key has been revoked"}"#
+        );
+    }
+
+    #[actix_web::test]
+    async fn middleware_inserts_record() {
+        let (middleware, pak, _) = setup();
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware.header("x-api-key".to_owned()))
+                .route("/whoami", web::get().to(from_extensions))
+                .route("/extract", web::get().to(whoami)),
+        )
+        .await;
+
+        for uri in ["/whoami", "/extract"] {
+            let request = test::TestRequest::get()
+                .uri(uri)
+                .insert_header(("x-api-key", pak.to_string()))
+                .to_request();
+            let body = test::call_and_read_body(&app, request).await;
+            assert_eq!(body, "owner-1");
+        }
+    }
+
+    #[actix_web::test]
+    async fn middleware_rejects_missing_key() {
+        let (middleware, _, _) = setup();
+        let app = test::init_service(
+            App::new()
+                .wrap(middleware)
+                .route("/whoami", web::get().to(from_extensions)),
+        )
+        .await;
+
+        let request = test::TestRequest::get().uri("/whoami").to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::UNAUTHORIZED);
+        assert_eq!(response.headers().get(WWW_AUTHENTICATE).unwrap(), "Bearer");
+    }
+
+    #[actix_web::test]
+    async fn extractor_hides_missing_configuration() {
+        let (_, pak, _) = setup();
+        let app = test::init_service(App::new().route("/whoami", web::get().to(whoami))).await;
+
+        let request = test::TestRequest::get()
+            .uri("/whoami")
+            .insert_header((AUTHORIZATION, format!("Bearer {}", pak.to_string())))
+            .to_request();
+        let response = test::call_service(&app, request).await;
+        assert_eq!(response.status(), StatusCode::INTERNAL_SERVER_ERROR);
+        let body = test::read_body(response).await;
+        assert_eq!(body, AuthRejection::Internal.json_body());
+    }
+}
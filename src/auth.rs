@@ -0,0 +1,103 @@
+use std::error::Error;
+use std::fmt;
+
+use crate::blocklist::Revocation;
+use crate::store::ApiKeyRecord;
+
+/// The result of authenticating a [PrefixedApiKey](crate::PrefixedApiKey) against a
+/// [KeyStore](crate::KeyStore) via [authenticate](crate::PrefixedApiKeyController::authenticate).
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum AuthOutcome {
+    /// The key matched its stored record, which is returned
+    Authenticated(ApiKeyRecord),
+    /// The key matched its stored record, but it has been rotated and will stop
+    /// working once its grace period ends. The record's `superseded_by` and
+    /// `expires_at` fields say which key replaced it and when.
+    Superseded(ApiKeyRecord),
+    /// The key's short token has been revoked or blocklisted
+    Revoked(Revocation),
+    /// No record exists for the key's short token, or the key has the wrong prefix
+    UnknownKey,
+    /// The key's record has passed its expiry
+    Expired,
+    /// A record exists for the key's short token, but the long token doesn't match its hash
+    InvalidSecret,
+}
+
+impl AuthOutcome {
+    /// Whether the key was successfully authenticated. This includes superseded keys
+    /// which are still within their grace period.
+    pub fn is_authenticated(&self) -> bool {
+        self.record().is_some()
+    }
+
+    /// Whether a superseded key was used, in which case its owner should be warned
+    /// to switch to the replacement key
+    pub fn is_superseded(&self) -> bool {
+        matches!(self, AuthOutcome::Superseded(_))
+    }
+
+    /// Gets the authenticated record, if authentication succeeded
+    pub fn record(&self) -> Option<&ApiKeyRecord> {
+        match self {
+            AuthOutcome::Authenticated(record) | AuthOutcome::Superseded(record) => Some(record),
+            _ => None,
+        }
+    }
+
+    /// Takes the authenticated record, if authentication succeeded
+    pub fn into_record(self) -> Option<ApiKeyRecord> {
+        match self {
+            AuthOutcome::Authenticated(record) | AuthOutcome::Superseded(record) => Some(record),
+            _ => None,
+        }
+    }
+}
+
+/// Errors raised by the store or blocklist while authenticating a key.
+#[derive(Debug)]
+pub enum AuthError<S, B> {
+    Store(S),
+    Blocklist(B),
+}
+
+impl<S: fmt::Display, B: fmt::Display> fmt::Display for AuthError<S, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthError::Store(err) => write!(f, "key store error: {}", err),
+            AuthError::Blocklist(err) => write!(f, "blocklist error: {}", err),
+        }
+    }
+}
+
+impl<S: fmt::Debug + fmt::Display, B: fmt::Debug + fmt::Display> Error for AuthError<S, B> {}
+
+/// Errors raised while rotating a key via [rotate_key](crate::PrefixedApiKeyController::rotate_key).
+#[derive(Debug)]
+pub enum RotationError<S> {
+    Store(S),
+    Rng(rand::Error),
+    /// No record exists for the short token being rotated
+    UnknownKey,
+    /// The key being rotated has been revoked, so it must not be replaced
+    Revoked,
+    /// The key being rotated has already expired
+    Expired,
+    /// The key being rotated has already been replaced by another key
+    Superseded,
+}
+
+impl<S: fmt::Display> fmt::Display for RotationError<S> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RotationError::Store(err) => write!(f, "key store error: {}", err),
+            RotationError::Rng(err) => write!(f, "rng error: {}", err),
+            RotationError::UnknownKey => write!(f, "no key exists with the given short token"),
+            RotationError::Revoked => write!(f, "revoked keys cannot be rotated"),
+            RotationError::Expired => write!(f, "expired keys cannot be rotated"),
+            RotationError::Superseded => write!(f, "key has already been rotated"),
+        }
+    }
+}
+
+impl<S: fmt::Debug + fmt::Display> Error for RotationError<S> {}
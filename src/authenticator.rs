@@ -0,0 +1,224 @@
+use std::error::Error;
+use std::fmt;
+
+use digest::{Digest, FixedOutputReset};
+use rand::RngCore;
+
+use crate::auth::AuthOutcome;
+use crate::blocklist::Blocklist;
+use crate::controller::PrefixedApiKeyController;
+#[cfg(any(feature = "tower", feature = "actix", feature = "tonic"))]
+use crate::extract::{CredentialExtractor, CredentialRequest, ExtractError};
+use crate::prefixed_api_key::{PrefixedApiKey, PrefixedApiKeyError};
+use crate::store::{ApiKeyRecord, KeyStore};
+
+/// Why a key provided by a client was rejected. This is what the web framework
+/// integrations report to clients, so it deliberately carries no details about the
+/// key or any internal errors.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AuthRejection {
+    /// No key was provided
+    MissingKey,
+    /// The provided key couldn't be parsed
+    MalformedKey,
+    /// No key exists with the provided key's short token, or its secret didn't match
+    UnknownKey,
+    /// The key has been revoked
+    RevokedKey,
+    /// The key has expired
+    ExpiredKey,
+    /// The key store or blocklist failed
+    Internal,
+}
+
+impl AuthRejection {
+    /// A short, machine readable code for the rejection
+    pub fn code(&self) -> &'static str {
+        match self {
+            AuthRejection::MissingKey => "missing_api_key",
+            AuthRejection::MalformedKey => "malformed_api_key",
+            AuthRejection::UnknownKey => "unknown_api_key",
+            AuthRejection::RevokedKey => "revoked_api_key",
+            AuthRejection::ExpiredKey => "expired_api_key",
+            AuthRejection::Internal => "internal_error",
+        }
+    }
+
+    /// Whether the rejection was the client's fault, rather than an internal error
+    pub fn is_client_error(&self) -> bool {
+        !matches!(self, AuthRejection::Internal)
+    }
+
+    /// The JSON body the axum and actix integrations respond with
+    #[cfg(any(feature = "axum", feature = "actix"))]
+    pub(crate) fn json_body(&self) -> String {
+        // Codes and messages are fixed strings, so they never need escaping
+        format!("{{\"error\":\"{}\",\"message\":\"{}\"}}", self.code(), self)
+    }
+}
+
+impl fmt::Display for AuthRejection {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            AuthRejection::MissingKey => write!(f, "no api key was provided"),
+            AuthRejection::MalformedKey => write!(f, "the api key is malformed"),
+            AuthRejection::UnknownKey => write!(f, "the api key is not valid"),
+            AuthRejection::RevokedKey => write!(f, "the api key has been revoked"),
+            AuthRejection::ExpiredKey => write!(f, "the api key has expired"),
+            AuthRejection::Internal => write!(f, "the api key could not be verified"),
+        }
+    }
+}
+
+impl Error for AuthRejection {}
+
+/// An object safe interface for parsing and authenticating keys, which lets the web
+/// framework integrations hold an authenticator without being generic over the
+/// controller's rng and digest, or the store and blocklist types.
+///
+/// Authentication is synchronous, and the integrations call it directly on the async
+/// executor's thread. That suits in-memory stores and blocklists, or ones backed by a
+/// local cache, but a store which waits on the network or disk blocks the executor for
+/// every request. Keep such lookups out of the request path, for example by caching
+/// records in memory and refreshing them in the background.
+pub trait KeyAuthenticator {
+    /// Parses the key string provided by the client
+    fn parse_key(&self, key: &str) -> Result<PrefixedApiKey, PrefixedApiKeyError> {
+        PrefixedApiKey::from_string(key)
+    }
+
+    /// Authenticates the parsed key
+    fn authenticate_key(
+        &self,
+        pak: &PrefixedApiKey,
+    ) -> Result<AuthOutcome, Box<dyn Error + Send + Sync>>;
+
+    /// Parses and authenticates the key string provided by the client, returning the
+    /// authenticated record or why the key was rejected.
+    fn authenticate_str(&self, key: Option<&str>) -> Result<ApiKeyRecord, AuthRejection> {
+        let key = key.ok_or(AuthRejection::MissingKey)?;
+        let pak = self
+            .parse_key(key)
+            .map_err(|_| AuthRejection::MalformedKey)?;
+        let outcome = self
+            .authenticate_key(&pak)
+            .map_err(|_| AuthRejection::Internal)?;
+
+        match outcome {
+            AuthOutcome::Authenticated(record) | AuthOutcome::Superseded(record) => Ok(record),
+            AuthOutcome::Revoked(_) => Err(AuthRejection::RevokedKey),
+            AuthOutcome::Expired => Err(AuthRejection::ExpiredKey),
+            AuthOutcome::UnknownKey | AuthOutcome::InvalidSecret => Err(AuthRejection::UnknownKey),
+        }
+    }
+}
+
+/// The authenticator held by the web framework integrations
+#[cfg(any(feature = "tower", feature = "actix", feature = "tonic"))]
+pub(crate) type SharedAuthenticator = std::sync::Arc<dyn KeyAuthenticator + Send + Sync>;
+
+/// Extracts and authenticates the request's key for the web framework integrations.
+/// Credentials which can't be used, like conflicting keys, are rejected as malformed.
+#[cfg(any(feature = "tower", feature = "actix", feature = "tonic"))]
+pub(crate) fn authenticate_request<A, Q>(
+    authenticator: &A,
+    extractor: &CredentialExtractor,
+    request: &Q,
+) -> Result<ApiKeyRecord, AuthRejection>
+where
+    A: KeyAuthenticator + ?Sized,
+    Q: CredentialRequest,
+{
+    match extractor.extract_str(request) {
+        Ok(key) => authenticator.authenticate_str(Some(&key)),
+        Err(ExtractError::MissingCredentials) => authenticator.authenticate_str(None),
+        Err(_) => Err(AuthRejection::MalformedKey),
+    }
+}
+
+/// Builds the `WWW-Authenticate` challenge for a rejection, as described by
+/// [RFC 6750](https://datatracker.ietf.org/doc/html/rfc6750#section-3).
+#[cfg(any(feature = "tower", feature = "actix"))]
+pub(crate) fn www_authenticate(realm: Option<&str>, rejection: AuthRejection) -> String {
+    let mut params = Vec::new();
+    if let Some(realm) = realm {
+        params.push(format!("realm=\"{}\"", realm.replace('"', "")));
+    }
+    if rejection != AuthRejection::MissingKey {
+        params.push("error=\"invalid_token\"".to_owned());
+    }
+
+    if params.is_empty() {
+        "Bearer".to_owned()
+    } else {
+        format!("Bearer {}", params.join(", "))
+    }
+}
+
+/// Bundles a [PrefixedApiKeyController] with the [KeyStore] and [Blocklist] it
+/// authenticates keys against.
+#[derive(Debug)]
+pub struct Authenticator<R, D, S, B>
+where
+    R: RngCore + Clone,
+    D: Digest + FixedOutputReset,
+{
+    controller: PrefixedApiKeyController<R, D>,
+    store: S,
+    blocklist: B,
+}
+
+impl<R, D, S, B> Authenticator<R, D, S, B>
+where
+    R: RngCore + Clone,
+    D: Digest + FixedOutputReset,
+    S: KeyStore,
+    B: Blocklist,
+{
+    pub fn new(
+        controller: PrefixedApiKeyController<R, D>,
+        store: S,
+        blocklist: B,
+    ) -> Authenticator<R, D, S, B> {
+        Authenticator {
+            controller,
+            store,
+            blocklist,
+        }
+    }
+
+    pub fn controller(&self) -> &PrefixedApiKeyController<R, D> {
+        &self.controller
+    }
+
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+
+    pub fn blocklist(&self) -> &B {
+        &self.blocklist
+    }
+}
+
+impl<R, D, S, B> KeyAuthenticator for Authenticator<R, D, S, B>
+where
+    R: RngCore + Clone,
+    D: Digest + FixedOutputReset,
+    S: KeyStore,
+    S::Error: Error + Send + Sync + 'static,
+    B: Blocklist,
+    B::Error: Error + Send + Sync + 'static,
+{
+    fn parse_key(&self, key: &str) -> Result<PrefixedApiKey, PrefixedApiKeyError> {
+        self.controller.parse_key(key)
+    }
+
+    fn authenticate_key(
+        &self,
+        pak: &PrefixedApiKey,
+    ) -> Result<AuthOutcome, Box<dyn Error + Send + Sync>> {
+        Ok(self
+            .controller
+            .authenticate(pak, &self.store, &self.blocklist)?)
+    }
+}
@@ -0,0 +1,233 @@
+use std::sync::Arc;
+
+use axum_core::body::Body;
+use axum_core::extract::{FromRef, FromRequestParts};
+use axum_core::response::{IntoResponse, Response};
+use http::header::{CONTENT_TYPE, WWW_AUTHENTICATE};
+use http::request::Parts;
+use http::{HeaderName, HeaderValue, StatusCode};
+
+use crate::authenticator::{
+    authenticate_request, www_authenticate, AuthRejection, KeyAuthenticator, SharedAuthenticator,
+};
+use crate::extract::{CredentialExtractor, CredentialSource};
+use crate::store::ApiKeyRecord;
+
+/// The authenticator used by the [PakAuth] extractor. Add this to your router's state
+/// and implement [FromRef] for it (or use it as the state directly).
+///
+/// Requires the "axum" feature, which needs Rust 1.78 or newer
+#[derive(Clone)]
+pub struct PakAuthState {
+    authenticator: SharedAuthenticator,
+    extractor: CredentialExtractor,
+}
+
+impl PakAuthState {
+    pub fn new<A: KeyAuthenticator + Send + Sync + 'static>(authenticator: A) -> PakAuthState {
+        PakAuthState::from_arc(Arc::new(authenticator))
+    }
+
+    /// Creates the state from an authenticator which is shared with other parts of
+    /// the application.
+    pub fn from_arc(authenticator: SharedAuthenticator) -> PakAuthState {
+        PakAuthState {
+            authenticator,
+            extractor: CredentialExtractor::bearer(),
+        }
+    }
+
+    /// Reads the key from the entire value of the given header, like `X-API-Key`,
+    /// instead of the `Authorization: Bearer` header.
+    pub fn header(mut self, name: HeaderName) -> Self {
+        self.extractor =
+            CredentialExtractor::new(vec![CredentialSource::Header(name.as_str().to_owned())]);
+        self
+    }
+
+    /// Reads the key from the extractor's sources, like cookies or the query string,
+    /// instead of the `Authorization: Bearer` header.
+    pub fn extractor(mut self, extractor: CredentialExtractor) -> Self {
+        self.extractor = extractor;
+        self
+    }
+}
+
+/// An axum extractor which authenticates the request's api key, giving handlers the
+/// authenticated record converted into `T`:
+///
+/// ```ignore
+/// async fn handler(PakAuth(record): PakAuth<MyRecord>) { ... }
+/// ```
+///
+/// If a [PakAuthLayer](crate::PakAuthLayer) already authenticated the request, its
+/// record is reused. Otherwise the key is authenticated using the [PakAuthState]
+/// taken from the router's state. Requests which fail authentication are rejected
+/// with an [AuthRejection], which responds with a JSON body describing the failure.
+///
+/// The lookup happens inline in the handler's future, so the authenticator's store and
+/// blocklist shouldn't block (see [KeyAuthenticator]).
+///
+/// Requires the "axum" feature, which needs Rust 1.78 or newer
+#[derive(Debug, Clone)]
+pub struct PakAuth<T = ApiKeyRecord>(pub T);
+
+impl<S, T> FromRequestParts<S> for PakAuth<T>
+where
+    PakAuthState: FromRef<S>,
+    S: Send + Sync,
+    T: From<ApiKeyRecord>,
+{
+    type Rejection = AuthRejection;
+
+    async fn from_request_parts(parts: &mut Parts, state: &S) -> Result<Self, Self::Rejection> {
+        if let Some(record) = parts.extensions.get::<ApiKeyRecord>() {
+            return Ok(PakAuth(record.clone().into()));
+        }
+
+        let state = PakAuthState::from_ref(state);
+        let record = authenticate_request(&*state.authenticator, &state.extractor, parts)?;
+        Ok(PakAuth(record.into()))
+    }
+}
+
+impl IntoResponse for AuthRejection {
+    fn into_response(self) -> Response {
+        let mut response = Response::new(Body::from(self.json_body()));
+        response
+            .headers_mut()
+            .insert(CONTENT_TYPE, HeaderValue::from_static("application/json"));
+
+        if !self.is_client_error() {
+            *response.status_mut() = StatusCode::INTERNAL_SERVER_ERROR;
+            return response;
+        }
+
+        *response.status_mut() = StatusCode::UNAUTHORIZED;
+        if let Ok(value) = HeaderValue::from_str(&www_authenticate(None, self)) {
+            response.headers_mut().insert(WWW_AUTHENTICATE, value);
+        }
+        response
+    }
+}
+
+#[cfg(test)]
+mod axum_auth_tests {
+    use std::sync::Arc;
+
+    use ::tower::ServiceExt;
+    use axum::body::to_bytes;
+    use axum::routing::get;
+    use axum::Router;
+    use http::header::AUTHORIZATION;
+    use http::{Request, StatusCode};
+    use rand::rngs::OsRng;
+    use sha2::Sha256;
+
+    use super::{PakAuth, PakAuthState};
+    use crate::{
+        ApiKeyRecord, Authenticator, Blocklist, KeyStore, MemoryBlocklist, MemoryKeyStore,
+        PakAuthLayer, PrefixedApiKey, PrefixedApiKeyController,
+    };
+
+    struct Owner(String);
+
+    impl From<ApiKeyRecord> for Owner {
+        fn from(record: ApiKeyRecord) -> Self {
+            Owner(record.owner_id.unwrap_or_default())
+        }
+    }
+
+    async fn whoami(PakAuth(owner): PakAuth<Owner>) -> String {
+        owner.0
+    }
+
+    fn setup() -> (Router, PrefixedApiKey, Arc<MemoryBlocklist>) {
+        let controller =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        let (pak, record) = controller.generate_key_and_record();
+        let store = MemoryKeyStore::new();
+        store
+            .put(record.with_owner_id("owner-1".to_owned()))
+            .unwrap();
+        let blocklist = Arc::new(MemoryBlocklist::new());
+        let authenticator = Authenticator::new(controller, store, blocklist.clone());
+
+        let router = Router::new()
+            .route("/whoami", get(whoami))
+            .with_state(PakAuthState::new(authenticator));
+        (router, pak, blocklist)
+    }
+
+    async fn call(router: Router, key: Option<String>) -> (StatusCode, String) {
+        let mut request = Request::builder().uri("/whoami");
+        if let Some(key) = key {
+            request = request.header(AUTHORIZATION, format!("Bearer {}", key));
+        }
+        let response = router
+            .oneshot(request.body(axum::body::Body::empty()).unwrap())
+            .await
+            .unwrap();
+        let status = response.status();
+        let body = to_bytes(response.into_body(), 1024).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[tokio::test]
+    async fn extracts_typed_record() {
+        let (router, pak, _) = setup();
+        let (status, body) = call(router, Some(pak.to_string())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "owner-1");
+    }
+
+    #[tokio::test]
+    async fn rejections_have_json_bodies() {
+        let (router, pak, blocklist) = setup();
+
+        let (status, body) = call(router.clone(), None).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert_eq!(
+            body,
+            r#"{"error":"missing_api_key","message":"no api key was provided"}"#
+        );
+
+        let (_, body) = call(router.clone(), Some("malformed".to_owned())).await;
+        assert!(body.contains("malformed_api_key"));
+
+        let (_, body) = call(router.clone(), Some("mycompany_abc_def".to_owned())).await;
+        assert!(body.contains("unknown_api_key"));
+
+        blocklist
+            .revoke(pak.short_token(), "leaked", "alice")
+            .unwrap();
+        let (status, body) = call(router, Some(pak.to_string())).await;
+        assert_eq!(status, StatusCode::UNAUTHORIZED);
+        assert!(body.contains("revoked_api_key"));
+    }
+
+    #[tokio::test]
+    async fn reuses_record_from_layer() {
+        let controller =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        let (pak, record) = controller.generate_key_and_record();
+        let store = MemoryKeyStore::new();
+        store
+            .put(record.with_owner_id("owner-2".to_owned()))
+            .unwrap();
+        let authenticator = Arc::new(Authenticator::new(
+            controller,
+            store,
+            MemoryBlocklist::new(),
+        ));
+
+        let router = Router::new()
+            .route("/whoami", get(whoami))
+            .layer(PakAuthLayer::from_arc(authenticator.clone()))
+            .with_state(PakAuthState::from_arc(authenticator));
+
+        let (status, body) = call(router, Some(pak.to_string())).await;
+        assert_eq!(status, StatusCode::OK);
+        assert_eq!(body, "owner-2");
+    }
+}
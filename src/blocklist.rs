@@ -0,0 +1,405 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::fs::OpenOptions;
+use std::io::{self, BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use crate::store::KeyStore;
+
+/// Details about why, when and by whom a key was revoked. Keys blocklisted without
+/// any details (like those listed in a plain blocklist file) leave these unset.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct Revocation {
+    /// The short token of the revoked key
+    pub short_token: String,
+    /// A human readable reason for the revocation
+    pub reason: Option<String>,
+    /// An identifier for whoever revoked the key
+    pub revoked_by: Option<String>,
+    /// When the key was revoked
+    pub revoked_at: Option<SystemTime>,
+}
+
+impl Revocation {
+    /// Creates a revocation for the given short token, recorded as happening now.
+    pub fn new(short_token: String, reason: String, revoked_by: String) -> Revocation {
+        Revocation {
+            short_token,
+            reason: Some(reason),
+            revoked_by: Some(revoked_by),
+            revoked_at: Some(SystemTime::now()),
+        }
+    }
+
+    /// Creates a revocation for the given short token without any further details.
+    pub fn bare(short_token: String) -> Revocation {
+        Revocation {
+            short_token,
+            reason: None,
+            revoked_by: None,
+            revoked_at: None,
+        }
+    }
+}
+
+/// A list of short tokens which must no longer be accepted. The blocklist is
+/// consulted by [check](crate::PrefixedApiKeyController::check) and
+/// [authenticate](crate::PrefixedApiKeyController::authenticate) before any hashing
+/// is done.
+pub trait Blocklist {
+    type Error;
+
+    /// Gets the revocation for the given short token, or `Ok(None)` if it isn't blocklisted.
+    fn lookup(&self, short_token: &str) -> Result<Option<Revocation>, Self::Error>;
+
+    /// Adds the revocation to the blocklist, replacing any existing revocation for
+    /// the same short token.
+    fn insert(&self, revocation: Revocation) -> Result<(), Self::Error>;
+
+    /// Whether or not the short token is blocklisted
+    fn is_blocked(&self, short_token: &str) -> Result<bool, Self::Error> {
+        Ok(self.lookup(short_token)?.is_some())
+    }
+
+    /// Revokes the key with the given short token, recording the reason, who revoked
+    /// it, and the current time. The recorded revocation is returned.
+    fn revoke(
+        &self,
+        short_token: &str,
+        reason: &str,
+        revoked_by: &str,
+    ) -> Result<Revocation, Self::Error> {
+        let revocation = Revocation::new(
+            short_token.to_owned(),
+            reason.to_owned(),
+            revoked_by.to_owned(),
+        );
+        self.insert(revocation.clone())?;
+        Ok(revocation)
+    }
+
+    /// Revokes all of the given short tokens with the same reason, returning how many
+    /// were revoked.
+    fn import<I, T>(
+        &self,
+        short_tokens: I,
+        reason: &str,
+        revoked_by: &str,
+    ) -> Result<usize, Self::Error>
+    where
+        I: IntoIterator<Item = T>,
+        T: AsRef<str>,
+    {
+        let mut count = 0;
+        for short_token in short_tokens {
+            self.revoke(short_token.as_ref(), reason, revoked_by)?;
+            count += 1;
+        }
+        Ok(count)
+    }
+}
+
+impl<B: Blocklist + ?Sized> Blocklist for &B {
+    type Error = B::Error;
+
+    fn lookup(&self, short_token: &str) -> Result<Option<Revocation>, Self::Error> {
+        (**self).lookup(short_token)
+    }
+
+    fn insert(&self, revocation: Revocation) -> Result<(), Self::Error> {
+        (**self).insert(revocation)
+    }
+}
+
+impl<B: Blocklist + ?Sized> Blocklist for Arc<B> {
+    type Error = B::Error;
+
+    fn lookup(&self, short_token: &str) -> Result<Option<Revocation>, Self::Error> {
+        (**self).lookup(short_token)
+    }
+
+    fn insert(&self, revocation: Revocation) -> Result<(), Self::Error> {
+        (**self).insert(revocation)
+    }
+}
+
+/// A [Blocklist] held entirely in memory.
+#[derive(Debug, Default)]
+pub struct MemoryBlocklist {
+    revocations: RwLock<HashMap<String, Revocation>>,
+}
+
+impl MemoryBlocklist {
+    pub fn new() -> MemoryBlocklist {
+        MemoryBlocklist::default()
+    }
+
+    /// The number of blocklisted short tokens
+    pub fn len(&self) -> usize {
+        self.revocations.read().unwrap().len()
+    }
+
+    /// Whether the blocklist is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Blocklist for MemoryBlocklist {
+    type Error = Infallible;
+
+    fn lookup(&self, short_token: &str) -> Result<Option<Revocation>, Self::Error> {
+        Ok(self.revocations.read().unwrap().get(short_token).cloned())
+    }
+
+    fn insert(&self, revocation: Revocation) -> Result<(), Self::Error> {
+        self.revocations
+            .write()
+            .unwrap()
+            .insert(revocation.short_token.clone(), revocation);
+        Ok(())
+    }
+}
+
+/// A [Blocklist] backed by a newline separated list of short tokens on disk. The
+/// file is read once when opened, and new revocations are appended to it.
+///
+/// Each line holds a short token, optionally followed by the tab separated unix
+/// timestamp, revoker and reason written by [revoke](Blocklist::revoke). Blank
+/// lines and lines starting with `#` are ignored.
+#[derive(Debug)]
+pub struct FileBlocklist {
+    path: PathBuf,
+    revocations: RwLock<HashMap<String, Revocation>>,
+}
+
+impl FileBlocklist {
+    /// Opens the blocklist file at `path`, creating it if it doesn't exist yet.
+    pub fn open<P: AsRef<Path>>(path: P) -> io::Result<FileBlocklist> {
+        let blocklist = FileBlocklist {
+            path: path.as_ref().to_owned(),
+            revocations: RwLock::new(HashMap::new()),
+        };
+        blocklist.reload()?;
+        Ok(blocklist)
+    }
+
+    /// Re-reads the file, picking up any changes made to it by other processes.
+    pub fn reload(&self) -> io::Result<()> {
+        let file = OpenOptions::new()
+            .read(true)
+            .append(true)
+            .create(true)
+            .open(&self.path)?;
+
+        let mut revocations = HashMap::new();
+        for line in BufReader::new(file).lines() {
+            if let Some(revocation) = parse_line(&line?) {
+                revocations.insert(revocation.short_token.clone(), revocation);
+            }
+        }
+
+        *self.revocations.write().unwrap() = revocations;
+        Ok(())
+    }
+
+    /// The number of blocklisted short tokens
+    pub fn len(&self) -> usize {
+        self.revocations.read().unwrap().len()
+    }
+
+    /// Whether the blocklist is empty
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl Blocklist for FileBlocklist {
+    type Error = io::Error;
+
+    fn lookup(&self, short_token: &str) -> Result<Option<Revocation>, Self::Error> {
+        Ok(self.revocations.read().unwrap().get(short_token).cloned())
+    }
+
+    /// Fails with [InvalidInput](io::ErrorKind::InvalidInput) if the short token is
+    /// empty, starts with `#` or contains whitespace, since it couldn't be read back
+    /// from the file as the same token.
+    fn insert(&self, revocation: Revocation) -> Result<(), Self::Error> {
+        let short_token = &revocation.short_token;
+        if short_token.is_empty()
+            || short_token.starts_with('#')
+            || short_token
+                .chars()
+                .any(|c| c.is_whitespace() || c.is_control())
+        {
+            return Err(io::Error::new(
+                io::ErrorKind::InvalidInput,
+                format!(
+                    "short token {:?} can't be written to a blocklist file",
+                    short_token
+                ),
+            ));
+        }
+
+        let mut file = OpenOptions::new()
+            .append(true)
+            .create(true)
+            .open(&self.path)?;
+        writeln!(file, "{}", format_line(&revocation))?;
+        self.revocations
+            .write()
+            .unwrap()
+            .insert(revocation.short_token.clone(), revocation);
+        Ok(())
+    }
+}
+
+fn parse_line(line: &str) -> Option<Revocation> {
+    let line = line.trim_end_matches('\r');
+    if line.trim().is_empty() || line.starts_with('#') {
+        return None;
+    }
+
+    let mut fields = line.splitn(4, '\t');
+    let mut revocation = Revocation::bare(fields.next()?.trim().to_owned());
+    revocation.revoked_at = fields
+        .next()
+        .and_then(|v| v.parse::<u64>().ok())
+        .map(|secs| UNIX_EPOCH + Duration::from_secs(secs));
+    revocation.revoked_by = fields.next().filter(|v| !v.is_empty()).map(str::to_owned);
+    revocation.reason = fields.next().filter(|v| !v.is_empty()).map(str::to_owned);
+    Some(revocation)
+}
+
+fn format_line(revocation: &Revocation) -> String {
+    // Tabs and newlines would corrupt the file, so they're flattened to spaces
+    let clean = |v: &Option<String>| v.as_deref().unwrap_or("").replace(['\t', '\n', '\r'], " ");
+    let revoked_at = revocation
+        .revoked_at
+        .and_then(|t| t.duration_since(UNIX_EPOCH).ok())
+        .map(|d| d.as_secs().to_string())
+        .unwrap_or_default();
+    format!(
+        "{}\t{}\t{}\t{}",
+        revocation.short_token,
+        revoked_at,
+        clean(&revocation.revoked_by),
+        clean(&revocation.reason)
+    )
+}
+
+/// A [Blocklist] which records revocations on the [ApiKeyRecord](crate::ApiKeyRecord)s
+/// held in a [KeyStore]. Revoking a short token that has no record in the store
+/// does nothing, since there is no key to authenticate against anyway.
+#[derive(Debug)]
+pub struct StoreBlocklist<S: KeyStore> {
+    store: S,
+}
+
+impl<S: KeyStore> StoreBlocklist<S> {
+    pub fn new(store: S) -> StoreBlocklist<S> {
+        StoreBlocklist { store }
+    }
+
+    /// Gets a reference to the underlying store
+    pub fn store(&self) -> &S {
+        &self.store
+    }
+}
+
+impl<S: KeyStore> Blocklist for StoreBlocklist<S> {
+    type Error = S::Error;
+
+    fn lookup(&self, short_token: &str) -> Result<Option<Revocation>, Self::Error> {
+        Ok(self
+            .store
+            .get(short_token)?
+            .and_then(|record| record.revocation))
+    }
+
+    fn insert(&self, revocation: Revocation) -> Result<(), Self::Error> {
+        if let Some(mut record) = self.store.get(&revocation.short_token)? {
+            record.revocation = Some(revocation);
+            self.store.put(record)?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod blocklist_tests {
+    use std::time::{Duration, UNIX_EPOCH};
+
+    use super::{Blocklist, FileBlocklist, MemoryBlocklist, StoreBlocklist};
+    use crate::store::{ApiKeyRecord, KeyStore, MemoryKeyStore};
+
+    #[test]
+    fn memory_blocklist_revokes_with_reason() {
+        let blocklist = MemoryBlocklist::new();
+        assert!(!blocklist.is_blocked("abc").unwrap());
+
+        let revocation = blocklist.revoke("abc", "leaked", "alice").unwrap();
+        assert_eq!(revocation.reason.as_deref(), Some("leaked"));
+        assert_eq!(revocation.revoked_by.as_deref(), Some("alice"));
+        assert!(revocation.revoked_at.is_some());
+        assert_eq!(blocklist.lookup("abc").unwrap(), Some(revocation));
+    }
+
+    #[test]
+    fn memory_blocklist_bulk_import() {
+        let blocklist = MemoryBlocklist::new();
+        let count = blocklist
+            .import(vec!["a", "b", "c"], "breach", "ops")
+            .unwrap();
+        assert_eq!(count, 3);
+        assert_eq!(blocklist.len(), 3);
+        assert!(blocklist.is_blocked("b").unwrap());
+    }
+
+    #[test]
+    fn file_blocklist_persists_revocations() {
+        let path = std::env::temp_dir().join(format!(
+            "pak-blocklist-{}-{:?}",
+            std::process::id(),
+            std::thread::current().id()
+        ));
+        std::fs::write(&path, "# leaked in march\nplain\n\n").unwrap();
+
+        let blocklist = FileBlocklist::open(&path).unwrap();
+        assert!(blocklist.is_blocked("plain").unwrap());
+        assert_eq!(blocklist.lookup("plain").unwrap().unwrap().reason, None);
+
+        blocklist.revoke("abc", "leaked\tin logs", "bob").unwrap();
+        for injected in ["abc\nother", "abc\tother", "#abc", ""] {
+            let err = blocklist.revoke(injected, "leaked", "bob").unwrap_err();
+            assert_eq!(err.kind(), std::io::ErrorKind::InvalidInput);
+        }
+
+        let reopened = FileBlocklist::open(&path).unwrap();
+        let revocation = reopened.lookup("abc").unwrap().unwrap();
+        assert_eq!(reopened.len(), 2);
+        assert_eq!(revocation.reason.as_deref(), Some("leaked in logs"));
+        assert_eq!(revocation.revoked_by.as_deref(), Some("bob"));
+        assert!(revocation.revoked_at.unwrap() > UNIX_EPOCH + Duration::from_secs(1));
+
+        std::fs::remove_file(&path).unwrap();
+    }
+
+    #[test]
+    fn store_blocklist_marks_records_revoked() {
+        let store = MemoryKeyStore::new();
+        store
+            .put(ApiKeyRecord::new("abc".to_owned(), "hash".to_owned()))
+            .unwrap();
+
+        let blocklist = StoreBlocklist::new(&store);
+        blocklist.revoke("abc", "leaked", "alice").unwrap();
+        blocklist.revoke("missing", "leaked", "alice").unwrap();
+
+        assert!(store.get("abc").unwrap().unwrap().is_revoked());
+        assert!(blocklist.is_blocked("abc").unwrap());
+        assert!(!blocklist.is_blocked("missing").unwrap());
+    }
+}
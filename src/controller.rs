@@ -1,11 +1,27 @@
+use std::convert::Infallible;
 use std::marker::PhantomData;
+#[cfg(feature = "signed-keys")]
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
 
 use constant_time_eq::constant_time_eq;
 use digest::{Digest, FixedOutputReset};
 use rand::RngCore;
 
+use crate::auth::{AuthError, AuthOutcome, RotationError};
+use crate::blocklist::{Blocklist, Revocation};
 use crate::controller_builder::ControllerBuilder;
-use crate::prefixed_api_key::PrefixedApiKey;
+#[cfg(feature = "derivation")]
+use crate::derivation::DerivationKey;
+use crate::key_format::{parse_version_marker, KeyFormats};
+use crate::prefixed_api_key::{PrefixedApiKey, PrefixedApiKeyError};
+use crate::scan::KeyScanner;
+use crate::scope::ScopeSet;
+#[cfg(feature = "signed-keys")]
+use crate::signed_key::{sign_long_token, KeySigner};
+use crate::store::{ApiKeyRecord, KeyStore};
+use crate::token_encoding::{LengthUnit, TokenEncoding};
+use crate::typed_builder::TypedControllerBuilder;
 
 #[derive(Clone, Debug)]
 pub struct PrefixedApiKeyController<R: RngCore + Clone, D: Digest + FixedOutputReset> {
@@ -15,6 +31,15 @@ pub struct PrefixedApiKeyController<R: RngCore + Clone, D: Digest + FixedOutputR
     short_token_prefix: Option<String>,
     short_token_length: usize,
     long_token_length: usize,
+    pub(crate) environment: Option<String>,
+    pub(crate) formats: KeyFormats,
+    pub(crate) encoding: TokenEncoding,
+    pub(crate) length_unit: LengthUnit,
+    #[cfg(feature = "derivation")]
+    pub(crate) derivation: Option<DerivationKey>,
+    #[cfg(feature = "signed-keys")]
+    pub(crate) signer: Option<Arc<dyn KeySigner>>,
+    pub(crate) last_used_throttle: Option<Duration>,
 }
 
 impl<R: RngCore + Clone, D: Digest + FixedOutputReset> PrefixedApiKeyController<R, D> {
@@ -32,6 +57,15 @@ impl<R: RngCore + Clone, D: Digest + FixedOutputReset> PrefixedApiKeyController<
             short_token_prefix,
             short_token_length,
             long_token_length,
+            environment: None,
+            formats: KeyFormats::new(),
+            encoding: TokenEncoding::default(),
+            length_unit: LengthUnit::default(),
+            #[cfg(feature = "derivation")]
+            derivation: None,
+            #[cfg(feature = "signed-keys")]
+            signer: None,
+            last_used_throttle: None,
         }
     }
 
@@ -41,6 +75,180 @@ impl<R: RngCore + Clone, D: Digest + FixedOutputReset> PrefixedApiKeyController<
         ControllerBuilder::new()
     }
 
+    /// Creates an instance of [TypedControllerBuilder], which checks the required
+    /// fields are set at compile time rather than when finalizing
+    pub fn configure_typed() -> TypedControllerBuilder<R, D> {
+        TypedControllerBuilder::new()
+    }
+
+    /// Getter method for accessing the configured key prefix
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    /// Getter method for accessing the configured environment
+    pub fn environment(&self) -> Option<&str> {
+        self.environment.as_deref()
+    }
+
+    /// Getter method for accessing the key formats the controller accepts
+    pub fn key_formats(&self) -> &KeyFormats {
+        &self.formats
+    }
+
+    /// Getter method for accessing the encoding used for the short and long tokens
+    pub fn token_encoding(&self) -> TokenEncoding {
+        self.encoding
+    }
+
+    /// Getter method for accessing the configured short token prefix
+    pub fn short_token_prefix(&self) -> Option<&str> {
+        self.short_token_prefix.as_deref()
+    }
+
+    /// Getter method for accessing what the short and long token lengths count
+    pub fn length_unit(&self) -> LengthUnit {
+        self.length_unit
+    }
+
+    /// Getter method for accessing the length of the short token, in the configured
+    /// [LengthUnit]
+    pub fn short_token_length(&self) -> usize {
+        self.short_token_length
+    }
+
+    /// Getter method for accessing the length of the long token, in the configured
+    /// [LengthUnit]
+    pub fn long_token_length(&self) -> usize {
+        self.long_token_length
+    }
+
+    /// The bits of entropy in the short token. Characters replaced by the short token
+    /// prefix aren't random, so they don't count. With [LengthUnit::Bytes] and a
+    /// short token prefix this is an upper bound, since base58 and base62 characters
+    /// aren't all equally likely.
+    pub fn short_token_entropy_bits(&self) -> f64 {
+        let literal = match &self.short_token_prefix {
+            Some(prefix) => prefix.chars().count(),
+            None => 0,
+        };
+        self.encoding
+            .entropy_bits(self.length_unit, self.short_token_length, literal)
+    }
+
+    /// The bits of entropy in the secret long token
+    pub fn long_token_entropy_bits(&self) -> f64 {
+        self.encoding
+            .entropy_bits(self.length_unit, self.long_token_length, 0)
+    }
+
+    /// Creates a [KeyScanner] which finds keys generated by this controller in
+    /// arbitrary text, like logs or git diffs.
+    pub fn scanner(&self) -> KeyScanner {
+        let scanner = KeyScanner::new(
+            self.prefix.clone(),
+            self.short_token_prefix.clone(),
+            self.short_token_length,
+            self.long_token_length,
+        )
+        .with_encoding(self.encoding)
+        .with_length_unit(self.length_unit)
+        .with_key_formats(self.formats.clone());
+        match &self.environment {
+            Some(environment) => scanner.with_environment(environment.clone()),
+            None => scanner,
+        }
+    }
+
+    /// A regex matching exactly the keys this controller generates. See
+    /// [KeyScanner::regex] for details, and the scanner's other methods for exporting
+    /// it as GitHub, gitleaks or trufflehog patterns.
+    pub fn scanning_regex(&self) -> String {
+        self.scanner().regex()
+    }
+
+    /// Parses a key string, like [PrefixedApiKey::from_string], but also accepts keys
+    /// with an environment segment, and keys in any of the controller's [KeyFormats].
+    /// Keys from a different environment than the one configured on the controller are
+    /// rejected with [WrongEnvironment](PrefixedApiKeyError::WrongEnvironment), and keys
+    /// with tokens which aren't in the configured [TokenEncoding] are rejected with
+    /// [InvalidEncoding](PrefixedApiKeyError::InvalidEncoding). With
+    /// [LengthUnit::Characters], tokens which aren't exactly the configured lengths are
+    /// rejected with [WrongTokenLength](PrefixedApiKeyError::WrongTokenLength).
+    pub fn parse_key(&self, key: &str) -> Result<PrefixedApiKey, PrefixedApiKeyError> {
+        let pak = self.formats.parse(key)?;
+        if pak.environment() != self.environment() {
+            return Err(PrefixedApiKeyError::WrongEnvironment {
+                expected: self.environment.clone(),
+                found: pak.environment().map(str::to_owned),
+            });
+        }
+        self.normalize_tokens(pak)
+    }
+
+    /// Checks the key's tokens are in the configured encoding, converting them to the
+    /// form they were generated in. The short token prefix is left as it is, since it
+    /// doesn't need to be in the encoding's alphabet.
+    fn normalize_tokens(&self, pak: PrefixedApiKey) -> Result<PrefixedApiKey, PrefixedApiKeyError> {
+        let invalid = || PrefixedApiKeyError::InvalidEncoding(self.encoding);
+
+        let literal: String = match &self.short_token_prefix {
+            Some(prefix) => prefix.chars().take(self.short_token_length).collect(),
+            None => String::new(),
+        };
+        let short_token = match pak.short_token().strip_prefix(literal.as_str()) {
+            Some(random) => {
+                literal.clone() + &self.encoding.normalize(random).ok_or_else(invalid)?
+            }
+            None => self
+                .encoding
+                .normalize(pak.short_token())
+                .ok_or_else(invalid)?,
+        };
+        let long_token = self
+            .encoding
+            .normalize(pak.long_token())
+            .ok_or_else(invalid)?;
+
+        if self.length_unit == LengthUnit::Characters {
+            for (token, expected) in [
+                (&short_token, self.short_token_length),
+                (&long_token, self.long_token_length),
+            ] {
+                let found = token.chars().count();
+                if found != expected {
+                    return Err(PrefixedApiKeyError::WrongTokenLength { expected, found });
+                }
+            }
+        }
+
+        Ok(pak.with_tokens(short_token, long_token))
+    }
+
+    /// Constructs a key from its parts, setting the configured environment and the
+    /// newest key format
+    pub(crate) fn new_key(&self, short_token: String, long_token: String) -> PrefixedApiKey {
+        let mut pak = PrefixedApiKey::new(self.prefix.to_owned(), short_token, long_token);
+        let format = self.formats.newest();
+        if format.version() != 1 {
+            pak = pak.with_format(format.clone());
+        }
+        match &self.environment {
+            Some(environment) => pak.with_environment(environment.clone()),
+            None => pak,
+        }
+    }
+
+    /// Creates the record for a newly generated key
+    pub(crate) fn new_record(pak: &PrefixedApiKey, hash: String) -> ApiKeyRecord {
+        let record = ApiKeyRecord::new(pak.short_token().to_owned(), hash)
+            .with_prefix(pak.prefix().to_owned());
+        match pak.environment() {
+            Some(environment) => record.with_environment(environment.to_owned()),
+            None => record,
+        }
+    }
+
     /// Generates random bytes using the configured random number generator
     ///
     /// Can potentially panic depending on the rng source's implementation of [fill_bytes](rand::RngCore::fill_bytes).
@@ -63,24 +271,143 @@ impl<R: RngCore + Clone, D: Digest + FixedOutputReset> PrefixedApiKeyController<
 
     /// Generates a random token for part of the api key. This can be used for generating
     /// both the secret long key, and the shorter plaintext key. The random values are
-    /// base58 encoded, which is a key feature/requirement of the library.
+    /// encoded using the configured [TokenEncoding], which is base58 by default.
     ///
     /// Can potentially panic depending on the rng source's implementation of [fill_bytes](rand::RngCore::fill_bytes).
     fn get_random_token(&self, length: usize) -> String {
-        let bytes = self.get_random_bytes(length);
-        bs58::encode(bytes).into_string()
+        match self.length_unit {
+            LengthUnit::Bytes => {
+                let bytes = self.get_random_bytes(length);
+                self.encoding.encode(&bytes)
+            }
+            LengthUnit::Characters => {
+                let mut rng = self.rng.clone();
+                let sampled = self.encoding.sample(length, |bytes| {
+                    rng.fill_bytes(bytes);
+                    Ok::<_, Infallible>(())
+                });
+                match sampled {
+                    Ok(token) => token,
+                    Err(never) => match never {},
+                }
+            }
+        }
     }
 
     /// Tries to generate a random token for part of the api key. This can be used for
     /// generating both the secret long key, and the shorter plaintext key. The random values
-    /// are base58 encoded, which is a key feature/requirement of the library.
+    /// are encoded using the configured [TokenEncoding], which is base58 by default.
     fn try_get_random_token(&self, length: usize) -> Result<String, crate::rand::Error> {
+        if self.length_unit == LengthUnit::Characters {
+            let mut rng = self.rng.clone();
+            return self
+                .encoding
+                .sample(length, |bytes| rng.try_fill_bytes(bytes));
+        }
+
         match self.try_get_random_bytes(length) {
-            Ok(bytes) => Ok(bs58::encode(bytes).into_string()),
+            Ok(bytes) => Ok(self.encoding.encode(&bytes)),
             Err(err) => Err(err),
         }
     }
 
+    /// Derives the long token for the short token, if the controller was configured
+    /// with a [DerivationKey](crate::DerivationKey)
+    #[cfg(feature = "derivation")]
+    pub(crate) fn derived_long_token(&self, short_token: &str) -> Option<String> {
+        self.derivation.as_ref().map(|derivation| {
+            derivation.derive_long_token(
+                short_token,
+                self.encoding,
+                self.length_unit,
+                self.long_token_length,
+            )
+        })
+    }
+
+    #[cfg(not(feature = "derivation"))]
+    fn derived_long_token(&self, _short_token: &str) -> Option<String> {
+        None
+    }
+
+    /// Signs the key with the short token, if the controller was configured with a
+    /// [KeySigner](crate::KeySigner)
+    #[cfg(feature = "signed-keys")]
+    pub(crate) fn signed_long_token(
+        &self,
+        short_token: &str,
+        expires_at: Option<SystemTime>,
+    ) -> Option<String> {
+        self.signer.as_ref().map(|signer| {
+            sign_long_token(
+                signer.as_ref(),
+                &self.prefix,
+                self.environment(),
+                short_token,
+                expires_at,
+                self.encoding,
+            )
+        })
+    }
+
+    #[cfg(not(feature = "signed-keys"))]
+    fn signed_long_token(
+        &self,
+        _short_token: &str,
+        _expires_at: Option<SystemTime>,
+    ) -> Option<String> {
+        None
+    }
+
+    /// If the short token prefix is configured, concats it and the random token and
+    /// drops any characters beyond the configured short token length
+    fn with_short_token_prefix(&self, random: String) -> String {
+        match self.short_token_prefix.as_ref() {
+            Some(prefix_string) => (prefix_string.to_owned() + &random)
+                .chars()
+                .take(self.short_token_length)
+                .collect(),
+            None => random,
+        }
+    }
+
+    /// Whether the short token looks like a `v<version>` marker, which [KeyFormats::parse]
+    /// would misread in keys without an environment. Short tokens made up entirely of the
+    /// short token prefix can't be regenerated, so they're never reported.
+    fn is_version_marker(&self, short_token: &str) -> bool {
+        let literal = match &self.short_token_prefix {
+            Some(prefix) => prefix.chars().count(),
+            None => 0,
+        };
+        literal < self.short_token_length && parse_version_marker(short_token).is_some()
+    }
+
+    /// Generates the short token, starting with the short token prefix if configured.
+    /// Short tokens which look like a version marker are regenerated.
+    ///
+    /// Can potentially panic depending on the rng source's implementation of [fill_bytes](rand::RngCore::fill_bytes).
+    pub(crate) fn generate_short_token(&self) -> String {
+        loop {
+            let random = self.get_random_token(self.short_token_length);
+            let short_token = self.with_short_token_prefix(random);
+            if !self.is_version_marker(&short_token) {
+                return short_token;
+            }
+        }
+    }
+
+    /// Tries to generate the short token, starting with the short token prefix if configured.
+    /// Short tokens which look like a version marker are regenerated.
+    pub(crate) fn try_generate_short_token(&self) -> Result<String, crate::rand::Error> {
+        loop {
+            let random = self.try_get_random_token(self.short_token_length)?;
+            let short_token = self.with_short_token_prefix(random);
+            if !self.is_version_marker(&short_token) {
+                return Ok(short_token);
+            }
+        }
+    }
+
     /// Generates a new PrefiexedApiKey using the configured string prefix, short token
     /// prefix (if configured), and random number generator. A hash of the new keys' long
     /// token is not calculated, so you'll still need to create the hash after calling
@@ -89,23 +416,19 @@ impl<R: RngCore + Clone, D: Digest + FixedOutputReset> PrefixedApiKeyController<
     /// Can potentially panic depending on the rng source's implementation of [fill_bytes](rand::RngCore::fill_bytes).
     pub fn generate_key(&self) -> PrefixedApiKey {
         // generate the short token
-        let mut short_token = self.get_random_token(self.short_token_length);
+        let short_token = self.generate_short_token();
 
-        // If the short token prefix is configured, concat it and the generated string and
-        // drop any characters beyond the configured short token length
-        if self.short_token_prefix.is_some() {
-            let prefix_string = self.short_token_prefix.as_ref().unwrap().to_owned();
-            short_token = (prefix_string + &short_token)
-                .chars()
-                .take(self.short_token_length)
-                .collect()
-        }
-
-        // Generate the secret long token
-        let long_token = self.get_random_token(self.long_token_length);
+        // Generate the secret long token, unless it's derived from or signs the short token
+        let long_token = match self.derived_long_token(&short_token) {
+            Some(long_token) => long_token,
+            None => match self.signed_long_token(&short_token, None) {
+                Some(long_token) => long_token,
+                None => self.get_random_token(self.long_token_length),
+            },
+        };
 
         // Construct and return the new pak
-        PrefixedApiKey::new(self.prefix.to_owned(), short_token, long_token)
+        self.new_key(short_token, long_token)
     }
 
     /// Tries to generate a new PrefiexedApiKey using the configured string prefix, short
@@ -114,23 +437,19 @@ impl<R: RngCore + Clone, D: Digest + FixedOutputReset> PrefixedApiKeyController<
     /// this function.
     pub fn try_generate_key(&self) -> Result<PrefixedApiKey, crate::rand::Error> {
         // generate the short token
-        let mut short_token = self.try_get_random_token(self.short_token_length)?;
+        let short_token = self.try_generate_short_token()?;
 
-        // If the short token prefix is configured, concat it and the generated string and
-        // drop any characters beyond the configured short token length
-        if self.short_token_prefix.is_some() {
-            let prefix_string = self.short_token_prefix.as_ref().unwrap().to_owned();
-            short_token = (prefix_string + &short_token)
-                .chars()
-                .take(self.short_token_length)
-                .collect()
-        }
-
-        // Generate the secret long token
-        let long_token = self.try_get_random_token(self.long_token_length)?;
+        // Generate the secret long token, unless it's derived from or signs the short token
+        let long_token = match self.derived_long_token(&short_token) {
+            Some(long_token) => long_token,
+            None => match self.signed_long_token(&short_token, None) {
+                Some(long_token) => long_token,
+                None => self.try_get_random_token(self.long_token_length)?,
+            },
+        };
 
         // Construct and return the new pak
-        let pak = PrefixedApiKey::new(self.prefix.to_owned(), short_token, long_token);
+        let pak = self.new_key(short_token, long_token);
         Ok(pak)
     }
 
@@ -138,6 +457,10 @@ impl<R: RngCore + Clone, D: Digest + FixedOutputReset> PrefixedApiKeyController<
     /// returns the hash of the long token.
     ///
     /// Can potentially panic depending on the rng source's implementation of [fill_bytes](rand::RngCore::fill_bytes).
+    #[deprecated(
+        since = "0.4.0",
+        note = "use generate_key_and_record, which returns the hash in an ApiKeyRecord ready for a KeyStore"
+    )]
     pub fn generate_key_and_hash(&self) -> (PrefixedApiKey, String) {
         let pak = self.generate_key();
         let hash = self.long_token_hashed(&pak);
@@ -146,6 +469,10 @@ impl<R: RngCore + Clone, D: Digest + FixedOutputReset> PrefixedApiKeyController<
 
     /// Generates a new key using the [try_generate_key](PrefixedApiKeyController::try_generate_key) function, but also calculates and
     /// returns the hash of the long token.
+    #[deprecated(
+        since = "0.4.0",
+        note = "use try_generate_key_and_record, which returns the hash in an ApiKeyRecord ready for a KeyStore"
+    )]
     pub fn try_generate_key_and_hash(
         &self,
     ) -> Result<(PrefixedApiKey, String), crate::rand::Error> {
@@ -158,6 +485,50 @@ impl<R: RngCore + Clone, D: Digest + FixedOutputReset> PrefixedApiKeyController<
         }
     }
 
+    /// Generates a new key using the [generate_key](PrefixedApiKeyController::generate_key) function, along with
+    /// an [ApiKeyRecord] holding the hash of the long token, ready to be saved to a [KeyStore].
+    ///
+    /// Can potentially panic depending on the rng source's implementation of [fill_bytes](rand::RngCore::fill_bytes).
+    pub fn generate_key_and_record(&self) -> (PrefixedApiKey, ApiKeyRecord) {
+        let pak = self.generate_key();
+        let hash = self.long_token_hashed(&pak);
+        let record = Self::new_record(&pak, hash);
+        (pak, record)
+    }
+
+    /// Generates a new key using the [try_generate_key](PrefixedApiKeyController::try_generate_key) function, along
+    /// with an [ApiKeyRecord] holding the hash of the long token, ready to be saved to a [KeyStore].
+    pub fn try_generate_key_and_record(
+        &self,
+    ) -> Result<(PrefixedApiKey, ApiKeyRecord), crate::rand::Error> {
+        let pak = self.try_generate_key()?;
+        let hash = self.long_token_hashed(&pak);
+        let record = Self::new_record(&pak, hash);
+        Ok((pak, record))
+    }
+
+    /// Like [generate_key_and_record](PrefixedApiKeyController::generate_key_and_record), but the
+    /// record is issued with the given scopes already granted.
+    ///
+    /// Can potentially panic depending on the rng source's implementation of [fill_bytes](rand::RngCore::fill_bytes).
+    pub fn generate_key_and_record_with_scopes(
+        &self,
+        scopes: ScopeSet,
+    ) -> (PrefixedApiKey, ApiKeyRecord) {
+        let (pak, record) = self.generate_key_and_record();
+        (pak, record.with_scopes(scopes))
+    }
+
+    /// Like [try_generate_key_and_record](PrefixedApiKeyController::try_generate_key_and_record),
+    /// but the record is issued with the given scopes already granted.
+    pub fn try_generate_key_and_record_with_scopes(
+        &self,
+        scopes: ScopeSet,
+    ) -> Result<(PrefixedApiKey, ApiKeyRecord), crate::rand::Error> {
+        let (pak, record) = self.try_generate_key_and_record()?;
+        Ok((pak, record.with_scopes(scopes)))
+    }
+
     /// Hashes the long token of the provided PrefixedApiKey using the hashing
     /// algorithm configured on the controller. The hashing instance gets
     /// reused each time this is called, which is why the [FixedOutputReset](digest::FixedOutputReset)
@@ -175,15 +546,243 @@ impl<R: RngCore + Clone, D: Digest + FixedOutputReset> PrefixedApiKeyController<
         let pak_hash = self.long_token_hashed(pak);
         constant_time_eq(pak_hash.as_bytes(), hash.as_bytes())
     }
+
+    /// Like [check_hash](PrefixedApiKeyController::check_hash), but first consults the
+    /// blocklist so revoked keys are rejected before any hashing is done. Returns
+    /// `Err` with the revocation if the key's short token is blocklisted.
+    pub fn check<B: Blocklist>(
+        &self,
+        pak: &PrefixedApiKey,
+        hash: &str,
+        blocklist: &B,
+    ) -> Result<Result<bool, Revocation>, B::Error> {
+        if let Some(revocation) = blocklist.lookup(pak.short_token())? {
+            return Ok(Err(revocation));
+        }
+        Ok(Ok(self.check_hash(pak, hash)))
+    }
+
+    /// Authenticates the key against the record stored for its short token. The
+    /// blocklist is consulted first, before any hashing is done. Then the long token is
+    /// hashed and compared against the stored hash, and only keys with the right secret
+    /// learn whether their record has been revoked or has expired.
+    ///
+    /// Records issued for another prefix or environment are reported as
+    /// [UnknownKey](AuthOutcome::UnknownKey), so controllers can safely share a store.
+    ///
+    /// If the controller was configured with [touch_last_used](crate::ControllerBuilder::touch_last_used),
+    /// the record's `last_used_at` is updated in the store on success, at most once per
+    /// configured interval.
+    pub fn authenticate<S: KeyStore, B: Blocklist>(
+        &self,
+        pak: &PrefixedApiKey,
+        store: &S,
+        blocklist: &B,
+    ) -> Result<AuthOutcome, AuthError<S::Error, B::Error>> {
+        authenticate_with(self, pak, store, blocklist)
+    }
+
+    /// Rotates the key with the given short token. A new key is issued with the same
+    /// owner, label and scopes, and linked to its predecessor. The predecessor keeps
+    /// working until `grace` has passed, during which [authenticate](PrefixedApiKeyController::authenticate)
+    /// reports it as [Superseded](AuthOutcome::Superseded).
+    ///
+    /// Both records are saved to the store, and the new key is returned along with its record.
+    /// Keys which are revoked, expired or already superseded can't be rotated, and records
+    /// issued for another prefix or environment are reported as
+    /// [UnknownKey](RotationError::UnknownKey), like [authenticate](PrefixedApiKeyController::authenticate) does.
+    ///
+    /// The two records are saved with separate [put](KeyStore::put)s, which aren't
+    /// atomic. If saving the predecessor fails, the new key exists but the predecessor
+    /// isn't marked as superseded, so rotating it again issues another key.
+    pub fn rotate_key<S: KeyStore>(
+        &self,
+        store: &S,
+        short_token: &str,
+        grace: Duration,
+    ) -> Result<(PrefixedApiKey, ApiKeyRecord), RotationError<S::Error>> {
+        let mut predecessor = match store.get(short_token).map_err(RotationError::Store)? {
+            Some(record) => record,
+            None => return Err(RotationError::UnknownKey),
+        };
+
+        if !issued_by(&predecessor, self) {
+            return Err(RotationError::UnknownKey);
+        }
+
+        if predecessor.is_revoked() {
+            return Err(RotationError::Revoked);
+        }
+
+        if predecessor.is_expired(SystemTime::now()) {
+            return Err(RotationError::Expired);
+        }
+
+        if predecessor.superseded_by.is_some() {
+            return Err(RotationError::Superseded);
+        }
+
+        let (pak, mut record) = self
+            .try_generate_key_and_record()
+            .map_err(RotationError::Rng)?;
+        record.owner_id = predecessor.owner_id.clone();
+        record.label = predecessor.label.clone();
+        record.scopes = predecessor.scopes.clone();
+        record.predecessor = Some(predecessor.short_token.clone());
+
+        // Never extend an expiry which was already sooner than the grace period
+        let grace_expiry = SystemTime::now() + grace;
+        predecessor.expires_at = match predecessor.expires_at {
+            Some(expires_at) if expires_at < grace_expiry => Some(expires_at),
+            _ => Some(grace_expiry),
+        };
+        predecessor.superseded_by = Some(record.short_token.clone());
+
+        store.put(record.clone()).map_err(RotationError::Store)?;
+        store.put(predecessor).map_err(RotationError::Store)?;
+
+        Ok((pak, record))
+    }
+}
+
+/// The parts of a controller which don't depend on its rng and digest types, so
+/// controllers with different types can be held together.
+pub(crate) trait ErasedController {
+    fn prefix(&self) -> &str;
+    fn environment(&self) -> Option<&str>;
+    fn last_used_throttle(&self) -> Option<Duration>;
+    fn parse_key(&self, key: &str) -> Result<PrefixedApiKey, PrefixedApiKeyError>;
+    fn try_generate_key_and_record(
+        &self,
+    ) -> Result<(PrefixedApiKey, ApiKeyRecord), crate::rand::Error>;
+    fn check_hash(&self, pak: &PrefixedApiKey, hash: &str) -> bool;
+    fn try_generate_short_token(&self) -> Result<String, crate::rand::Error>;
+}
+
+impl<R: RngCore + Clone, D: Digest + FixedOutputReset> ErasedController
+    for PrefixedApiKeyController<R, D>
+{
+    fn prefix(&self) -> &str {
+        PrefixedApiKeyController::prefix(self)
+    }
+
+    fn environment(&self) -> Option<&str> {
+        PrefixedApiKeyController::environment(self)
+    }
+
+    fn last_used_throttle(&self) -> Option<Duration> {
+        self.last_used_throttle
+    }
+
+    fn parse_key(&self, key: &str) -> Result<PrefixedApiKey, PrefixedApiKeyError> {
+        PrefixedApiKeyController::parse_key(self, key)
+    }
+
+    fn try_generate_key_and_record(
+        &self,
+    ) -> Result<(PrefixedApiKey, ApiKeyRecord), crate::rand::Error> {
+        PrefixedApiKeyController::try_generate_key_and_record(self)
+    }
+
+    fn check_hash(&self, pak: &PrefixedApiKey, hash: &str) -> bool {
+        PrefixedApiKeyController::check_hash(self, pak, hash)
+    }
+
+    fn try_generate_short_token(&self) -> Result<String, crate::rand::Error> {
+        PrefixedApiKeyController::try_generate_short_token(self)
+    }
+}
+
+/// Whether the record was issued for the controller's prefix and environment. Records
+/// saved before prefixes were stored have no prefix, so only their environment is compared.
+pub(crate) fn issued_by<C: ErasedController + ?Sized>(
+    record: &ApiKeyRecord,
+    controller: &C,
+) -> bool {
+    let same_prefix = match &record.prefix {
+        Some(prefix) => prefix == controller.prefix(),
+        None => true,
+    };
+    same_prefix && record.environment.as_deref() == controller.environment()
+}
+
+/// Authenticates the key as described by [PrefixedApiKeyController::authenticate]
+pub(crate) fn authenticate_with<C, S, B>(
+    controller: &C,
+    pak: &PrefixedApiKey,
+    store: &S,
+    blocklist: &B,
+) -> Result<AuthOutcome, AuthError<S::Error, B::Error>>
+where
+    C: ErasedController + ?Sized,
+    S: KeyStore,
+    B: Blocklist,
+{
+    if pak.prefix() != controller.prefix() || pak.environment() != controller.environment() {
+        return Ok(AuthOutcome::UnknownKey);
+    }
+
+    if let Some(revocation) = blocklist
+        .lookup(pak.short_token())
+        .map_err(AuthError::Blocklist)?
+    {
+        return Ok(AuthOutcome::Revoked(revocation));
+    }
+
+    let mut record = match store.get(pak.short_token()).map_err(AuthError::Store)? {
+        Some(record) => record,
+        None => return Ok(AuthOutcome::UnknownKey),
+    };
+
+    // Keys issued for another environment or prefix must never work here, even if
+    // they share a store
+    if !issued_by(&record, controller) {
+        return Ok(AuthOutcome::UnknownKey);
+    }
+
+    // The secret is checked before the record's revocation and expiry, so those are
+    // only revealed to someone holding the key
+    if !controller.check_hash(pak, &record.hash) {
+        return Ok(AuthOutcome::InvalidSecret);
+    }
+
+    if let Some(revocation) = record.revocation.take() {
+        return Ok(AuthOutcome::Revoked(revocation));
+    }
+
+    let now = SystemTime::now();
+    if record.is_expired(now) {
+        return Ok(AuthOutcome::Expired);
+    }
+
+    if let Some(throttle) = controller.last_used_throttle() {
+        if record.touch(now, throttle) {
+            store
+                .touch_last_used(&record.short_token, now)
+                .map_err(AuthError::Store)?;
+        }
+    }
+
+    if record.superseded_by.is_some() {
+        return Ok(AuthOutcome::Superseded(record));
+    }
+
+    Ok(AuthOutcome::Authenticated(record))
 }
 
 #[cfg(test)]
 mod controller_tests {
+    use std::time::{Duration, SystemTime};
+
     use rand::rngs::OsRng;
     use sha2::Sha256;
 
     use crate::controller::PrefixedApiKeyController;
-    use crate::PrefixedApiKey;
+    use crate::{
+        ApiKeyRecord, AuthOutcome, Blocklist, KeyStore, LengthUnit, MemoryBlocklist,
+        MemoryKeyStore, PrefixedApiKey, PrefixedApiKeyError, RotationError, ScopeSet,
+        StoreBlocklist, TokenEncoding,
+    };
 
     #[test]
     fn configuration_works() {
@@ -201,7 +800,7 @@ mod controller_tests {
             PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
         let token_string = generator.generate_key().to_string();
         let pak_result = PrefixedApiKey::from_string(&token_string);
-        assert_eq!(pak_result.is_ok(), true);
+        assert!(pak_result.is_ok());
         let pak_string = pak_result.unwrap().to_string();
         assert_eq!(token_string, pak_string);
     }
@@ -214,7 +813,7 @@ mod controller_tests {
         assert!(token_res.is_ok());
         let token_string = token_res.unwrap().to_string();
         let pak_result = PrefixedApiKey::from_string(&token_string);
-        assert_eq!(pak_result.is_ok(), true);
+        assert!(pak_result.is_ok());
         let pak_string = pak_result.unwrap().to_string();
         assert_eq!(token_string, pak_string);
     }
@@ -235,6 +834,7 @@ mod controller_tests {
     }
 
     #[test]
+    #[allow(deprecated)]
     fn generate_key_and_hash() {
         let generator =
             PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
@@ -283,4 +883,497 @@ mod controller_tests {
 
         assert!(generator.check_hash(&pak, pak_hash));
     }
+
+    #[test]
+    fn check_rejects_blocklisted_keys() {
+        let generator =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        let pak = generator.generate_key();
+        let hash = generator.long_token_hashed(&pak);
+        let blocklist = MemoryBlocklist::new();
+
+        assert_eq!(generator.check(&pak, &hash, &blocklist).unwrap(), Ok(true));
+
+        let revocation = blocklist
+            .revoke(pak.short_token(), "leaked", "alice")
+            .unwrap();
+        assert_eq!(
+            generator.check(&pak, &hash, &blocklist).unwrap(),
+            Err(revocation)
+        );
+    }
+
+    #[test]
+    fn authenticate_outcomes() {
+        let generator =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        let pak = generator.generate_key();
+        let hash = generator.long_token_hashed(&pak);
+        let store = MemoryKeyStore::new();
+        let blocklist = MemoryBlocklist::new();
+
+        let outcome = generator.authenticate(&pak, &store, &blocklist).unwrap();
+        assert_eq!(outcome, AuthOutcome::UnknownKey);
+
+        let record = ApiKeyRecord::new(pak.short_token().to_owned(), hash);
+        store.put(record.clone()).unwrap();
+        let outcome = generator.authenticate(&pak, &store, &blocklist).unwrap();
+        assert_eq!(outcome, AuthOutcome::Authenticated(record));
+
+        let wrong_secret = PrefixedApiKey::new(
+            pak.prefix().to_owned(),
+            pak.short_token().to_owned(),
+            "wrong".to_owned(),
+        );
+        let outcome = generator
+            .authenticate(&wrong_secret, &store, &blocklist)
+            .unwrap();
+        assert_eq!(outcome, AuthOutcome::InvalidSecret);
+
+        let revocation = StoreBlocklist::new(&store)
+            .revoke(pak.short_token(), "leaked", "alice")
+            .unwrap();
+        let outcome = generator.authenticate(&pak, &store, &blocklist).unwrap();
+        assert_eq!(outcome, AuthOutcome::Revoked(revocation));
+    }
+
+    #[test]
+    fn generate_key_and_record() {
+        let generator =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        let (pak, record) = generator.generate_key_and_record();
+        assert_eq!(record.short_token, pak.short_token());
+        assert!(generator.check_hash(&pak, &record.hash));
+        assert_eq!(record.last_used_at, None);
+    }
+
+    #[test]
+    fn generate_key_and_record_with_scopes() {
+        let generator =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        let scopes = ScopeSet::parse("billing:read users:*").unwrap();
+        let (pak, record) = generator.generate_key_and_record_with_scopes(scopes.clone());
+        assert_eq!(record.short_token, pak.short_token());
+        assert_eq!(record.scopes, scopes);
+
+        let (_, record) = generator
+            .try_generate_key_and_record_with_scopes(scopes.clone())
+            .unwrap();
+        assert_eq!(record.scopes, scopes);
+    }
+
+    #[test]
+    fn authenticate_touches_last_used() {
+        let generator = PrefixedApiKeyController::<_, Sha256>::configure()
+            .prefix("mycompany".to_owned())
+            .rng(OsRng)
+            .default_lengths()
+            .touch_last_used(Duration::from_secs(60))
+            .finalize()
+            .unwrap();
+        let (pak, record) = generator.try_generate_key_and_record().unwrap();
+        let store = MemoryKeyStore::new();
+        let blocklist = MemoryBlocklist::new();
+        store.put(record).unwrap();
+
+        let outcome = generator.authenticate(&pak, &store, &blocklist).unwrap();
+        let last_used_at = outcome.record().unwrap().last_used_at;
+        assert!(last_used_at.is_some());
+        assert_eq!(
+            store.get(pak.short_token()).unwrap().unwrap().last_used_at,
+            last_used_at
+        );
+
+        // A second authentication within the throttle interval leaves it untouched
+        let outcome = generator.authenticate(&pak, &store, &blocklist).unwrap();
+        assert_eq!(outcome.record().unwrap().last_used_at, last_used_at);
+    }
+
+    #[test]
+    fn rotate_key_keeps_predecessor_working_during_grace() {
+        let generator =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        let (old_pak, old_record) = generator.generate_key_and_record();
+        let store = MemoryKeyStore::new();
+        let blocklist = MemoryBlocklist::new();
+        store
+            .put(
+                old_record
+                    .with_label("ci".to_owned())
+                    .with_scopes(ScopeSet::parse("read:billing").unwrap()),
+            )
+            .unwrap();
+
+        let (new_pak, new_record) = generator
+            .rotate_key(&store, old_pak.short_token(), Duration::from_secs(3600))
+            .unwrap();
+        assert_eq!(
+            new_record.predecessor.as_deref(),
+            Some(old_pak.short_token())
+        );
+        assert_eq!(new_record.label.as_deref(), Some("ci"));
+        assert_eq!(new_record.scopes.to_string(), "read:billing");
+
+        let outcome = generator
+            .authenticate(&new_pak, &store, &blocklist)
+            .unwrap();
+        assert_eq!(outcome, AuthOutcome::Authenticated(new_record.clone()));
+
+        let outcome = generator
+            .authenticate(&old_pak, &store, &blocklist)
+            .unwrap();
+        assert!(outcome.is_authenticated());
+        assert!(outcome.is_superseded());
+        assert_eq!(
+            outcome.record().unwrap().superseded_by.as_deref(),
+            Some(new_pak.short_token())
+        );
+    }
+
+    #[test]
+    fn rotate_key_expires_predecessor_after_grace() {
+        let generator =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        let (old_pak, old_record) = generator.generate_key_and_record();
+        let store = MemoryKeyStore::new();
+        let blocklist = MemoryBlocklist::new();
+        store.put(old_record).unwrap();
+
+        generator
+            .rotate_key(&store, old_pak.short_token(), Duration::from_secs(0))
+            .unwrap();
+
+        let outcome = generator
+            .authenticate(&old_pak, &store, &blocklist)
+            .unwrap();
+        assert_eq!(outcome, AuthOutcome::Expired);
+    }
+
+    #[test]
+    fn rotate_key_rejects_unknown_and_revoked_keys() {
+        let generator =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        let (pak, record) = generator.generate_key_and_record();
+        let store = MemoryKeyStore::new();
+        store.put(record).unwrap();
+
+        let result = generator.rotate_key(&store, "missing", Duration::from_secs(60));
+        assert!(matches!(result, Err(RotationError::UnknownKey)));
+
+        StoreBlocklist::new(&store)
+            .revoke(pak.short_token(), "leaked", "alice")
+            .unwrap();
+        let result = generator.rotate_key(&store, pak.short_token(), Duration::from_secs(60));
+        assert!(matches!(result, Err(RotationError::Revoked)));
+    }
+
+    #[test]
+    fn rotate_key_rejects_expired_and_superseded_keys() {
+        let generator =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        let store = MemoryKeyStore::new();
+
+        let (pak, record) = generator.generate_key_and_record();
+        store
+            .put(record.with_expires_at(SystemTime::now() - Duration::from_secs(1)))
+            .unwrap();
+        let result = generator.rotate_key(&store, pak.short_token(), Duration::from_secs(60));
+        assert!(matches!(result, Err(RotationError::Expired)));
+
+        let (pak, record) = generator.generate_key_and_record();
+        store.put(record).unwrap();
+        generator
+            .rotate_key(&store, pak.short_token(), Duration::from_secs(60))
+            .unwrap();
+        let result = generator.rotate_key(&store, pak.short_token(), Duration::from_secs(60));
+        assert!(matches!(result, Err(RotationError::Superseded)));
+    }
+
+    #[test]
+    fn rotate_key_rejects_keys_from_other_tenants() {
+        let tenant_a =
+            PrefixedApiKeyController::<_, Sha256>::new("tenantA".to_owned(), OsRng, None, 8, 24);
+        let tenant_b =
+            PrefixedApiKeyController::<_, Sha256>::new("tenantB".to_owned(), OsRng, None, 8, 24);
+        let live = PrefixedApiKeyController::<_, Sha256>::configure()
+            .prefix("tenantA".to_owned())
+            .rng(OsRng)
+            .default_lengths()
+            .environment("live".to_owned())
+            .finalize()
+            .unwrap();
+        let store = MemoryKeyStore::new();
+
+        let (pak, record) = tenant_a.generate_key_and_record();
+        store.put(record).unwrap();
+
+        for other in [&tenant_b, &live] {
+            let result = other.rotate_key(&store, pak.short_token(), Duration::from_secs(60));
+            assert!(matches!(result, Err(RotationError::UnknownKey)));
+        }
+        let record = store.get(pak.short_token()).unwrap().unwrap();
+        assert_eq!(record.superseded_by, None);
+        assert_eq!(store.len(), 1);
+
+        assert!(tenant_a
+            .rotate_key(&store, pak.short_token(), Duration::from_secs(60))
+            .is_ok());
+    }
+
+    #[test]
+    fn authenticate_hides_record_state_without_the_secret() {
+        let generator =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        let store = MemoryKeyStore::new();
+        let blocklist = MemoryBlocklist::new();
+
+        let (expired, record) = generator.generate_key_and_record();
+        store
+            .put(record.with_expires_at(SystemTime::now() - Duration::from_secs(1)))
+            .unwrap();
+        let (revoked, record) = generator.generate_key_and_record();
+        store.put(record).unwrap();
+        StoreBlocklist::new(&store)
+            .revoke(revoked.short_token(), "leaked", "alice")
+            .unwrap();
+
+        for pak in [&expired, &revoked] {
+            let guess = PrefixedApiKey::new(
+                pak.prefix().to_owned(),
+                pak.short_token().to_owned(),
+                "guess".to_owned(),
+            );
+            let outcome = generator.authenticate(&guess, &store, &blocklist).unwrap();
+            assert_eq!(outcome, AuthOutcome::InvalidSecret);
+        }
+        let outcome = generator
+            .authenticate(&expired, &store, &blocklist)
+            .unwrap();
+        assert_eq!(outcome, AuthOutcome::Expired);
+        let outcome = generator
+            .authenticate(&revoked, &store, &blocklist)
+            .unwrap();
+        assert!(matches!(outcome, AuthOutcome::Revoked(_)));
+    }
+
+    #[test]
+    fn authenticate_consults_blocklist_first() {
+        let generator =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        let pak = generator.generate_key();
+        let store = MemoryKeyStore::new();
+        let blocklist = MemoryBlocklist::new();
+        blocklist
+            .import(vec![pak.short_token()], "breach", "ops")
+            .unwrap();
+
+        // The key isn't even in the store, but the blocklist wins
+        let outcome = generator.authenticate(&pak, &store, &blocklist).unwrap();
+        assert!(matches!(outcome, AuthOutcome::Revoked(_)));
+    }
+
+    fn environment_controller(environment: &str) -> PrefixedApiKeyController<OsRng, Sha256> {
+        PrefixedApiKeyController::configure()
+            .prefix("mycompany".to_owned())
+            .rng(OsRng)
+            .default_lengths()
+            .environment(environment.to_owned())
+            .finalize()
+            .unwrap()
+    }
+
+    #[test]
+    fn environment_is_added_to_keys() {
+        let live = environment_controller("live");
+        let (pak, record) = live.generate_key_and_record();
+        assert_eq!(pak.environment(), Some("live"));
+        assert_eq!(record.environment.as_deref(), Some("live"));
+        assert!(pak.to_string().starts_with("mycompany_live_"));
+
+        let parsed = live.parse_key(&pak.to_string()).unwrap();
+        assert_eq!(parsed.short_token(), pak.short_token());
+        assert_eq!(parsed.long_token(), pak.long_token());
+    }
+
+    #[test]
+    fn parse_key_rejects_other_environments() {
+        let live = environment_controller("live");
+        let test = environment_controller("test");
+        let untagged =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+
+        let test_key = test.generate_key().to_string();
+        assert_eq!(
+            live.parse_key(&test_key).unwrap_err(),
+            PrefixedApiKeyError::WrongEnvironment {
+                expected: Some("live".to_owned()),
+                found: Some("test".to_owned()),
+            }
+        );
+        assert!(matches!(
+            untagged.parse_key(&test_key),
+            Err(PrefixedApiKeyError::WrongEnvironment { expected: None, .. })
+        ));
+        assert!(matches!(
+            live.parse_key(&untagged.generate_key().to_string()),
+            Err(PrefixedApiKeyError::WrongEnvironment { found: None, .. })
+        ));
+    }
+
+    #[test]
+    fn authenticate_rejects_other_environments_in_shared_store() {
+        let live = environment_controller("live");
+        let test = environment_controller("test");
+        let store = MemoryKeyStore::new();
+        let blocklist = MemoryBlocklist::new();
+
+        let (test_pak, test_record) = test.generate_key_and_record();
+        store.put(test_record).unwrap();
+
+        let outcome = test.authenticate(&test_pak, &store, &blocklist).unwrap();
+        assert!(outcome.is_authenticated());
+
+        // Swapping the environment segment doesn't let a test key into live
+        let swapped = PrefixedApiKey::new(
+            "mycompany".to_owned(),
+            test_pak.short_token().to_owned(),
+            test_pak.long_token().to_owned(),
+        )
+        .with_environment("live".to_owned());
+        let outcome = live.authenticate(&swapped, &store, &blocklist).unwrap();
+        assert!(matches!(outcome, AuthOutcome::UnknownKey));
+
+        let outcome = live.authenticate(&test_pak, &store, &blocklist).unwrap();
+        assert!(matches!(outcome, AuthOutcome::UnknownKey));
+    }
+
+    fn encoding_controller(encoding: TokenEncoding) -> PrefixedApiKeyController<OsRng, Sha256> {
+        PrefixedApiKeyController::configure()
+            .prefix("mycompany".to_owned())
+            .rng(OsRng)
+            .short_token_prefix(Some("mc".to_owned()))
+            .default_lengths()
+            .token_encoding(encoding)
+            .finalize()
+            .unwrap()
+    }
+
+    #[test]
+    fn tokens_use_configured_encoding() {
+        let hex = encoding_controller(TokenEncoding::Hex);
+        let pak = hex.generate_key();
+        let hash = hex.long_token_hashed(&pak);
+        assert_eq!(pak.short_token().len(), 8);
+        assert!(pak.short_token().starts_with("mc"));
+        assert_eq!(pak.long_token().len(), 48);
+        assert!(pak
+            .long_token()
+            .bytes()
+            .all(|byte| byte.is_ascii_hexdigit()));
+
+        let parsed = hex.parse_key(&pak.to_string()).unwrap();
+        assert_eq!(parsed.short_token(), pak.short_token());
+        assert!(hex.check_hash(&parsed, &hash));
+
+        let base58 = encoding_controller(TokenEncoding::Base58);
+        assert_eq!(
+            hex.parse_key("mycompany_mcabcdef_xyz").unwrap_err(),
+            PrefixedApiKeyError::InvalidEncoding(TokenEncoding::Hex)
+        );
+        assert_eq!(
+            base58.parse_key("mycompany_mcabcdef_0OIl").unwrap_err(),
+            PrefixedApiKeyError::InvalidEncoding(TokenEncoding::Base58)
+        );
+    }
+
+    #[test]
+    fn crockford_keys_are_case_insensitive() {
+        let controller = encoding_controller(TokenEncoding::Crockford32);
+        let pak = controller.generate_key();
+        let hash = controller.long_token_hashed(&pak);
+        assert_eq!(pak.long_token().len(), 39);
+
+        // Read back over the phone, in lowercase, with an O instead of a 0
+        let misread = format!(
+            "mycompany_{}_{}",
+            pak.short_token().to_lowercase(),
+            pak.long_token().to_lowercase().replace('0', "o")
+        );
+        let parsed = controller.parse_key(&misread).unwrap();
+        assert_eq!(parsed.short_token(), pak.short_token());
+        assert_eq!(parsed.long_token(), pak.long_token());
+        assert!(controller.check_hash(&parsed, &hash));
+    }
+
+    fn character_controller(encoding: TokenEncoding) -> PrefixedApiKeyController<OsRng, Sha256> {
+        PrefixedApiKeyController::configure()
+            .prefix("mycompany".to_owned())
+            .rng(OsRng)
+            .short_token_prefix(Some("mc".to_owned()))
+            .short_token_length(8)
+            .long_token_length(32)
+            .token_encoding(encoding)
+            .length_unit(LengthUnit::Characters)
+            .finalize()
+            .unwrap()
+    }
+
+    #[test]
+    fn character_lengths_are_exact() {
+        for encoding in [
+            TokenEncoding::Base58,
+            TokenEncoding::Base62,
+            TokenEncoding::Hex,
+        ] {
+            let controller = character_controller(encoding);
+            for _ in 0..50 {
+                let pak = controller.generate_key();
+                let hash = controller.long_token_hashed(&pak);
+                assert_eq!(pak.short_token().len(), 8);
+                assert!(pak.short_token().starts_with("mc"));
+                assert_eq!(pak.long_token().len(), 32);
+
+                let parsed = controller.parse_key(&pak.to_string()).unwrap();
+                assert!(controller.check_hash(&parsed, &hash));
+            }
+            let pak = controller.try_generate_key().unwrap();
+            assert_eq!(pak.long_token().len(), 32);
+        }
+
+        let controller = character_controller(TokenEncoding::Base58);
+        assert_eq!(
+            controller
+                .parse_key("mycompany_mcabcdef_abcdefghijkmnopqrstuvwxyzABCDEF")
+                .unwrap_err(),
+            PrefixedApiKeyError::WrongTokenLength {
+                expected: 32,
+                found: 31
+            }
+        );
+        assert_eq!(
+            controller
+                .parse_key("mycompany_mcabc_abcdefghijkmnopqrstuvwxyzABCDEFG")
+                .unwrap_err(),
+            PrefixedApiKeyError::WrongTokenLength {
+                expected: 8,
+                found: 5
+            }
+        );
+    }
+
+    #[test]
+    fn reports_entropy_bits() {
+        let bytes =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        assert_eq!(bytes.short_token_entropy_bits(), 64.0);
+        assert_eq!(bytes.long_token_entropy_bits(), 192.0);
+
+        let hex = character_controller(TokenEncoding::Hex);
+        assert_eq!(hex.short_token_entropy_bits(), 24.0);
+        assert_eq!(hex.long_token_entropy_bits(), 128.0);
+
+        let crockford = character_controller(TokenEncoding::Crockford32);
+        assert_eq!(crockford.short_token_entropy_bits(), 30.0);
+        assert_eq!(crockford.long_token_entropy_bits(), 160.0);
+    }
 }
@@ -1,5 +1,7 @@
+#[cfg(feature = "sha2")]
 use crate::PrefixedApiKeyController;
 
+#[cfg(feature = "sha2")]
 use rand::rngs::{OsRng, StdRng, ThreadRng};
 
 // Aliases using OsRng
@@ -4,20 +4,50 @@ use rand::{
     RngCore, SeedableRng,
 };
 use std::fmt;
+#[cfg(feature = "signed-keys")]
+use std::sync::Arc;
+use std::time::Duration;
 use std::{error::Error, marker::PhantomData};
 
 #[cfg(feature = "sha2")]
 use sha2::{Sha224, Sha256, Sha384, Sha512, Sha512_224, Sha512_256};
 
 use crate::controller::PrefixedApiKeyController;
+#[cfg(feature = "derivation")]
+use crate::derivation::{DerivationKey, MAXIMUM_DERIVED_LENGTH};
+use crate::key_format::{parse_version_marker, KeyFormat, KeyFormats};
+#[cfg(feature = "signed-keys")]
+use crate::signed_key::{KeySigner, SIGNED_HEADER_LENGTH};
+use crate::token_encoding::{LengthUnit, TokenEncoding};
 
 #[derive(Debug, Clone)]
 pub enum BuilderError {
     MissingPrefix,
     MissingRng,
+    /// Never returned, since the digest is set by the builder's type
     MissingDigest,
     MissingShortTokenLength,
     MissingLongTokenLength,
+    /// The environment was empty, contained the `_` separator, or looked like a
+    /// version marker such as `v2`
+    InvalidEnvironment(String),
+    /// More than one key type was configured with the same prefix
+    DuplicatePrefix(String),
+    /// The short or long token length was zero
+    ZeroTokenLength,
+    /// The short token prefix would fill the whole short token, leaving no random characters
+    ShortTokenPrefixTooLong {
+        prefix_length: usize,
+        short_token_length: usize,
+    },
+    /// The short or long token had less entropy than the configured minimum
+    InsufficientEntropy {
+        token: &'static str,
+        bits: f64,
+        minimum: u32,
+    },
+    /// Two options were configured which can't be used together
+    IncompatibleOptions(&'static str),
 }
 
 impl fmt::Display for BuilderError {
@@ -32,19 +62,68 @@ impl fmt::Display for BuilderError {
             BuilderError::MissingLongTokenLength => {
                 write!(f, "expected long_token_length to be set, but wasn't")
             }
+            BuilderError::InvalidEnvironment(environment) => {
+                write!(
+                    f,
+                    "environment must be non-empty, not contain '_' and not be a version marker, got {:?}",
+                    environment
+                )
+            }
+            BuilderError::DuplicatePrefix(prefix) => {
+                write!(f, "prefix {:?} is used by more than one key type", prefix)
+            }
+            BuilderError::ZeroTokenLength => {
+                write!(f, "expected token lengths to be greater than zero, but weren't")
+            }
+            BuilderError::ShortTokenPrefixTooLong {
+                prefix_length,
+                short_token_length,
+            } => write!(
+                f,
+                "short_token_prefix is {} characters, which leaves no room for random characters in a short token of length {}",
+                prefix_length, short_token_length
+            ),
+            BuilderError::InsufficientEntropy {
+                token,
+                bits,
+                minimum,
+            } => write!(
+                f,
+                "{} token has {:.1} bits of entropy, but at least {} are required",
+                token, bits, minimum
+            ),
+            BuilderError::IncompatibleOptions(reason) => write!(f, "{}", reason),
         }
     }
 }
 
 impl Error for BuilderError {}
 
+/// The size of a token, either as a length or the entropy it needs
+#[derive(Debug, Clone, Copy)]
+enum TokenSize {
+    Length(usize),
+    EntropyBits(u32),
+}
+
 pub struct ControllerBuilder<R: RngCore + Clone, D: Digest + FixedOutputReset> {
     prefix: Option<String>,
     rng: Option<R>,
     digest: PhantomData<D>,
     short_token_prefix: Option<String>,
-    short_token_length: Option<usize>,
-    long_token_length: Option<usize>,
+    short_token_size: Option<TokenSize>,
+    long_token_size: Option<TokenSize>,
+    minimum_short_token_entropy_bits: u32,
+    minimum_long_token_entropy_bits: u32,
+    environment: Option<String>,
+    formats: KeyFormats,
+    encoding: TokenEncoding,
+    length_unit: LengthUnit,
+    #[cfg(feature = "derivation")]
+    derivation: Option<DerivationKey>,
+    #[cfg(feature = "signed-keys")]
+    signer: Option<Arc<dyn KeySigner>>,
+    last_used_throttle: Option<Duration>,
 }
 
 impl<R: RngCore + Clone, D: Digest + FixedOutputReset> ControllerBuilder<R, D> {
@@ -54,13 +133,25 @@ impl<R: RngCore + Clone, D: Digest + FixedOutputReset> ControllerBuilder<R, D> {
             rng: None,
             digest: PhantomData,
             short_token_prefix: None,
-            short_token_length: None,
-            long_token_length: None,
+            short_token_size: None,
+            long_token_size: None,
+            minimum_short_token_entropy_bits: 0,
+            minimum_long_token_entropy_bits: 0,
+            environment: None,
+            formats: KeyFormats::new(),
+            encoding: TokenEncoding::default(),
+            length_unit: LengthUnit::default(),
+            #[cfg(feature = "derivation")]
+            derivation: None,
+            #[cfg(feature = "signed-keys")]
+            signer: None,
+            last_used_throttle: None,
         }
     }
 
     /// Finishes building the controller, returning Err if any necessary configs are
-    /// missing.
+    /// missing, contradict each other, or leave the tokens with less entropy than the
+    /// configured minimums.
     pub fn finalize(self) -> Result<PrefixedApiKeyController<R, D>, BuilderError> {
         if self.prefix.is_none() {
             return Err(BuilderError::MissingPrefix);
@@ -70,21 +161,149 @@ impl<R: RngCore + Clone, D: Digest + FixedOutputReset> ControllerBuilder<R, D> {
             return Err(BuilderError::MissingRng);
         }
 
-        if self.short_token_length.is_none() {
-            return Err(BuilderError::MissingShortTokenLength);
+        let literal = match &self.short_token_prefix {
+            Some(prefix) => prefix.chars().count(),
+            None => 0,
+        };
+        let short_token_length = match self.short_token_size {
+            Some(size) => self.resolve_length(size, literal),
+            None => return Err(BuilderError::MissingShortTokenLength),
+        };
+        let long_token_length = match (self.signed_long_token_length()?, self.long_token_size) {
+            (Some(length), _) => length,
+            (None, Some(size)) => self.resolve_length(size, 0),
+            (None, None) => return Err(BuilderError::MissingLongTokenLength),
+        };
+
+        if short_token_length == 0 || long_token_length == 0 {
+            return Err(BuilderError::ZeroTokenLength);
+        }
+
+        #[cfg(feature = "derivation")]
+        if self.derivation.is_some() && long_token_length > MAXIMUM_DERIVED_LENGTH {
+            return Err(BuilderError::IncompatibleOptions(
+                "derived long tokens can be at most 8160 bytes or characters long",
+            ));
         }
 
-        if self.long_token_length.is_none() {
-            return Err(BuilderError::MissingLongTokenLength);
+        if literal >= short_token_length {
+            return Err(BuilderError::ShortTokenPrefixTooLong {
+                prefix_length: literal,
+                short_token_length,
+            });
         }
 
-        Ok(PrefixedApiKeyController::new(
+        let checks = [
+            (
+                "short",
+                short_token_length,
+                literal,
+                self.minimum_short_token_entropy_bits,
+            ),
+            (
+                "long",
+                long_token_length,
+                0,
+                self.minimum_long_token_entropy_bits,
+            ),
+        ];
+        // Signed long tokens aren't random, so their strength comes from the signature
+        let checks = if self.signing() {
+            &checks[..1]
+        } else {
+            &checks[..]
+        };
+        for &(token, length, literal, minimum) in checks {
+            let bits = self
+                .encoding
+                .entropy_bits(self.length_unit, length, literal);
+            if bits < minimum as f64 {
+                return Err(BuilderError::InsufficientEntropy {
+                    token,
+                    bits,
+                    minimum,
+                });
+            }
+        }
+
+        if let Some(environment) = &self.environment {
+            if environment.is_empty()
+                || environment.contains('_')
+                || parse_version_marker(environment).is_some()
+            {
+                return Err(BuilderError::InvalidEnvironment(environment.clone()));
+            }
+        }
+
+        let mut controller = PrefixedApiKeyController::new(
             self.prefix.unwrap(),
             self.rng.unwrap(),
             self.short_token_prefix,
-            self.short_token_length.unwrap(),
-            self.long_token_length.unwrap(),
-        ))
+            short_token_length,
+            long_token_length,
+        );
+        controller.environment = self.environment;
+        controller.formats = self.formats;
+        controller.encoding = self.encoding;
+        controller.length_unit = self.length_unit;
+        controller.last_used_throttle = self.last_used_throttle;
+        #[cfg(feature = "derivation")]
+        {
+            controller.derivation = self.derivation;
+        }
+        #[cfg(feature = "signed-keys")]
+        {
+            controller.signer = self.signer;
+        }
+        Ok(controller)
+    }
+
+    /// Turns a token size into a length in the configured unit
+    fn resolve_length(&self, size: TokenSize, literal: usize) -> usize {
+        match size {
+            TokenSize::Length(length) => length,
+            TokenSize::EntropyBits(bits) => {
+                self.encoding
+                    .length_for_entropy(self.length_unit, bits, literal)
+            }
+        }
+    }
+
+    /// Whether long tokens are signed rather than random
+    #[cfg(feature = "signed-keys")]
+    fn signing(&self) -> bool {
+        self.signer.is_some()
+    }
+
+    #[cfg(not(feature = "signed-keys"))]
+    fn signing(&self) -> bool {
+        false
+    }
+
+    /// The length of signed long tokens in bytes, if keys are signed
+    #[cfg(feature = "signed-keys")]
+    fn signed_long_token_length(&self) -> Result<Option<usize>, BuilderError> {
+        let signer = match &self.signer {
+            Some(signer) => signer,
+            None => return Ok(None),
+        };
+        #[cfg(feature = "derivation")]
+        if self.derivation.is_some() {
+            return Err(BuilderError::IncompatibleOptions(
+                "long tokens can't be both derived and signed",
+            ));
+        }
+        if self.length_unit == LengthUnit::Characters {
+            return Err(BuilderError::IncompatibleOptions(
+                "signed long tokens can't have a length in characters",
+            ));
+        }
+        Ok(Some(SIGNED_HEADER_LENGTH + signer.signature_length()))
+    }
+
+    #[cfg(not(feature = "signed-keys"))]
+    fn signed_long_token_length(&self) -> Result<Option<usize>, BuilderError> {
+        Ok(None)
     }
 
     /// Helper for setting the default short and long token length based on the
@@ -116,15 +335,130 @@ impl<R: RngCore + Clone, D: Digest + FixedOutputReset> ControllerBuilder<R, D> {
         self
     }
 
-    /// The length of the short token
+    /// The length of the short token, in the configured [length_unit](ControllerBuilder::length_unit)
     pub fn short_token_length(mut self, short_token_length: usize) -> Self {
-        self.short_token_length = Some(short_token_length);
+        self.short_token_size = Some(TokenSize::Length(short_token_length));
         self
     }
 
-    /// The length of the secret long token
+    /// Sets the short token length to the shortest which has at least `bits` bits of
+    /// entropy, taking the encoding, length unit and short token prefix into account.
+    /// Replaces any length set with [short_token_length](ControllerBuilder::short_token_length).
+    pub fn short_token_entropy_bits(mut self, bits: u32) -> Self {
+        self.short_token_size = Some(TokenSize::EntropyBits(bits));
+        self
+    }
+
+    /// The length of the secret long token, in the configured [length_unit](ControllerBuilder::length_unit)
     pub fn long_token_length(mut self, long_token_length: usize) -> Self {
-        self.long_token_length = Some(long_token_length);
+        self.long_token_size = Some(TokenSize::Length(long_token_length));
+        self
+    }
+
+    /// Sets the long token length to the shortest which has at least `bits` bits of
+    /// entropy, taking the encoding and length unit into account. Replaces any length
+    /// set with [long_token_length](ControllerBuilder::long_token_length).
+    pub fn long_token_entropy_bits(mut self, bits: u32) -> Self {
+        self.long_token_size = Some(TokenSize::EntropyBits(bits));
+        self
+    }
+
+    /// The least entropy the short token may have. Short tokens are stored in plaintext
+    /// and only used to look keys up, so this only guards against collisions.
+    ///
+    /// Default: 0
+    pub fn minimum_short_token_entropy_bits(mut self, bits: u32) -> Self {
+        self.minimum_short_token_entropy_bits = bits;
+        self
+    }
+
+    /// The least entropy the secret long token may have. 128 bits, which 16 random bytes
+    /// meet, is a good minimum for new controllers.
+    ///
+    /// Default: 0, matching [PrefixedApiKeyController::new], which doesn't check entropy
+    pub fn minimum_long_token_entropy_bits(mut self, bits: u32) -> Self {
+        self.minimum_long_token_entropy_bits = bits;
+        self
+    }
+
+    /// Sets the encoding used to turn the random bytes of the short and long tokens into
+    /// characters. Parsed keys must use the same encoding.
+    ///
+    /// Default: [TokenEncoding::Base58]
+    pub fn token_encoding(mut self, encoding: TokenEncoding) -> Self {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Sets what the short and long token lengths count. With [LengthUnit::Characters]
+    /// tokens are always exactly the configured number of characters, instead of
+    /// varying with the encoding of the random bytes.
+    ///
+    /// Default: [LengthUnit::Bytes]
+    pub fn length_unit(mut self, length_unit: LengthUnit) -> Self {
+        self.length_unit = length_unit;
+        self
+    }
+
+    /// Tags generated keys with an environment, like `test` or `live`, which is added
+    /// as a segment after the prefix: `mycompany_live_<short>_<long>`. Keys from any
+    /// other environment are rejected by [parse_key](PrefixedApiKeyController::parse_key)
+    /// and [authenticate](PrefixedApiKeyController::authenticate).
+    ///
+    /// Default: None
+    pub fn environment(mut self, environment: String) -> Self {
+        self.environment = Some(environment);
+        self
+    }
+
+    /// Registers an additional [KeyFormat]. Keys in every registered format are accepted
+    /// when parsing, while new keys are generated in the newest one. The original
+    /// version 1 format is always registered.
+    pub fn key_format<F: KeyFormat + Send + Sync + 'static>(mut self, format: F) -> Self {
+        self.formats.register(format);
+        self
+    }
+
+    /// Enables updating a record's `last_used_at` timestamp in the key store whenever
+    /// it's successfully authenticated. To avoid a write on every request, the timestamp
+    /// is only updated if it's older than `throttle`.
+    ///
+    /// Default: None
+    pub fn touch_last_used(mut self, throttle: Duration) -> Self {
+        self.last_used_throttle = Some(throttle);
+        self
+    }
+}
+
+#[cfg(feature = "signed-keys")]
+impl<R: RngCore + Clone, D: Digest + FixedOutputReset> ControllerBuilder<R, D> {
+    /// Signs the short token, environment and expiry of every key into its long token,
+    /// so keys can be verified with [verify_stateless](PrefixedApiKeyController::verify_stateless)
+    /// without a database lookup. Long tokens are still hashed as usual for the
+    /// [KeyStore](crate::KeyStore) path.
+    ///
+    /// The long token length is set by the signer, so it doesn't need to be configured.
+    /// Signing can't be combined with [derive_long_tokens](ControllerBuilder::derive_long_tokens)
+    /// or [LengthUnit::Characters].
+    ///
+    /// Requires the "signed-keys" feature
+    pub fn sign_keys<S: KeySigner + 'static>(mut self, signer: S) -> Self {
+        self.signer = Some(Arc::new(signer));
+        self
+    }
+}
+
+#[cfg(feature = "derivation")]
+impl<R: RngCore + Clone, D: Digest + FixedOutputReset> ControllerBuilder<R, D> {
+    /// Derives long tokens from a master secret and the short token, instead of
+    /// generating them randomly, so lost keys can be recovered with
+    /// [derive_key](PrefixedApiKeyController::derive_key). Read the warning on
+    /// [DerivationKey] before using this: anyone with the master secret can compute
+    /// every key.
+    ///
+    /// Requires the "derivation" feature
+    pub fn derive_long_tokens(mut self, derivation: DerivationKey) -> Self {
+        self.derivation = Some(derivation);
         self
     }
 }
@@ -260,7 +594,8 @@ mod controller_builder_tests {
     use rand::rngs::OsRng;
     use sha2::Sha256;
 
-    use super::ControllerBuilder;
+    use super::{BuilderError, ControllerBuilder};
+    use crate::{LengthUnit, TokenEncoding};
 
     #[test]
     fn errors_when_no_values_set() {
@@ -335,6 +670,122 @@ mod controller_builder_tests {
             .finalize();
         assert!(controller_result.is_ok())
     }
+
+    #[test]
+    fn errors_with_invalid_environment() {
+        for environment in ["", "live_eu", "v2"] {
+            let controller_result = ControllerBuilder::<_, Sha256>::new()
+                .prefix("mycompany".to_owned())
+                .rng(OsRng)
+                .default_lengths()
+                .environment(environment.to_owned())
+                .finalize();
+            assert!(matches!(
+                controller_result,
+                Err(BuilderError::InvalidEnvironment(_))
+            ));
+        }
+    }
+
+    #[test]
+    fn sets_lengths_from_entropy() {
+        let controller = ControllerBuilder::<_, Sha256>::new()
+            .prefix("mycompany".to_owned())
+            .rng(OsRng)
+            .short_token_prefix(Some("mc".to_owned()))
+            .short_token_entropy_bits(32)
+            .long_token_entropy_bits(128)
+            .token_encoding(TokenEncoding::Hex)
+            .length_unit(LengthUnit::Characters)
+            .finalize()
+            .unwrap();
+        assert_eq!(controller.short_token_length(), 10);
+        assert_eq!(controller.long_token_length(), 32);
+        assert_eq!(controller.short_token_entropy_bits(), 32.0);
+        assert_eq!(controller.long_token_entropy_bits(), 128.0);
+
+        let controller = ControllerBuilder::<_, Sha256>::new()
+            .prefix("mycompany".to_owned())
+            .rng(OsRng)
+            .default_lengths()
+            .long_token_entropy_bits(256)
+            .finalize()
+            .unwrap();
+        assert_eq!(controller.short_token_length(), 8);
+        assert_eq!(controller.long_token_length(), 32);
+    }
+
+    #[test]
+    fn errors_with_contradictory_lengths() {
+        let builder = || {
+            ControllerBuilder::<_, Sha256>::new()
+                .prefix("mycompany".to_owned())
+                .rng(OsRng)
+                .default_lengths()
+        };
+
+        assert!(matches!(
+            builder().long_token_length(0).finalize(),
+            Err(BuilderError::ZeroTokenLength)
+        ));
+        assert!(matches!(
+            builder()
+                .short_token_prefix(Some("mycompany".to_owned()))
+                .finalize(),
+            Err(BuilderError::ShortTokenPrefixTooLong {
+                prefix_length: 9,
+                short_token_length: 8
+            })
+        ));
+    }
+
+    #[test]
+    fn errors_with_insufficient_entropy() {
+        let builder = || {
+            ControllerBuilder::<_, Sha256>::new()
+                .prefix("mycompany".to_owned())
+                .rng(OsRng)
+                .default_lengths()
+                .minimum_long_token_entropy_bits(128)
+        };
+
+        // No minimum is enforced unless one is configured
+        assert!(ControllerBuilder::<_, Sha256>::new()
+            .prefix("mycompany".to_owned())
+            .rng(OsRng)
+            .short_token_length(8)
+            .long_token_length(4)
+            .finalize()
+            .is_ok());
+        assert!(matches!(
+            builder().long_token_length(15).finalize(),
+            Err(BuilderError::InsufficientEntropy {
+                token: "long",
+                minimum: 128,
+                ..
+            })
+        ));
+        assert!(builder().long_token_length(16).finalize().is_ok());
+        assert!(builder()
+            .long_token_length(15)
+            .minimum_long_token_entropy_bits(120)
+            .finalize()
+            .is_ok());
+
+        // The short token prefix takes up characters which would otherwise be random
+        let result = builder()
+            .short_token_prefix(Some("mcmcmc".to_owned()))
+            .minimum_short_token_entropy_bits(32)
+            .finalize();
+        match result {
+            Err(BuilderError::InsufficientEntropy {
+                token: "short",
+                bits,
+                minimum: 32,
+            }) => assert!(bits < 12.0),
+            other => panic!("expected InsufficientEntropy, got {:?}", other.map(|_| ())),
+        }
+    }
 }
 
 #[cfg(feature = "sha2")]
@@ -343,10 +794,10 @@ mod controller_builder_sha2_tests {
     use digest::{Digest, FixedOutputReset};
 
     use crate::{
-        rand::rngs::OsRng, rand::rngs::StdRng, rand::rngs::ThreadRng, rand::RngCore,
-        rand::SeedableRng, BuilderError, PakControllerOsSha224, PakControllerOsSha256,
-        PakControllerOsSha384, PakControllerOsSha512, PakControllerOsSha512_224,
-        PakControllerOsSha512_256, PakControllerStdSha256, PakControllerThreadSha256,
+        rand::rngs::OsRng, rand::RngCore, BuilderError, PakControllerOsSha224,
+        PakControllerOsSha256, PakControllerOsSha384, PakControllerOsSha512,
+        PakControllerOsSha512_224, PakControllerOsSha512_256, PakControllerStdSha256,
+        PakControllerThreadSha256,
     };
 
     use super::{ControllerBuilder, PrefixedApiKeyController};
@@ -356,7 +807,8 @@ mod controller_builder_sha2_tests {
         R: RngCore + Clone,
         D: Digest + FixedOutputReset,
     {
-        let (pak, hash) = controller.generate_key_and_hash();
+        let pak = controller.generate_key();
+        let hash = controller.long_token_hashed(&pak);
         controller.check_hash(&pak, &hash)
     }
 
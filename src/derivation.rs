@@ -0,0 +1,257 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+
+use digest::{Digest, FixedOutputReset};
+use hkdf::Hkdf;
+use rand::RngCore;
+use sha2::Sha256;
+
+use crate::controller::PrefixedApiKeyController;
+use crate::prefixed_api_key::PrefixedApiKey;
+use crate::token_encoding::{LengthUnit, TokenEncoding};
+
+/// The shortest master secret accepted, matching the output of SHA-256
+const MINIMUM_MASTER_LENGTH: usize = 32;
+
+/// The longest long token which can be derived. HKDF-SHA256 expands at most 255 blocks
+/// of 32 bytes, and every block of the output stream is at most the token's length.
+pub(crate) const MAXIMUM_DERIVED_LENGTH: usize = 255 * 32;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum DerivationError {
+    /// The master secret was shorter than 32 bytes
+    MasterTooShort(usize),
+    /// The controller wasn't configured with a [DerivationKey]
+    NotConfigured,
+}
+
+impl fmt::Display for DerivationError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            DerivationError::MasterTooShort(length) => write!(
+                f,
+                "master secret must be at least {} bytes, got {}",
+                MINIMUM_MASTER_LENGTH, length
+            ),
+            DerivationError::NotConfigured => {
+                write!(f, "controller isn't configured to derive long tokens")
+            }
+        }
+    }
+}
+
+impl Error for DerivationError {}
+
+/// A master secret which long tokens are derived from, using HKDF-SHA256 with the short
+/// token as the salt: `long_token = HKDF(master, short_token, info)`. Short tokens are
+/// still random, so every key is different, but a lost key can be re-derived from the
+/// master secret and its short token with
+/// [derive_key](PrefixedApiKeyController::derive_key).
+///
+/// <p style="background:rgba(255,181,77,0.16);padding:0.75em;">
+/// <strong>Warning:</strong>
+/// This changes the threat model. Short tokens are stored in plaintext and shown to
+/// customers, so anyone holding the master secret can compute every key the controller
+/// has ever issued, without access to the key store. A leaked master secret must be
+/// treated as a leak of every key, and the master secret needs the same protection as
+/// a signing key: keep it in a KMS or HSM, never alongside the key store. Random long
+/// tokens, the default, have no such single point of failure.
+/// </p>
+///
+/// Hashes of derived long tokens are still stored and checked as usual, so changing the
+/// master secret doesn't stop existing keys from authenticating, it only stops them
+/// from being re-derived.
+///
+/// Requires the "derivation" feature
+#[derive(Clone)]
+pub struct DerivationKey {
+    master: Arc<[u8]>,
+    info: Vec<u8>,
+}
+
+impl DerivationKey {
+    /// Creates the key from a master secret of at least 32 random bytes
+    pub fn new(master: &[u8]) -> Result<DerivationKey, DerivationError> {
+        if master.len() < MINIMUM_MASTER_LENGTH {
+            return Err(DerivationError::MasterTooShort(master.len()));
+        }
+        Ok(DerivationKey {
+            master: master.into(),
+            info: Vec::new(),
+        })
+    }
+
+    /// Sets the HKDF info, which binds derived tokens to a context. Controllers sharing
+    /// a master secret should each use a different info, so a short token issued by
+    /// both doesn't derive the same long token.
+    ///
+    /// Default: empty
+    pub fn with_info(mut self, info: Vec<u8>) -> DerivationKey {
+        self.info = info;
+        self
+    }
+
+    /// Derives the long token for the short token, in the given encoding and length
+    pub(crate) fn derive_long_token(
+        &self,
+        short_token: &str,
+        encoding: TokenEncoding,
+        length_unit: LengthUnit,
+        length: usize,
+    ) -> String {
+        // Each short token gets its own output stream, which is read in blocks numbered
+        // by a counter appended to the info
+        let hkdf = Hkdf::<Sha256>::new(Some(short_token.as_bytes()), &self.master);
+        let mut counter = 0u32;
+        let mut fill = |bytes: &mut [u8]| {
+            hkdf.expand_multi_info(&[&self.info, &counter.to_be_bytes()], bytes)
+                .expect("finalize rejects lengths above the HKDF output limit");
+            counter += 1;
+            Ok::<_, std::convert::Infallible>(())
+        };
+
+        let token = match length_unit {
+            LengthUnit::Bytes => {
+                let mut bytes = vec![0u8; length];
+                fill(&mut bytes).map(|_| encoding.encode(&bytes))
+            }
+            LengthUnit::Characters => encoding.sample(length, fill),
+        };
+        match token {
+            Ok(token) => token,
+            Err(never) => match never {},
+        }
+    }
+}
+
+/// A custom implementation of Debug that hides the master secret
+impl fmt::Debug for DerivationKey {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("DerivationKey")
+            .field("master", &"***")
+            .field("info", &String::from_utf8_lossy(&self.info))
+            .finish()
+    }
+}
+
+impl<R: RngCore + Clone, D: Digest + FixedOutputReset> PrefixedApiKeyController<R, D> {
+    /// Re-derives the key with the given short token from the controller's
+    /// [DerivationKey], for recovering a customer's key. The key is only correct if the
+    /// short token was issued by this controller with the same master secret and info.
+    ///
+    /// Requires the "derivation" feature
+    pub fn derive_key(&self, short_token: &str) -> Result<PrefixedApiKey, DerivationError> {
+        match self.derived_long_token(short_token) {
+            Some(long_token) => Ok(self.new_key(short_token.to_owned(), long_token)),
+            None => Err(DerivationError::NotConfigured),
+        }
+    }
+}
+
+#[cfg(test)]
+mod derivation_tests {
+    use rand::rngs::OsRng;
+    use sha2::Sha256;
+
+    use super::{DerivationError, DerivationKey};
+    use crate::{BuilderError, LengthUnit, PrefixedApiKeyController, TokenEncoding};
+
+    const MASTER: [u8; 32] = [7; 32];
+
+    fn derived_controller(key: DerivationKey) -> PrefixedApiKeyController<OsRng, Sha256> {
+        PrefixedApiKeyController::configure()
+            .prefix("mycompany".to_owned())
+            .rng(OsRng)
+            .default_lengths()
+            .derive_long_tokens(key)
+            .finalize()
+            .unwrap()
+    }
+
+    #[test]
+    fn rejects_short_master_secrets() {
+        assert_eq!(
+            DerivationKey::new(&[0; 16]).unwrap_err(),
+            DerivationError::MasterTooShort(16)
+        );
+        let debug = format!("{:?}", DerivationKey::new(&MASTER).unwrap());
+        assert!(!debug.contains('7'));
+    }
+
+    #[test]
+    fn long_tokens_can_be_rederived() {
+        let controller = derived_controller(DerivationKey::new(&MASTER).unwrap());
+        let pak = controller.generate_key();
+        let hash = controller.long_token_hashed(&pak);
+        let other = controller.generate_key();
+        assert_ne!(pak.short_token(), other.short_token());
+        assert_ne!(pak.long_token(), other.long_token());
+
+        // A separate controller with the same master secret recovers the key
+        let recovery = derived_controller(DerivationKey::new(&MASTER).unwrap());
+        let recovered = recovery.derive_key(pak.short_token()).unwrap();
+        assert_eq!(recovered.to_string(), pak.to_string());
+        assert!(recovery.check_hash(&recovered, &hash));
+
+        let other_info = derived_controller(
+            DerivationKey::new(&MASTER)
+                .unwrap()
+                .with_info(b"eu".to_vec()),
+        );
+        let rederived = other_info.derive_key(pak.short_token()).unwrap();
+        assert_ne!(rederived.long_token(), pak.long_token());
+
+        let random =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        assert_eq!(
+            random.derive_key(pak.short_token()).unwrap_err(),
+            DerivationError::NotConfigured
+        );
+    }
+
+    #[test]
+    fn derives_configured_lengths() {
+        let controller = PrefixedApiKeyController::<_, Sha256>::configure()
+            .prefix("mycompany".to_owned())
+            .rng(OsRng)
+            .short_token_length(10)
+            .long_token_length(40)
+            .token_encoding(TokenEncoding::Base62)
+            .length_unit(LengthUnit::Characters)
+            .derive_long_tokens(DerivationKey::new(&MASTER).unwrap())
+            .finalize()
+            .unwrap();
+
+        let pak = controller.try_generate_key().unwrap();
+        assert_eq!(pak.long_token().len(), 40);
+        assert_eq!(
+            controller
+                .derive_key(pak.short_token())
+                .unwrap()
+                .long_token(),
+            pak.long_token()
+        );
+    }
+
+    #[test]
+    fn rejects_lengths_beyond_the_hkdf_limit() {
+        for length_unit in [LengthUnit::Bytes, LengthUnit::Characters] {
+            let builder = || {
+                PrefixedApiKeyController::<_, Sha256>::configure()
+                    .prefix("mycompany".to_owned())
+                    .rng(OsRng)
+                    .short_token_length(8)
+                    .length_unit(length_unit)
+                    .derive_long_tokens(DerivationKey::new(&MASTER).unwrap())
+            };
+
+            assert!(matches!(
+                builder().long_token_length(8161).finalize(),
+                Err(BuilderError::IncompatibleOptions(_))
+            ));
+            let controller = builder().long_token_length(8160).finalize().unwrap();
+            assert!(controller.derive_key("abc").is_ok());
+        }
+    }
+}
@@ -0,0 +1,513 @@
+use std::error::Error;
+use std::fmt;
+
+#[cfg(feature = "basic-auth")]
+use base64::engine::general_purpose::STANDARD;
+#[cfg(feature = "basic-auth")]
+use base64::Engine;
+
+use crate::prefixed_api_key::{PrefixedApiKey, PrefixedApiKeyError};
+
+/// A place in a request where clients may send their key.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum CredentialSource {
+    /// The `Authorization` header, using the `Bearer` scheme
+    Bearer,
+    /// The `Authorization` header, using the `Basic` scheme. The key is taken from
+    /// the password, or from the username if the password is empty.
+    ///
+    /// Decoding it requires the "basic-auth" feature. Without it, this source never
+    /// provides a key.
+    Basic,
+    /// The entire value of a header, like `X-API-Key`
+    Header(String),
+    /// A query string parameter, like `?api_key=`
+    Query(String),
+    /// A cookie
+    Cookie(String),
+}
+
+impl fmt::Display for CredentialSource {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            CredentialSource::Bearer => write!(f, "bearer authorization"),
+            CredentialSource::Basic => write!(f, "basic authorization"),
+            CredentialSource::Header(name) => write!(f, "header {:?}", name),
+            CredentialSource::Query(name) => write!(f, "query parameter {:?}", name),
+            CredentialSource::Cookie(name) => write!(f, "cookie {:?}", name),
+        }
+    }
+}
+
+#[derive(Debug, PartialEq, Eq)]
+pub enum ExtractError {
+    /// None of the sources held a key
+    MissingCredentials,
+    /// Different keys were provided by different sources, or by the same source twice
+    ConflictingCredentials(CredentialSource, CredentialSource),
+    /// The `Authorization: Basic` header wasn't valid base64 encoded `username:password`
+    InvalidBasicAuth,
+    /// The key couldn't be parsed
+    Malformed(PrefixedApiKeyError),
+}
+
+impl fmt::Display for ExtractError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ExtractError::MissingCredentials => write!(f, "no api key was provided"),
+            ExtractError::ConflictingCredentials(first, second) => write!(
+                f,
+                "conflicting api keys were provided by {} and {}",
+                first, second
+            ),
+            ExtractError::InvalidBasicAuth => write!(f, "invalid basic authorization header"),
+            ExtractError::Malformed(err) => write!(f, "malformed api key: {}", err),
+        }
+    }
+}
+
+impl Error for ExtractError {}
+
+/// The parts of a request that keys can be extracted from. This is implemented for
+/// [SimpleRequest], for `http` requests when the "tower" feature is enabled, for
+/// actix-web's `HttpRequest` with the "actix" feature and for tonic's `MetadataMap` with
+/// the "tonic" feature, but is easy to implement for any web framework's request type.
+pub trait CredentialRequest {
+    /// All values of the header with the given name, which is matched case insensitively
+    fn header_values(&self, name: &str) -> Vec<&str>;
+
+    /// The raw query string, without the leading `?`
+    fn query_string(&self) -> Option<&str>;
+}
+
+/// A framework independent [CredentialRequest], built up from headers and a query string.
+#[derive(Debug, Clone, Default)]
+pub struct SimpleRequest {
+    headers: Vec<(String, String)>,
+    query: Option<String>,
+}
+
+impl SimpleRequest {
+    pub fn new() -> SimpleRequest {
+        SimpleRequest::default()
+    }
+
+    /// Adds a header to the request
+    pub fn header(mut self, name: &str, value: &str) -> Self {
+        self.headers.push((name.to_owned(), value.to_owned()));
+        self
+    }
+
+    /// Sets the raw query string of the request, without the leading `?`
+    pub fn query(mut self, query: &str) -> Self {
+        self.query = Some(query.to_owned());
+        self
+    }
+}
+
+impl CredentialRequest for SimpleRequest {
+    fn header_values(&self, name: &str) -> Vec<&str> {
+        self.headers
+            .iter()
+            .filter(|(header, _)| header.eq_ignore_ascii_case(name))
+            .map(|(_, value)| value.as_str())
+            .collect()
+    }
+
+    fn query_string(&self) -> Option<&str> {
+        self.query.as_deref()
+    }
+}
+
+#[cfg(feature = "tower")]
+impl CredentialRequest for http::request::Parts {
+    fn header_values(&self, name: &str) -> Vec<&str> {
+        self.headers
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect()
+    }
+
+    fn query_string(&self) -> Option<&str> {
+        self.uri.query()
+    }
+}
+
+#[cfg(feature = "tower")]
+impl<B> CredentialRequest for http::Request<B> {
+    fn header_values(&self, name: &str) -> Vec<&str> {
+        self.headers()
+            .get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect()
+    }
+
+    fn query_string(&self) -> Option<&str> {
+        self.uri().query()
+    }
+}
+
+#[cfg(feature = "actix")]
+impl CredentialRequest for actix_web::HttpRequest {
+    fn header_values(&self, name: &str) -> Vec<&str> {
+        self.headers()
+            .get_all(name)
+            .filter_map(|value| value.to_str().ok())
+            .collect()
+    }
+
+    fn query_string(&self) -> Option<&str> {
+        Some(actix_web::HttpRequest::query_string(self))
+    }
+}
+
+/// gRPC has no query strings, so keys can only be found in the metadata
+#[cfg(feature = "tonic")]
+impl CredentialRequest for tonic::metadata::MetadataMap {
+    fn header_values(&self, name: &str) -> Vec<&str> {
+        self.get_all(name)
+            .iter()
+            .filter_map(|value| value.to_str().ok())
+            .collect()
+    }
+
+    fn query_string(&self) -> Option<&str> {
+        None
+    }
+}
+
+/// Extracts a [PrefixedApiKey] from a request by checking an ordered list of
+/// [CredentialSource]s.
+///
+/// The key from the first source that has one is returned, but every source is still
+/// checked so that requests carrying different keys in different places are rejected
+/// with [ConflictingCredentials](ExtractError::ConflictingCredentials), rather than
+/// having one of them silently win.
+#[derive(Debug, Clone)]
+pub struct CredentialExtractor {
+    sources: Vec<CredentialSource>,
+}
+
+impl CredentialExtractor {
+    /// Creates an extractor which checks the given sources in order
+    pub fn new(sources: Vec<CredentialSource>) -> CredentialExtractor {
+        CredentialExtractor { sources }
+    }
+
+    /// Creates an extractor which only checks the `Authorization: Bearer` header
+    pub fn bearer() -> CredentialExtractor {
+        CredentialExtractor::new(vec![CredentialSource::Bearer])
+    }
+
+    /// Adds another source, to be checked after the existing ones
+    pub fn source(mut self, source: CredentialSource) -> Self {
+        self.sources.push(source);
+        self
+    }
+
+    pub fn sources(&self) -> &[CredentialSource] {
+        &self.sources
+    }
+
+    /// Extracts the raw key string from the request, without parsing it
+    pub fn extract_str<Q: CredentialRequest>(&self, request: &Q) -> Result<String, ExtractError> {
+        let mut found: Option<(&CredentialSource, String)> = None;
+
+        for source in &self.sources {
+            for value in source_values(source, request)? {
+                match &found {
+                    None => found = Some((source, value)),
+                    Some((_, existing)) if *existing == value => {}
+                    Some((first, _)) => {
+                        return Err(ExtractError::ConflictingCredentials(
+                            (*first).clone(),
+                            source.clone(),
+                        ))
+                    }
+                }
+            }
+        }
+
+        found
+            .map(|(_, value)| value)
+            .ok_or(ExtractError::MissingCredentials)
+    }
+
+    /// Extracts and parses the key from the request
+    pub fn extract<Q: CredentialRequest>(
+        &self,
+        request: &Q,
+    ) -> Result<PrefixedApiKey, ExtractError> {
+        let key = self.extract_str(request)?;
+        PrefixedApiKey::from_string(&key).map_err(ExtractError::Malformed)
+    }
+}
+
+impl Default for CredentialExtractor {
+    /// Checks the `Authorization: Bearer` header followed by the `X-API-Key` header
+    fn default() -> Self {
+        CredentialExtractor::new(vec![
+            CredentialSource::Bearer,
+            CredentialSource::Header("x-api-key".to_owned()),
+        ])
+    }
+}
+
+/// Gets every non-empty credential the source provides in the request
+fn source_values<Q: CredentialRequest>(
+    source: &CredentialSource,
+    request: &Q,
+) -> Result<Vec<String>, ExtractError> {
+    let mut values = Vec::new();
+
+    match source {
+        CredentialSource::Bearer => {
+            for value in request.header_values("authorization") {
+                if let Some(token) = strip_scheme(value, "bearer") {
+                    values.push(token.to_owned());
+                }
+            }
+        }
+        CredentialSource::Basic => values.extend(basic_values(request)?),
+        CredentialSource::Header(name) => {
+            for value in request.header_values(name) {
+                values.push(value.trim().to_owned());
+            }
+        }
+        CredentialSource::Query(name) => {
+            for pair in request.query_string().unwrap_or("").split('&') {
+                let (key, value) = pair.split_once('=').unwrap_or((pair, ""));
+                if percent_decode(key) == *name {
+                    values.push(percent_decode(value));
+                }
+            }
+        }
+        CredentialSource::Cookie(name) => {
+            for header in request.header_values("cookie") {
+                for cookie in header.split(';') {
+                    if let Some((key, value)) = cookie.trim().split_once('=') {
+                        if key == name {
+                            values.push(value.trim_matches('"').to_owned());
+                        }
+                    }
+                }
+            }
+        }
+    }
+
+    values.retain(|value| !value.is_empty());
+    Ok(values)
+}
+
+fn strip_scheme<'a>(value: &'a str, scheme: &str) -> Option<&'a str> {
+    let (found, credentials) = value.trim().split_once(' ')?;
+    if found.eq_ignore_ascii_case(scheme) {
+        Some(credentials.trim())
+    } else {
+        None
+    }
+}
+
+/// Gets the key from every `Authorization: Basic` header
+#[cfg(feature = "basic-auth")]
+fn basic_values<Q: CredentialRequest>(request: &Q) -> Result<Vec<String>, ExtractError> {
+    request
+        .header_values("authorization")
+        .into_iter()
+        .filter_map(|value| strip_scheme(value, "basic"))
+        .map(decode_basic)
+        .collect()
+}
+
+/// Basic authorization can't be decoded without the "basic-auth" feature, so it never
+/// provides a key
+#[cfg(not(feature = "basic-auth"))]
+fn basic_values<Q: CredentialRequest>(_request: &Q) -> Result<Vec<String>, ExtractError> {
+    Ok(Vec::new())
+}
+
+#[cfg(feature = "basic-auth")]
+fn decode_basic(encoded: &str) -> Result<String, ExtractError> {
+    let decoded = STANDARD
+        .decode(encoded)
+        .ok()
+        .and_then(|bytes| String::from_utf8(bytes).ok())
+        .ok_or(ExtractError::InvalidBasicAuth)?;
+    let (username, password) = decoded
+        .split_once(':')
+        .ok_or(ExtractError::InvalidBasicAuth)?;
+
+    if password.is_empty() {
+        Ok(username.to_owned())
+    } else {
+        Ok(password.to_owned())
+    }
+}
+
+fn percent_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' if i + 2 < bytes.len() => {
+                let high = (bytes[i + 1] as char).to_digit(16);
+                let low = (bytes[i + 2] as char).to_digit(16);
+                match (high, low) {
+                    (Some(high), Some(low)) => {
+                        decoded.push((high * 16 + low) as u8);
+                        i += 2;
+                    }
+                    _ => decoded.push(b'%'),
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod extract_tests {
+    use super::{CredentialExtractor, CredentialSource, ExtractError, SimpleRequest};
+
+    const KEY: &str = "mycompany_CEUsS4psCmc_BddpcwWyCT3EkDjHSSTRaSK1dxtuQgbjb";
+    const OTHER_KEY: &str = "mycompany_BRTRKFsL_51FwqftsmMDHHbJAMEXXHCgG";
+
+    fn all_sources() -> CredentialExtractor {
+        CredentialExtractor::new(vec![
+            CredentialSource::Bearer,
+            CredentialSource::Header("x-api-key".to_owned()),
+            CredentialSource::Basic,
+            CredentialSource::Query("api_key".to_owned()),
+            CredentialSource::Cookie("pak".to_owned()),
+        ])
+    }
+
+    #[test]
+    fn extracts_from_each_source() {
+        let extractor = all_sources();
+        let requests = vec![
+            SimpleRequest::new().header("Authorization", &format!("Bearer {}", KEY)),
+            SimpleRequest::new().header("X-Api-Key", KEY),
+            SimpleRequest::new().query(&format!("page=2&api_key={}", KEY)),
+            SimpleRequest::new().header("Cookie", &format!("theme=dark; pak={}", KEY)),
+        ];
+
+        for request in requests {
+            assert_eq!(extractor.extract(&request).unwrap().to_string(), KEY);
+        }
+    }
+
+    #[test]
+    fn missing_credentials() {
+        let request = SimpleRequest::new()
+            .header("Authorization", "Bearer ")
+            .query("api_key=");
+        assert_eq!(
+            all_sources().extract(&request).unwrap_err(),
+            ExtractError::MissingCredentials
+        );
+    }
+
+    #[test]
+    fn same_key_in_multiple_places_is_fine() {
+        let request = SimpleRequest::new()
+            .header("Authorization", &format!("Bearer {}", KEY))
+            .query(&format!("api_key={}", KEY));
+        assert_eq!(all_sources().extract(&request).unwrap().to_string(), KEY);
+    }
+
+    #[test]
+    fn conflicting_credentials() {
+        let request = SimpleRequest::new()
+            .header("X-API-Key", OTHER_KEY)
+            .query(&format!("api_key={}", KEY));
+        assert_eq!(
+            all_sources().extract(&request).unwrap_err(),
+            ExtractError::ConflictingCredentials(
+                CredentialSource::Header("x-api-key".to_owned()),
+                CredentialSource::Query("api_key".to_owned()),
+            )
+        );
+
+        let request = SimpleRequest::new()
+            .header("X-API-Key", OTHER_KEY)
+            .header("X-API-Key", KEY);
+        assert!(matches!(
+            all_sources().extract(&request),
+            Err(ExtractError::ConflictingCredentials(_, _))
+        ));
+    }
+
+    #[test]
+    fn unconfigured_sources_are_ignored() {
+        let request = SimpleRequest::new()
+            .header("Authorization", &format!("Bearer {}", KEY))
+            .query(&format!("api_key={}", OTHER_KEY));
+        let extractor = CredentialExtractor::default();
+        assert_eq!(extractor.extract(&request).unwrap().to_string(), KEY);
+    }
+
+    #[cfg(feature = "basic-auth")]
+    #[test]
+    fn extracts_basic_credentials() {
+        let extractor = all_sources();
+        let basic_user = base64_encode(&format!("{}:", KEY));
+        let basic_pass = base64_encode(&format!("api:{}", KEY));
+        let requests = vec![
+            SimpleRequest::new().header("authorization", &format!("Basic {}", basic_user)),
+            SimpleRequest::new().header("authorization", &format!("basic {}", basic_pass)),
+        ];
+
+        for request in requests {
+            assert_eq!(extractor.extract(&request).unwrap().to_string(), KEY);
+        }
+
+        let request = SimpleRequest::new().header("Authorization", "Basic not-base64!");
+        assert_eq!(
+            extractor.extract(&request).unwrap_err(),
+            ExtractError::InvalidBasicAuth
+        );
+    }
+
+    #[cfg(not(feature = "basic-auth"))]
+    #[test]
+    fn basic_credentials_need_the_feature() {
+        let request = SimpleRequest::new().header("Authorization", "Basic not-base64!");
+        assert_eq!(
+            all_sources().extract(&request).unwrap_err(),
+            ExtractError::MissingCredentials
+        );
+    }
+
+    #[test]
+    fn malformed_credentials() {
+        let request = SimpleRequest::new().query("api_key=a_b");
+        assert!(matches!(
+            all_sources().extract(&request),
+            Err(ExtractError::Malformed(_))
+        ));
+    }
+
+    #[test]
+    fn query_values_are_percent_decoded() {
+        let request = SimpleRequest::new().query("api%5Fkey=mycompany%5Fabc_def");
+        assert_eq!(
+            all_sources().extract_str(&request).unwrap(),
+            "mycompany_abc_def"
+        );
+    }
+
+    #[cfg(feature = "basic-auth")]
+    fn base64_encode(value: &str) -> String {
+        use base64::Engine;
+        base64::engine::general_purpose::STANDARD.encode(value)
+    }
+}
@@ -0,0 +1,290 @@
+use std::collections::BTreeMap;
+use std::fmt;
+use std::sync::Arc;
+
+use crate::prefixed_api_key::{PrefixedApiKey, PrefixedApiKeyError};
+
+type SharedFormat = Arc<dyn KeyFormat + Send + Sync>;
+
+/// A version of the string form of keys. Every version starts with the key's prefix,
+/// followed by a `v<version>` marker segment for every version except 1, so keys
+/// issued before a format change keep working:
+///
+/// ```text
+/// mycompany_<short>_<long>          version 1
+/// mycompany_v1_<short>_<long>       version 1, with its optional marker
+/// mycompany_v2_<anything>           version 2
+/// ```
+///
+/// Formats are only responsible for what follows the marker. Controllers never issue
+/// short tokens or environments which look like a marker, so version 1 keys are never
+/// misread as another version.
+pub trait KeyFormat {
+    fn version(&self) -> u32;
+
+    /// Formats the parts of the key which follow its prefix and version marker
+    fn format_body(&self, pak: &PrefixedApiKey) -> String;
+
+    /// Parses the parts of the key which follow its prefix and version marker
+    fn parse_body(&self, prefix: &str, body: &str) -> Result<PrefixedApiKey, PrefixedApiKeyError>;
+
+    /// The character between the short and long tokens, for formats whose body is laid
+    /// out like version 1's: `[<environment>_]<short><separator><long>`. This lets a
+    /// [KeyScanner](crate::KeyScanner) find keys in the format. Formats laid out any
+    /// other way return `None`, the default, and aren't scanned for.
+    fn token_separator(&self) -> Option<char> {
+        None
+    }
+}
+
+/// The original `<prefix>_[<environment>_]<short>_<long>` format, which is what
+/// [PrefixedApiKey::from_string_with_environment] and `to_string` use.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct KeyFormatV1;
+
+impl KeyFormat for KeyFormatV1 {
+    fn version(&self) -> u32 {
+        1
+    }
+
+    fn format_body(&self, pak: &PrefixedApiKey) -> String {
+        match pak.environment() {
+            Some(environment) => {
+                format!("{}_{}_{}", environment, pak.short_token(), pak.long_token())
+            }
+            None => format!("{}_{}", pak.short_token(), pak.long_token()),
+        }
+    }
+
+    fn parse_body(&self, prefix: &str, body: &str) -> Result<PrefixedApiKey, PrefixedApiKeyError> {
+        PrefixedApiKey::from_string_with_environment(&format!("{}_{}", prefix, body))
+    }
+
+    fn token_separator(&self) -> Option<char> {
+        Some('_')
+    }
+}
+
+/// Formats the key, adding the version marker when the format needs one
+pub(crate) fn format_key(format: &dyn KeyFormat, pak: &PrefixedApiKey) -> String {
+    match format.version() {
+        1 => format!("{}_{}", pak.prefix(), format.format_body(pak)),
+        version => format!("{}_v{}_{}", pak.prefix(), version, format.format_body(pak)),
+    }
+}
+
+/// Gets the version from a `v<version>` marker segment
+pub(crate) fn parse_version_marker(segment: &str) -> Option<u32> {
+    let digits = segment.strip_prefix('v')?;
+    if digits.is_empty() || !digits.bytes().all(|byte| byte.is_ascii_digit()) {
+        return None;
+    }
+    digits.parse().ok()
+}
+
+/// The key formats a controller accepts. Parsing accepts every registered version,
+/// while new keys are generated using the newest one. Version 1 is always registered.
+#[derive(Clone)]
+pub struct KeyFormats {
+    formats: BTreeMap<u32, SharedFormat>,
+}
+
+impl KeyFormats {
+    pub fn new() -> KeyFormats {
+        let mut formats: BTreeMap<u32, SharedFormat> = BTreeMap::new();
+        formats.insert(1, Arc::new(KeyFormatV1));
+        KeyFormats { formats }
+    }
+
+    /// Adds the format, replacing any format already registered for its version
+    pub fn register<F: KeyFormat + Send + Sync + 'static>(&mut self, format: F) {
+        self.formats.insert(format.version(), Arc::new(format));
+    }
+
+    /// The registered versions, from oldest to newest
+    pub fn versions(&self) -> impl Iterator<Item = u32> + '_ {
+        self.formats.keys().copied()
+    }
+
+    /// The registered formats, from oldest to newest
+    pub(crate) fn iter(&self) -> impl DoubleEndedIterator<Item = &SharedFormat> {
+        self.formats.values()
+    }
+
+    /// The newest registered format, used for new keys
+    pub(crate) fn newest(&self) -> &SharedFormat {
+        // Version 1 can't be removed, so there's always a newest format
+        self.formats.values().next_back().unwrap()
+    }
+
+    /// Formats the key using the newest registered format
+    pub fn format(&self, pak: &PrefixedApiKey) -> String {
+        format_key(self.newest().as_ref(), pak)
+    }
+
+    /// Parses the key using the format for its version marker, or version 1 when it
+    /// has none. Keys in versions which aren't registered are rejected with
+    /// [UnsupportedVersion](PrefixedApiKeyError::UnsupportedVersion).
+    pub fn parse(&self, key: &str) -> Result<PrefixedApiKey, PrefixedApiKeyError> {
+        let (prefix, rest) = key
+            .split_once('_')
+            .ok_or(PrefixedApiKeyError::WrongNumberOfParts(1))?;
+
+        let (version, body) = match rest.split_once('_') {
+            Some((segment, body)) => match parse_version_marker(segment) {
+                Some(version) => (version, body),
+                None => (1, rest),
+            },
+            None => (1, rest),
+        };
+
+        let format = self
+            .formats
+            .get(&version)
+            .ok_or(PrefixedApiKeyError::UnsupportedVersion(version))?;
+        let pak = format.parse_body(prefix, body)?;
+        Ok(match version {
+            1 => pak,
+            _ => pak.with_format(format.clone()),
+        })
+    }
+}
+
+impl Default for KeyFormats {
+    fn default() -> Self {
+        KeyFormats::new()
+    }
+}
+
+impl fmt::Debug for KeyFormats {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("KeyFormats")
+            .field("versions", &self.formats.keys().collect::<Vec<_>>())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod key_format_tests {
+    use rand::rngs::OsRng;
+    use sha2::Sha256;
+
+    use super::{KeyFormat, KeyFormats};
+    use crate::{PrefixedApiKey, PrefixedApiKeyController, PrefixedApiKeyError};
+
+    /// Joins the tokens with a `.`, so version 1 parsers can't read it
+    struct DottedFormat;
+
+    impl KeyFormat for DottedFormat {
+        fn version(&self) -> u32 {
+            2
+        }
+
+        fn format_body(&self, pak: &PrefixedApiKey) -> String {
+            format!("{}.{}", pak.short_token(), pak.long_token())
+        }
+
+        fn parse_body(
+            &self,
+            prefix: &str,
+            body: &str,
+        ) -> Result<PrefixedApiKey, PrefixedApiKeyError> {
+            let (short, long) = body
+                .split_once('.')
+                .ok_or(PrefixedApiKeyError::WrongNumberOfParts(2))?;
+            Ok(PrefixedApiKey::new(
+                prefix.to_owned(),
+                short.to_owned(),
+                long.to_owned(),
+            ))
+        }
+    }
+
+    #[test]
+    fn v1_matches_original_format() {
+        let formats = KeyFormats::new();
+        for key in ["mycompany_abc_def", "mycompany_live_abc_def"] {
+            let pak = formats.parse(key).unwrap();
+            assert_eq!(pak.version(), 1);
+            assert_eq!(formats.format(&pak), key);
+            assert_eq!(pak.to_string(), key);
+        }
+
+        let pak = formats.parse("mycompany_v1_abc_def").unwrap();
+        assert_eq!(pak.short_token(), "abc");
+        assert_eq!(pak.to_string(), "mycompany_abc_def");
+
+        assert_eq!(
+            formats.parse("mycompany_v2_abc.def").unwrap_err(),
+            PrefixedApiKeyError::UnsupportedVersion(2)
+        );
+        assert_eq!(
+            formats.parse("mycompany").unwrap_err(),
+            PrefixedApiKeyError::WrongNumberOfParts(1)
+        );
+    }
+
+    #[test]
+    fn generates_newest_and_parses_all() {
+        let old =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        let new = PrefixedApiKeyController::<_, Sha256>::configure()
+            .prefix("mycompany".to_owned())
+            .rng(OsRng)
+            .default_lengths()
+            .key_format(DottedFormat)
+            .finalize()
+            .unwrap();
+        assert_eq!(new.key_formats().versions().collect::<Vec<_>>(), [1, 2]);
+
+        let pak = new.generate_key();
+        let hash = new.long_token_hashed(&pak);
+        let key = pak.to_string();
+        assert_eq!(pak.version(), 2);
+        assert_eq!(
+            key,
+            format!("mycompany_v2_{}.{}", pak.short_token(), pak.long_token())
+        );
+
+        let parsed = new.parse_key(&key).unwrap();
+        assert_eq!(parsed.version(), 2);
+        assert_eq!(parsed.to_string(), key);
+        assert!(new.check_hash(&parsed, &hash));
+
+        let legacy = old.generate_key().to_string();
+        let parsed = new.parse_key(&legacy).unwrap();
+        assert_eq!(parsed.version(), 1);
+        assert_eq!(parsed.to_string(), legacy);
+
+        assert_eq!(
+            old.parse_key(&key).unwrap_err(),
+            PrefixedApiKeyError::UnsupportedVersion(2)
+        );
+    }
+    #[cfg(feature = "test-util")]
+    #[test]
+    fn short_tokens_never_look_like_version_markers() {
+        use crate::{FixedRng, LengthUnit, TokenEncoding};
+
+        // 57 and 3 are "v" and "3" in base62, so the first short token would be "v3"
+        let controller = PrefixedApiKeyController::<_, Sha256>::configure()
+            .prefix("mycompany".to_owned())
+            .rng(FixedRng::new(vec![57, 3, 10, 20, 30, 40, 50]))
+            .token_encoding(TokenEncoding::Base62)
+            .length_unit(LengthUnit::Characters)
+            .short_token_length(2)
+            .long_token_length(16)
+            .finalize()
+            .unwrap();
+
+        for pak in [
+            controller.generate_key(),
+            controller.try_generate_key().unwrap(),
+        ] {
+            assert_ne!(pak.short_token(), "v3");
+            let parsed = controller.parse_key(&pak.to_string()).unwrap();
+            assert_eq!(parsed.short_token(), pak.short_token());
+            assert_eq!(parsed.long_token(), pak.long_token());
+        }
+    }
+}
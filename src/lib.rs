@@ -6,10 +6,159 @@ mod controller_builder;
 pub use crate::controller_builder::BuilderError;
 pub use crate::controller_builder::ControllerBuilder;
 
+mod typed_builder;
+pub use crate::typed_builder::Configured;
+pub use crate::typed_builder::TypedControllerBuilder;
+pub use crate::typed_builder::Unconfigured;
+
 mod controller;
 pub use crate::controller::PrefixedApiKeyController;
 
+mod token_encoding;
+pub use crate::token_encoding::LengthUnit;
+pub use crate::token_encoding::TokenEncoding;
+
+mod key_format;
+pub use crate::key_format::KeyFormat;
+pub use crate::key_format::KeyFormatV1;
+pub use crate::key_format::KeyFormats;
+
+mod multi_controller;
+pub use crate::multi_controller::KeyPolicy;
+pub use crate::multi_controller::KeyType;
+pub use crate::multi_controller::MultiKeyController;
+pub use crate::multi_controller::MultiKeyControllerBuilder;
+pub use crate::multi_controller::MultiKeyError;
+pub use crate::multi_controller::PublishableKey;
+
+mod prefix_router;
+pub use crate::prefix_router::PrefixRouter;
+
+mod store;
+pub use crate::store::ApiKeyRecord;
+pub use crate::store::KeyStore;
+pub use crate::store::MemoryKeyStore;
+
+mod blocklist;
+pub use crate::blocklist::Blocklist;
+pub use crate::blocklist::FileBlocklist;
+pub use crate::blocklist::MemoryBlocklist;
+pub use crate::blocklist::Revocation;
+pub use crate::blocklist::StoreBlocklist;
+
+mod scope;
+pub use crate::scope::require_scopes;
+pub use crate::scope::Scope;
+pub use crate::scope::ScopeError;
+pub use crate::scope::ScopeSet;
+
+mod auth;
+pub use crate::auth::AuthError;
+pub use crate::auth::AuthOutcome;
+pub use crate::auth::RotationError;
+
+mod extract;
+pub use crate::extract::CredentialExtractor;
+pub use crate::extract::CredentialRequest;
+pub use crate::extract::CredentialSource;
+pub use crate::extract::ExtractError;
+pub use crate::extract::SimpleRequest;
+
+mod scan;
+pub use crate::scan::GitHubPattern;
+pub use crate::scan::KeyScanner;
+pub use crate::scan::ScanMatch;
+
+mod authenticator;
+pub use crate::authenticator::AuthRejection;
+pub use crate::authenticator::Authenticator;
+pub use crate::authenticator::KeyAuthenticator;
+
+#[cfg(feature = "tower")]
+mod tower_auth;
+#[cfg(feature = "tower")]
+pub use crate::tower_auth::PakAuthLayer;
+#[cfg(feature = "tower")]
+pub use crate::tower_auth::PakAuthService;
+
+#[cfg(feature = "axum")]
+mod axum_auth;
+#[cfg(feature = "axum")]
+pub use crate::axum_auth::PakAuth;
+#[cfg(feature = "axum")]
+pub use crate::axum_auth::PakAuthState;
+
+#[cfg(feature = "actix")]
+mod actix_auth;
+#[cfg(feature = "actix")]
+pub use crate::actix_auth::ActixPakAuth;
+#[cfg(feature = "actix")]
+pub use crate::actix_auth::ActixPakAuthMiddleware;
+#[cfg(feature = "actix")]
+pub use crate::actix_auth::ActixPakAuthService;
+
+#[cfg(feature = "tonic")]
+mod tonic_auth;
+#[cfg(feature = "tonic")]
+pub use crate::tonic_auth::PakAuthInterceptor;
+
+#[cfg(feature = "secret-scanning")]
+mod secret_scanning;
+#[cfg(feature = "secret-scanning")]
+pub use crate::secret_scanning::PublicKeyError;
+#[cfg(feature = "secret-scanning")]
+pub use crate::secret_scanning::PublicKeySet;
+#[cfg(feature = "secret-scanning")]
+pub use crate::secret_scanning::SecretScanningError;
+#[cfg(feature = "secret-scanning")]
+pub use crate::secret_scanning::SecretScanningHandler;
+#[cfg(feature = "secret-scanning")]
+pub use crate::secret_scanning::SecretScanningLabel;
+#[cfg(feature = "secret-scanning")]
+pub use crate::secret_scanning::SecretScanningReport;
+#[cfg(feature = "secret-scanning")]
+pub use crate::secret_scanning::SecretScanningVerdict;
+#[cfg(feature = "secret-scanning")]
+pub use crate::secret_scanning::GITHUB_KEY_IDENTIFIER_HEADER;
+#[cfg(feature = "secret-scanning")]
+pub use crate::secret_scanning::GITHUB_SIGNATURE_HEADER;
+
+#[cfg(feature = "derivation")]
+mod derivation;
+#[cfg(feature = "derivation")]
+pub use crate::derivation::DerivationError;
+#[cfg(feature = "derivation")]
+pub use crate::derivation::DerivationKey;
+
+#[cfg(feature = "signed-keys")]
+mod signed_key;
+#[cfg(feature = "signed-keys")]
+pub use crate::signed_key::HmacSigner;
+#[cfg(feature = "signed-keys")]
+pub use crate::signed_key::KeySigner;
+#[cfg(feature = "signed-keys")]
+pub use crate::signed_key::SignatureVerifier;
+#[cfg(feature = "signed-keys")]
+pub use crate::signed_key::SignedClaims;
+#[cfg(feature = "signed-keys")]
+pub use crate::signed_key::StatelessError;
+
+#[cfg(feature = "ed25519")]
+mod public_key;
+#[cfg(feature = "ed25519")]
+pub use crate::public_key::Ed25519Signer;
+#[cfg(feature = "ed25519")]
+pub use crate::public_key::PublicKeyVerifier;
+
+#[cfg(feature = "test-util")]
+mod test_util;
+#[cfg(feature = "test-util")]
+pub use crate::test_util::FixedRng;
+#[cfg(feature = "test-util")]
+pub use crate::test_util::SeededRng;
+
 mod controller_alias;
+#[cfg(feature = "sha2")]
 pub use controller_alias::*;
 
 // reexport rngs
@@ -0,0 +1,798 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+use std::time::{Duration, SystemTime};
+
+use digest::{Digest, FixedOutputReset};
+use rand::RngCore;
+
+use crate::auth::{AuthError, AuthOutcome};
+use crate::blocklist::Blocklist;
+use crate::controller::{authenticate_with, issued_by, ErasedController, PrefixedApiKeyController};
+use crate::controller_builder::BuilderError;
+use crate::prefixed_api_key::{PrefixedApiKey, PrefixedApiKeyError};
+use crate::scope::ScopeSet;
+use crate::store::{ApiKeyRecord, KeyStore};
+
+type BoxedController = Box<dyn ErasedController + Send + Sync>;
+
+/// The name of a kind of key a [MultiKeyController] can issue, like `secret` or
+/// `restricted`. Any name can be registered, and [publishable](KeyType::publishable)
+/// and [secret](KeyType::secret) are provided for the most common pair.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct KeyType(String);
+
+impl KeyType {
+    pub fn new(name: String) -> KeyType {
+        KeyType(name)
+    }
+
+    /// Keys which identify an account, and are safe to embed in browsers and apps
+    pub fn publishable() -> KeyType {
+        KeyType("publishable".to_owned())
+    }
+
+    /// Keys which grant access to an account, and must be kept secret
+    pub fn secret() -> KeyType {
+        KeyType("secret".to_owned())
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+}
+
+impl fmt::Display for KeyType {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl From<&str> for KeyType {
+    fn from(name: &str) -> Self {
+        KeyType(name.to_owned())
+    }
+}
+
+impl From<String> for KeyType {
+    fn from(name: String) -> Self {
+        KeyType(name)
+    }
+}
+
+/// Errors raised while generating keys with a [MultiKeyController]
+#[derive(Debug)]
+pub enum MultiKeyError {
+    /// No profile was configured for the key type
+    UnconfiguredKeyType(KeyType),
+    /// The key type is publishable, so its keys have no secret and are generated with
+    /// [try_generate_publishable_key](MultiKeyController::try_generate_publishable_key)
+    PublishableKeyType(KeyType),
+    /// The key type isn't publishable, so its keys have a secret and are generated with
+    /// [try_generate_key](MultiKeyController::try_generate_key)
+    SecretKeyType(KeyType),
+    Rng(rand::Error),
+}
+
+impl fmt::Display for MultiKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            MultiKeyError::UnconfiguredKeyType(key_type) => {
+                write!(f, "no profile is configured for {} keys", key_type)
+            }
+            MultiKeyError::PublishableKeyType(key_type) => {
+                write!(f, "{} keys are publishable and have no secret", key_type)
+            }
+            MultiKeyError::SecretKeyType(key_type) => {
+                write!(f, "{} keys have a secret and aren't publishable", key_type)
+            }
+            MultiKeyError::Rng(err) => write!(f, "rng error: {}", err),
+        }
+    }
+}
+
+impl Error for MultiKeyError {}
+
+/// Settings applied to the records of newly generated keys of one [KeyType]
+#[derive(Debug, Clone, Default)]
+pub struct KeyPolicy {
+    /// How long new keys are accepted for, if not forever
+    pub ttl: Option<Duration>,
+    /// The scopes granted to new keys
+    pub scopes: ScopeSet,
+}
+
+impl KeyPolicy {
+    pub fn new() -> KeyPolicy {
+        KeyPolicy::default()
+    }
+
+    /// Sets how long new keys are accepted for
+    pub fn with_ttl(mut self, ttl: Duration) -> KeyPolicy {
+        self.ttl = Some(ttl);
+        self
+    }
+
+    /// Sets the scopes granted to new keys
+    pub fn with_scopes(mut self, scopes: ScopeSet) -> KeyPolicy {
+        self.scopes = scopes;
+        self
+    }
+
+    fn apply(&self, record: &mut ApiKeyRecord) {
+        if let Some(ttl) = self.ttl {
+            record.expires_at = Some(record.created_at + ttl);
+        }
+        record.scopes = self.scopes.clone();
+    }
+}
+
+/// A key issued by a publishable profile of a [MultiKeyController]. It only identifies
+/// an account, so it's just a prefix, optional environment and token, like `pk_BRTRKFsL`,
+/// with no long token. Its record's hash is empty.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PublishableKey {
+    prefix: String,
+    environment: Option<String>,
+    token: String,
+}
+
+impl PublishableKey {
+    pub fn prefix(&self) -> &str {
+        &self.prefix
+    }
+
+    pub fn environment(&self) -> Option<&str> {
+        self.environment.as_deref()
+    }
+
+    /// The random token identifying the key, which is also its record's short token
+    pub fn token(&self) -> &str {
+        &self.token
+    }
+}
+
+impl fmt::Display for PublishableKey {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match &self.environment {
+            Some(environment) => write!(f, "{}_{}_{}", self.prefix, environment, self.token),
+            None => write!(f, "{}_{}", self.prefix, self.token),
+        }
+    }
+}
+
+struct Profile {
+    controller: BoxedController,
+    policy: KeyPolicy,
+    publishable: bool,
+}
+
+/// Issues and authenticates several types of keys, each with their own controller
+/// and [KeyPolicy]. Every type must use a different prefix, which is how parsed keys
+/// are matched up with their type.
+///
+/// Publishable types only issue [PublishableKey]s, which have no secret, so they're
+/// looked up with [identify_publishable_key](MultiKeyController::identify_publishable_key)
+/// instead of being authenticated:
+///
+/// ```ignore
+/// let controller = MultiKeyController::configure()
+///     .publishable_profile(KeyType::publishable(), publishable_controller)
+///     .profile_with_policy(KeyType::secret(), secret_controller, policy)
+///     .finalize()?;
+///
+/// let (pak, record) = controller.try_generate_key_and_record(&KeyType::secret())?;
+/// let (key_type, pak) = controller.parse_key(&pak.to_string())?;
+/// ```
+pub struct MultiKeyController {
+    profiles: HashMap<KeyType, Profile>,
+    prefixes: HashMap<String, KeyType>,
+}
+
+impl MultiKeyController {
+    /// Creates an instance of [MultiKeyControllerBuilder] for adding the profile of
+    /// each key type
+    pub fn configure() -> MultiKeyControllerBuilder {
+        MultiKeyControllerBuilder::new()
+    }
+
+    /// The key types which have a profile
+    pub fn key_types(&self) -> impl Iterator<Item = &KeyType> + '_ {
+        self.profiles.keys()
+    }
+
+    /// The prefix used by keys of the given type
+    pub fn prefix(&self, key_type: &KeyType) -> Option<&str> {
+        self.profiles
+            .get(key_type)
+            .map(|profile| profile.controller.prefix())
+    }
+
+    /// Whether keys of the given type are publishable
+    pub fn is_publishable(&self, key_type: &KeyType) -> bool {
+        matches!(self.profiles.get(key_type), Some(profile) if profile.publishable)
+    }
+
+    /// Identifies the key's type from its prefix
+    pub fn key_type(&self, pak: &PrefixedApiKey) -> Option<&KeyType> {
+        self.prefixes.get(pak.prefix())
+    }
+
+    fn profile(&self, key_type: &KeyType) -> Result<&Profile, MultiKeyError> {
+        self.profiles
+            .get(key_type)
+            .ok_or_else(|| MultiKeyError::UnconfiguredKeyType(key_type.clone()))
+    }
+
+    /// Finds the profile for the prefix, if it issues keys of the given kind
+    fn profile_for_prefix(&self, prefix: &str, publishable: bool) -> Option<(&KeyType, &Profile)> {
+        let key_type = self.prefixes.get(prefix)?;
+        let profile = &self.profiles[key_type];
+        if profile.publishable == publishable {
+            Some((key_type, profile))
+        } else {
+            None
+        }
+    }
+
+    /// Generates a new key of the given type.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [try_generate_key](MultiKeyController::try_generate_key) would return
+    /// Err, such as when no profile was configured for the key type, or it's publishable.
+    pub fn generate_key(&self, key_type: &KeyType) -> PrefixedApiKey {
+        self.generate_key_and_record(key_type).0
+    }
+
+    /// Generates a new key of the given type, along with its [ApiKeyRecord] with the
+    /// type's [KeyPolicy] applied.
+    ///
+    /// # Panics
+    ///
+    /// Panics if [try_generate_key_and_record](MultiKeyController::try_generate_key_and_record)
+    /// would return Err, such as when no profile was configured for the key type, or it's publishable.
+    pub fn generate_key_and_record(&self, key_type: &KeyType) -> (PrefixedApiKey, ApiKeyRecord) {
+        match self.try_generate_key_and_record(key_type) {
+            Ok(generated) => generated,
+            Err(err) => panic!("{}", err),
+        }
+    }
+
+    /// Generates a new key of the given type, returning Err if no profile was configured
+    /// for the key type, the type is publishable, or the rng source fails.
+    pub fn try_generate_key(&self, key_type: &KeyType) -> Result<PrefixedApiKey, MultiKeyError> {
+        self.try_generate_key_and_record(key_type)
+            .map(|(pak, _)| pak)
+    }
+
+    /// Generates a new key of the given type, along with its [ApiKeyRecord] with the
+    /// type's [KeyPolicy] applied. Returns Err if no profile was configured for the key
+    /// type, the type is publishable, or the rng source fails.
+    pub fn try_generate_key_and_record(
+        &self,
+        key_type: &KeyType,
+    ) -> Result<(PrefixedApiKey, ApiKeyRecord), MultiKeyError> {
+        let profile = self.profile(key_type)?;
+        if profile.publishable {
+            return Err(MultiKeyError::PublishableKeyType(key_type.clone()));
+        }
+
+        let (pak, mut record) = profile
+            .controller
+            .try_generate_key_and_record()
+            .map_err(MultiKeyError::Rng)?;
+        profile.policy.apply(&mut record);
+        Ok((pak, record))
+    }
+
+    /// Generates a new publishable key of the given type, along with its [ApiKeyRecord]
+    /// with the type's [KeyPolicy] applied. No long token is generated, so the record's
+    /// hash is empty. Returns Err if no profile was configured for the key type, the type
+    /// isn't publishable, or the rng source fails.
+    pub fn try_generate_publishable_key(
+        &self,
+        key_type: &KeyType,
+    ) -> Result<(PublishableKey, ApiKeyRecord), MultiKeyError> {
+        let profile = self.profile(key_type)?;
+        if !profile.publishable {
+            return Err(MultiKeyError::SecretKeyType(key_type.clone()));
+        }
+
+        let controller = &profile.controller;
+        let token = controller
+            .try_generate_short_token()
+            .map_err(MultiKeyError::Rng)?;
+        let key = PublishableKey {
+            prefix: controller.prefix().to_owned(),
+            environment: controller.environment().map(str::to_owned),
+            token,
+        };
+
+        let mut record =
+            ApiKeyRecord::new(key.token.clone(), String::new()).with_prefix(key.prefix.clone());
+        record.environment = key.environment.clone();
+        profile.policy.apply(&mut record);
+        Ok((key, record))
+    }
+
+    /// Parses a key string, using its prefix to find its type. The key is then parsed
+    /// by that type's controller, so its environment is checked too. Publishable keys
+    /// are parsed with [parse_publishable_key](MultiKeyController::parse_publishable_key)
+    /// instead, so their prefixes are reported as unknown here.
+    pub fn parse_key(&self, key: &str) -> Result<(KeyType, PrefixedApiKey), PrefixedApiKeyError> {
+        let prefix = key.split('_').next().unwrap_or_default();
+        let (key_type, profile) = self
+            .profile_for_prefix(prefix, false)
+            .ok_or_else(|| PrefixedApiKeyError::UnknownPrefix(prefix.to_owned()))?;
+
+        let pak = profile.controller.parse_key(key)?;
+        Ok((key_type.clone(), pak))
+    }
+
+    /// Parses a publishable key string, using its prefix to find its type and checking
+    /// that it has the type's environment.
+    pub fn parse_publishable_key(
+        &self,
+        key: &str,
+    ) -> Result<(KeyType, PublishableKey), PrefixedApiKeyError> {
+        let parts: Vec<&str> = key.split('_').collect();
+        let (key_type, profile) = self
+            .profile_for_prefix(parts[0], true)
+            .ok_or_else(|| PrefixedApiKeyError::UnknownPrefix(parts[0].to_owned()))?;
+
+        let environment = profile.controller.environment();
+        let (found, token) = match parts.as_slice() {
+            [_, token] => (None, *token),
+            [_, found, token] => (Some(*found), *token),
+            _ => return Err(PrefixedApiKeyError::WrongNumberOfParts(parts.len())),
+        };
+        if found != environment {
+            return Err(PrefixedApiKeyError::WrongEnvironment {
+                expected: environment.map(str::to_owned),
+                found: found.map(str::to_owned),
+            });
+        }
+        if token.is_empty() {
+            return Err(PrefixedApiKeyError::WrongNumberOfParts(parts.len() - 1));
+        }
+
+        let key = PublishableKey {
+            prefix: parts[0].to_owned(),
+            environment: environment.map(str::to_owned),
+            token: token.to_owned(),
+        };
+        Ok((key_type.clone(), key))
+    }
+
+    /// Authenticates the key using its type's controller, as described by
+    /// [PrefixedApiKeyController::authenticate]. Keys with unknown or publishable
+    /// prefixes are reported as [UnknownKey](AuthOutcome::UnknownKey).
+    pub fn authenticate<S: KeyStore, B: Blocklist>(
+        &self,
+        pak: &PrefixedApiKey,
+        store: &S,
+        blocklist: &B,
+    ) -> Result<AuthOutcome, AuthError<S::Error, B::Error>> {
+        match self.profile_for_prefix(pak.prefix(), false) {
+            Some((_, profile)) => {
+                authenticate_with(profile.controller.as_ref(), pak, store, blocklist)
+            }
+            None => Ok(AuthOutcome::UnknownKey),
+        }
+    }
+
+    /// Looks up the record of a publishable key. There's no secret to check, so this
+    /// only tells which account the key belongs to, and must never be used to grant
+    /// access. Records issued for another prefix or environment, or with a secret, are
+    /// reported as [UnknownKey](AuthOutcome::UnknownKey), and revoked or expired keys are
+    /// reported like [authenticate](MultiKeyController::authenticate) does.
+    pub fn identify_publishable_key<S: KeyStore, B: Blocklist>(
+        &self,
+        key: &PublishableKey,
+        store: &S,
+        blocklist: &B,
+    ) -> Result<AuthOutcome, AuthError<S::Error, B::Error>> {
+        let profile = match self.profile_for_prefix(key.prefix(), true) {
+            Some((_, profile)) => profile,
+            None => return Ok(AuthOutcome::UnknownKey),
+        };
+        if key.environment() != profile.controller.environment() {
+            return Ok(AuthOutcome::UnknownKey);
+        }
+
+        if let Some(revocation) = blocklist
+            .lookup(key.token())
+            .map_err(AuthError::Blocklist)?
+        {
+            return Ok(AuthOutcome::Revoked(revocation));
+        }
+
+        let mut record = match store.get(key.token()).map_err(AuthError::Store)? {
+            Some(record) => record,
+            None => return Ok(AuthOutcome::UnknownKey),
+        };
+        if !issued_by(&record, profile.controller.as_ref()) || !record.hash.is_empty() {
+            return Ok(AuthOutcome::UnknownKey);
+        }
+
+        if let Some(revocation) = record.revocation.take() {
+            return Ok(AuthOutcome::Revoked(revocation));
+        }
+        if record.is_expired(SystemTime::now()) {
+            return Ok(AuthOutcome::Expired);
+        }
+
+        Ok(AuthOutcome::Authenticated(record))
+    }
+}
+
+impl fmt::Debug for MultiKeyController {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("MultiKeyController")
+            .field("prefixes", &self.prefixes)
+            .finish()
+    }
+}
+
+/// Builds a [MultiKeyController]
+#[derive(Default)]
+pub struct MultiKeyControllerBuilder {
+    profiles: Vec<(KeyType, Profile)>,
+}
+
+impl MultiKeyControllerBuilder {
+    pub fn new() -> MultiKeyControllerBuilder {
+        MultiKeyControllerBuilder::default()
+    }
+
+    /// Adds the controller used for keys of the given type, with the default policy.
+    /// This replaces any profile previously added for the key type.
+    pub fn profile<R, D>(
+        self,
+        key_type: KeyType,
+        controller: PrefixedApiKeyController<R, D>,
+    ) -> Self
+    where
+        R: RngCore + Clone + Send + Sync + 'static,
+        D: Digest + FixedOutputReset + Send + Sync + 'static,
+    {
+        self.profile_with_policy(key_type, controller, KeyPolicy::default())
+    }
+
+    /// Adds the controller and policy used for keys of the given type. This replaces
+    /// any profile previously added for the key type.
+    pub fn profile_with_policy<R, D>(
+        self,
+        key_type: KeyType,
+        controller: PrefixedApiKeyController<R, D>,
+        policy: KeyPolicy,
+    ) -> Self
+    where
+        R: RngCore + Clone + Send + Sync + 'static,
+        D: Digest + FixedOutputReset + Send + Sync + 'static,
+    {
+        self.add_profile(key_type, Box::new(controller), policy, false)
+    }
+
+    /// Adds a publishable key type, whose keys have no secret. Only the controller's
+    /// prefix, environment and short token settings are used. This replaces any profile
+    /// previously added for the key type.
+    pub fn publishable_profile<R, D>(
+        self,
+        key_type: KeyType,
+        controller: PrefixedApiKeyController<R, D>,
+    ) -> Self
+    where
+        R: RngCore + Clone + Send + Sync + 'static,
+        D: Digest + FixedOutputReset + Send + Sync + 'static,
+    {
+        self.publishable_profile_with_policy(key_type, controller, KeyPolicy::default())
+    }
+
+    /// Like [publishable_profile](MultiKeyControllerBuilder::publishable_profile), with
+    /// the policy applied to the records of new keys.
+    pub fn publishable_profile_with_policy<R, D>(
+        self,
+        key_type: KeyType,
+        controller: PrefixedApiKeyController<R, D>,
+        policy: KeyPolicy,
+    ) -> Self
+    where
+        R: RngCore + Clone + Send + Sync + 'static,
+        D: Digest + FixedOutputReset + Send + Sync + 'static,
+    {
+        self.add_profile(key_type, Box::new(controller), policy, true)
+    }
+
+    fn add_profile(
+        mut self,
+        key_type: KeyType,
+        controller: BoxedController,
+        policy: KeyPolicy,
+        publishable: bool,
+    ) -> Self {
+        self.profiles.retain(|(existing, _)| *existing != key_type);
+        self.profiles.push((
+            key_type,
+            Profile {
+                controller,
+                policy,
+                publishable,
+            },
+        ));
+        self
+    }
+
+    /// Finishes building the controller, returning Err if two key types share a prefix
+    pub fn finalize(self) -> Result<MultiKeyController, BuilderError> {
+        let mut profiles = HashMap::new();
+        let mut prefixes = HashMap::new();
+
+        for (key_type, profile) in self.profiles {
+            let prefix = profile.controller.prefix().to_owned();
+            if prefixes.insert(prefix.clone(), key_type.clone()).is_some() {
+                return Err(BuilderError::DuplicatePrefix(prefix));
+            }
+            profiles.insert(key_type, profile);
+        }
+
+        Ok(MultiKeyController { profiles, prefixes })
+    }
+}
+
+#[cfg(test)]
+mod multi_controller_tests {
+    use std::time::Duration;
+
+    use rand::rngs::OsRng;
+    use sha2::{Sha256, Sha512};
+
+    use super::{KeyPolicy, KeyType, MultiKeyController, MultiKeyError};
+    use crate::{
+        AuthOutcome, Blocklist, BuilderError, KeyStore, MemoryBlocklist, MemoryKeyStore,
+        PrefixedApiKey, PrefixedApiKeyController, PrefixedApiKeyError,
+    };
+
+    fn restricted() -> KeyType {
+        KeyType::new("restricted".to_owned())
+    }
+
+    fn controller() -> MultiKeyController {
+        let publishable =
+            PrefixedApiKeyController::<_, Sha256>::new("pk".to_owned(), OsRng, None, 8, 16);
+        let restricted_controller =
+            PrefixedApiKeyController::<_, Sha256>::new("rk".to_owned(), OsRng, None, 8, 16);
+        let secret =
+            PrefixedApiKeyController::<_, Sha512>::new("sk".to_owned(), OsRng, None, 8, 32);
+        let policy = KeyPolicy::new()
+            .with_ttl(Duration::from_secs(3600))
+            .with_scopes("billing:read billing:write".parse().unwrap());
+
+        MultiKeyController::configure()
+            .publishable_profile(KeyType::publishable(), publishable)
+            .profile(restricted(), restricted_controller)
+            .profile_with_policy(KeyType::secret(), secret, policy)
+            .finalize()
+            .unwrap()
+    }
+
+    #[test]
+    fn generates_each_type() {
+        let controller = controller();
+
+        let (pak, record) = controller.generate_key_and_record(&restricted());
+        assert_eq!(pak.prefix(), "rk");
+        assert!(record.expires_at.is_none());
+        assert!(record.scopes.is_empty());
+
+        let (pak, record) = controller.generate_key_and_record(&KeyType::secret());
+        assert_eq!(pak.prefix(), "sk");
+        assert_eq!(
+            record.expires_at,
+            Some(record.created_at + Duration::from_secs(3600))
+        );
+        assert_eq!(record.scopes.to_string(), "billing:read billing:write");
+    }
+
+    #[test]
+    fn publishable_keys_have_no_secret() {
+        let controller = controller();
+        assert!(controller.is_publishable(&KeyType::publishable()));
+        assert!(!controller.is_publishable(&KeyType::secret()));
+
+        let (key, record) = controller
+            .try_generate_publishable_key(&KeyType::publishable())
+            .unwrap();
+        assert_eq!(key.prefix(), "pk");
+        assert!(!key.token().is_empty());
+        assert_eq!(key.to_string(), format!("pk_{}", key.token()));
+        assert_eq!(record.short_token, key.token());
+        assert_eq!(record.prefix.as_deref(), Some("pk"));
+        assert!(record.hash.is_empty());
+
+        let (key_type, parsed) = controller.parse_publishable_key(&key.to_string()).unwrap();
+        assert_eq!(key_type, KeyType::publishable());
+        assert_eq!(parsed, key);
+
+        assert!(matches!(
+            controller.try_generate_key(&KeyType::publishable()),
+            Err(MultiKeyError::PublishableKeyType(_))
+        ));
+        assert!(matches!(
+            controller.try_generate_publishable_key(&KeyType::secret()),
+            Err(MultiKeyError::SecretKeyType(_))
+        ));
+        assert_eq!(
+            controller.parse_key(&key.to_string()).unwrap_err(),
+            PrefixedApiKeyError::UnknownPrefix("pk".to_owned())
+        );
+    }
+
+    #[test]
+    fn identifies_publishable_keys() {
+        let controller = controller();
+        let store = MemoryKeyStore::new();
+        let blocklist = MemoryBlocklist::new();
+
+        let (key, record) = controller
+            .try_generate_publishable_key(&KeyType::publishable())
+            .unwrap();
+        store.put(record.clone()).unwrap();
+        let outcome = controller
+            .identify_publishable_key(&key, &store, &blocklist)
+            .unwrap();
+        assert_eq!(outcome, AuthOutcome::Authenticated(record));
+
+        // A secret key's record can't be looked up as a publishable key
+        let (pak, record) = controller.generate_key_and_record(&KeyType::secret());
+        store.put(record).unwrap();
+        let (_, forged) = controller
+            .parse_publishable_key(&format!("pk_{}", pak.short_token()))
+            .unwrap();
+        let outcome = controller
+            .identify_publishable_key(&forged, &store, &blocklist)
+            .unwrap();
+        assert_eq!(outcome, AuthOutcome::UnknownKey);
+
+        blocklist.revoke(key.token(), "leaked", "alice").unwrap();
+        let outcome = controller
+            .identify_publishable_key(&key, &store, &blocklist)
+            .unwrap();
+        assert!(matches!(outcome, AuthOutcome::Revoked(_)));
+    }
+
+    #[test]
+    fn parse_key_dispatches_on_prefix() {
+        let controller = controller();
+
+        for key_type in [restricted(), KeyType::secret()] {
+            let pak = controller.generate_key(&key_type);
+            let (parsed_type, parsed) = controller.parse_key(&pak.to_string()).unwrap();
+            assert_eq!(parsed_type, key_type);
+            assert_eq!(parsed.short_token(), pak.short_token());
+            assert_eq!(controller.key_type(&pak), Some(&key_type));
+        }
+
+        assert_eq!(
+            controller.parse_key("xk_abc_def").unwrap_err(),
+            PrefixedApiKeyError::UnknownPrefix("xk".to_owned())
+        );
+        assert_eq!(
+            controller.parse_publishable_key("pk_live_abc").unwrap_err(),
+            PrefixedApiKeyError::WrongEnvironment {
+                expected: None,
+                found: Some("live".to_owned())
+            }
+        );
+    }
+
+    #[test]
+    fn authenticates_with_each_types_digest() {
+        let controller = controller();
+        let store = MemoryKeyStore::new();
+        let blocklist = MemoryBlocklist::new();
+
+        let (restricted, record) = controller.generate_key_and_record(&restricted());
+        store.put(record).unwrap();
+        let (secret, record) = controller.generate_key_and_record(&KeyType::secret());
+        store.put(record).unwrap();
+
+        for pak in [&restricted, &secret] {
+            let outcome = controller.authenticate(pak, &store, &blocklist).unwrap();
+            assert!(outcome.is_authenticated());
+        }
+
+        // A secret key's short token presented with the restricted prefix doesn't match
+        // the prefix its record was issued with
+        let swapped = PrefixedApiKey::new(
+            "rk".to_owned(),
+            secret.short_token().to_owned(),
+            secret.long_token().to_owned(),
+        );
+        let outcome = controller
+            .authenticate(&swapped, &store, &blocklist)
+            .unwrap();
+        assert!(matches!(outcome, AuthOutcome::UnknownKey));
+
+        for prefix in ["xk", "pk"] {
+            let unknown =
+                PrefixedApiKey::new(prefix.to_owned(), "abc".to_owned(), "def".to_owned());
+            let outcome = controller
+                .authenticate(&unknown, &store, &blocklist)
+                .unwrap();
+            assert!(matches!(outcome, AuthOutcome::UnknownKey));
+        }
+    }
+
+    #[test]
+    fn key_types_sharing_a_digest_cant_be_swapped() {
+        let restricted_controller =
+            PrefixedApiKeyController::<_, Sha256>::new("rk".to_owned(), OsRng, None, 8, 24);
+        let secret =
+            PrefixedApiKeyController::<_, Sha256>::new("sk".to_owned(), OsRng, None, 8, 24);
+        let controller = MultiKeyController::configure()
+            .profile(restricted(), restricted_controller)
+            .profile(KeyType::secret(), secret)
+            .finalize()
+            .unwrap();
+        let store = MemoryKeyStore::new();
+        let blocklist = MemoryBlocklist::new();
+
+        let (restricted, record) = controller.generate_key_and_record(&restricted());
+        store.put(record).unwrap();
+
+        // Re-prefixing a restricted key as a secret one must not grant secret access
+        let forged = PrefixedApiKey::new(
+            "sk".to_owned(),
+            restricted.short_token().to_owned(),
+            restricted.long_token().to_owned(),
+        );
+        let outcome = controller
+            .authenticate(&forged, &store, &blocklist)
+            .unwrap();
+        assert!(matches!(outcome, AuthOutcome::UnknownKey));
+        assert!(controller
+            .authenticate(&restricted, &store, &blocklist)
+            .unwrap()
+            .is_authenticated());
+    }
+
+    #[test]
+    fn try_generate_reports_unconfigured_key_types() {
+        let secret =
+            PrefixedApiKeyController::<_, Sha256>::new("sk".to_owned(), OsRng, None, 8, 16);
+        let controller = MultiKeyController::configure()
+            .profile(KeyType::secret(), secret)
+            .finalize()
+            .unwrap();
+
+        assert!(controller.try_generate_key(&KeyType::secret()).is_ok());
+        assert!(matches!(
+            controller.try_generate_key_and_record(&restricted()),
+            Err(MultiKeyError::UnconfiguredKeyType(key_type)) if key_type == restricted()
+        ));
+    }
+
+    #[test]
+    #[should_panic(expected = "no profile is configured for restricted keys")]
+    fn generate_panics_for_unconfigured_key_types() {
+        MultiKeyController::configure()
+            .finalize()
+            .unwrap()
+            .generate_key(&restricted());
+    }
+
+    #[test]
+    fn rejects_shared_prefixes() {
+        let first = PrefixedApiKeyController::<_, Sha256>::new("pk".to_owned(), OsRng, None, 8, 16);
+        let second =
+            PrefixedApiKeyController::<_, Sha256>::new("pk".to_owned(), OsRng, None, 8, 32);
+
+        let result = MultiKeyController::configure()
+            .publishable_profile(KeyType::publishable(), first)
+            .profile(KeyType::secret(), second)
+            .finalize();
+        assert!(matches!(result, Err(BuilderError::DuplicatePrefix(prefix)) if prefix == "pk"));
+    }
+}
@@ -0,0 +1,238 @@
+use std::collections::HashMap;
+use std::fmt;
+use std::sync::{Arc, RwLock};
+
+use digest::{Digest, FixedOutputReset};
+use rand::RngCore;
+
+use crate::auth::{AuthError, AuthOutcome};
+use crate::blocklist::Blocklist;
+use crate::controller::{authenticate_with, ErasedController, PrefixedApiKeyController};
+use crate::prefixed_api_key::{PrefixedApiKey, PrefixedApiKeyError};
+use crate::store::KeyStore;
+
+type SharedController = Arc<dyn ErasedController + Send + Sync>;
+
+/// Routes keys to the controller for their prefix, for platforms where each tenant
+/// issues keys with its own prefix. Lookups are a single hash map access, and tenants
+/// can be added or removed at runtime through a shared reference.
+///
+/// The lock is only held while looking up a controller, never while hashing or
+/// accessing a key store.
+#[derive(Default)]
+pub struct PrefixRouter {
+    controllers: RwLock<HashMap<String, SharedController>>,
+}
+
+impl PrefixRouter {
+    pub fn new() -> PrefixRouter {
+        PrefixRouter::default()
+    }
+
+    /// Adds the controller for its configured prefix, replacing any controller already
+    /// using the prefix. Returns whether one was replaced.
+    pub fn insert<R, D>(&self, controller: PrefixedApiKeyController<R, D>) -> bool
+    where
+        R: RngCore + Clone + Send + Sync + 'static,
+        D: Digest + FixedOutputReset + Send + Sync + 'static,
+    {
+        let prefix = controller.prefix().to_owned();
+        self.controllers
+            .write()
+            .unwrap()
+            .insert(prefix, Arc::new(controller))
+            .is_some()
+    }
+
+    /// Removes the controller for the prefix, returning whether there was one
+    pub fn remove(&self, prefix: &str) -> bool {
+        self.controllers.write().unwrap().remove(prefix).is_some()
+    }
+
+    pub fn contains(&self, prefix: &str) -> bool {
+        self.controllers.read().unwrap().contains_key(prefix)
+    }
+
+    /// The prefixes which currently have a controller, in no particular order
+    pub fn prefixes(&self) -> Vec<String> {
+        self.controllers.read().unwrap().keys().cloned().collect()
+    }
+
+    pub fn len(&self) -> usize {
+        self.controllers.read().unwrap().len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.controllers.read().unwrap().is_empty()
+    }
+
+    fn route(&self, prefix: &str) -> Option<SharedController> {
+        self.controllers.read().unwrap().get(prefix).cloned()
+    }
+
+    /// Parses a key string using the controller for its prefix, as described by
+    /// [PrefixedApiKeyController::parse_key]. Keys with prefixes which aren't routed
+    /// are rejected with [UnknownPrefix](PrefixedApiKeyError::UnknownPrefix).
+    pub fn parse_key(&self, key: &str) -> Result<PrefixedApiKey, PrefixedApiKeyError> {
+        let prefix = key.split('_').next().unwrap_or_default();
+        match self.route(prefix) {
+            Some(controller) => controller.parse_key(key),
+            None => Err(PrefixedApiKeyError::UnknownPrefix(prefix.to_owned())),
+        }
+    }
+
+    /// Checks the key against the hash using the digest of the controller for its
+    /// prefix. Keys with prefixes which aren't routed never match.
+    ///
+    /// A bare hash doesn't say which tenant issued it, so tenants sharing a digest accept
+    /// each other's keys here. Use [authenticate](PrefixRouter::authenticate), which checks
+    /// the prefix stored in the key's record, when tenants share a store.
+    pub fn check_hash(&self, pak: &PrefixedApiKey, hash: &str) -> bool {
+        match self.route(pak.prefix()) {
+            Some(controller) => controller.check_hash(pak, hash),
+            None => false,
+        }
+    }
+
+    /// Authenticates the key using the controller for its prefix, as described by
+    /// [PrefixedApiKeyController::authenticate]. Keys with prefixes which aren't
+    /// routed are reported as [UnknownKey](AuthOutcome::UnknownKey), and so are keys
+    /// whose record was issued by another tenant.
+    pub fn authenticate<S: KeyStore, B: Blocklist>(
+        &self,
+        pak: &PrefixedApiKey,
+        store: &S,
+        blocklist: &B,
+    ) -> Result<AuthOutcome, AuthError<S::Error, B::Error>> {
+        match self.route(pak.prefix()) {
+            Some(controller) => authenticate_with(controller.as_ref(), pak, store, blocklist),
+            None => Ok(AuthOutcome::UnknownKey),
+        }
+    }
+}
+
+impl fmt::Debug for PrefixRouter {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("PrefixRouter")
+            .field("prefixes", &self.prefixes())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod prefix_router_tests {
+    use std::sync::Arc;
+    use std::thread;
+
+    use rand::rngs::OsRng;
+    use sha2::{Sha256, Sha512};
+
+    use super::PrefixRouter;
+    use crate::{
+        AuthOutcome, KeyStore, MemoryBlocklist, MemoryKeyStore, PrefixedApiKey,
+        PrefixedApiKeyController, PrefixedApiKeyError,
+    };
+
+    fn tenant(prefix: &str) -> PrefixedApiKeyController<OsRng, Sha256> {
+        PrefixedApiKeyController::new(prefix.to_owned(), OsRng, None, 8, 24)
+    }
+
+    #[test]
+    fn routes_by_prefix() {
+        let router = PrefixRouter::new();
+        assert!(!router.insert(tenant("tenantA")));
+        assert!(!router.insert(PrefixedApiKeyController::<_, Sha512>::new(
+            "tenantB".to_owned(),
+            OsRng,
+            None,
+            8,
+            24
+        )));
+        assert_eq!(router.len(), 2);
+
+        let a_controller = tenant("tenantA");
+        let a_pak = a_controller.generate_key();
+        let a_hash = a_controller.long_token_hashed(&a_pak);
+        let b_controller =
+            PrefixedApiKeyController::<_, Sha512>::new("tenantB".to_owned(), OsRng, None, 8, 24);
+        let b_pak = b_controller.generate_key();
+        let b_hash = b_controller.long_token_hashed(&b_pak);
+
+        let parsed = router.parse_key(&a_pak.to_string()).unwrap();
+        assert_eq!(parsed.prefix(), "tenantA");
+        assert!(router.check_hash(&a_pak, &a_hash));
+        assert!(router.check_hash(&b_pak, &b_hash));
+        assert!(!router.check_hash(&a_pak, &b_hash));
+
+        assert_eq!(
+            router.parse_key("tenantC_abc_def").unwrap_err(),
+            PrefixedApiKeyError::UnknownPrefix("tenantC".to_owned())
+        );
+    }
+
+    #[test]
+    fn tenants_can_be_removed() {
+        let router = PrefixRouter::new();
+        router.insert(tenant("tenantA"));
+
+        let (pak, record) = tenant("tenantA").generate_key_and_record();
+        let store = MemoryKeyStore::new();
+        store.put(record).unwrap();
+        let blocklist = MemoryBlocklist::new();
+
+        let outcome = router.authenticate(&pak, &store, &blocklist).unwrap();
+        assert!(outcome.is_authenticated());
+
+        assert!(router.remove("tenantA"));
+        assert!(!router.remove("tenantA"));
+        let outcome = router.authenticate(&pak, &store, &blocklist).unwrap();
+        assert!(matches!(outcome, AuthOutcome::UnknownKey));
+        assert!(!router.check_hash(&pak, &store.get(pak.short_token()).unwrap().unwrap().hash));
+    }
+
+    #[test]
+    fn tenants_can_be_added_concurrently() {
+        let router = Arc::new(PrefixRouter::new());
+
+        let handles: Vec<_> = (0..8)
+            .map(|i| {
+                let router = router.clone();
+                thread::spawn(move || {
+                    let prefix = format!("tenant{}", i);
+                    router.insert(tenant(&prefix));
+                    let pak = tenant(&prefix).generate_key();
+                    router.parse_key(&pak.to_string()).unwrap();
+                })
+            })
+            .collect();
+        for handle in handles {
+            handle.join().unwrap();
+        }
+
+        assert_eq!(router.len(), 8);
+        assert!(router.contains("tenant3"));
+    }
+
+    #[test]
+    fn tenants_cant_forge_each_others_keys() {
+        let router = PrefixRouter::new();
+        router.insert(tenant("tenantA"));
+        router.insert(tenant("tenantB"));
+
+        let (pak, record) = tenant("tenantA").generate_key_and_record();
+        let store = MemoryKeyStore::new();
+        store.put(record).unwrap();
+        let blocklist = MemoryBlocklist::new();
+
+        // Both tenants use the same digest, so only the record's prefix tells them apart
+        let forged = PrefixedApiKey::new(
+            "tenantB".to_owned(),
+            pak.short_token().to_owned(),
+            pak.long_token().to_owned(),
+        );
+        let outcome = router.authenticate(&forged, &store, &blocklist).unwrap();
+        assert!(matches!(outcome, AuthOutcome::UnknownKey));
+        let outcome = router.authenticate(&pak, &store, &blocklist).unwrap();
+        assert!(outcome.is_authenticated());
+    }
+}
@@ -2,18 +2,70 @@ use digest::{Digest, FixedOutputReset};
 use std::error::Error;
 use std::fmt;
 use std::fmt::Debug;
+use std::sync::Arc;
+
+use crate::key_format::{format_key, KeyFormat, KeyFormatV1};
+use crate::token_encoding::TokenEncoding;
 
 #[derive(Debug, PartialEq, Eq)]
 pub enum PrefixedApiKeyError {
     WrongNumberOfParts(usize),
+    /// No key type uses the key's prefix
+    UnknownPrefix(String),
+    /// The key's environment segment didn't match the controller's environment
+    WrongEnvironment {
+        expected: Option<String>,
+        found: Option<String>,
+    },
+    /// The key's version marker is for a format which isn't registered
+    UnsupportedVersion(u32),
+    /// A token contained characters which aren't in the controller's encoding
+    InvalidEncoding(TokenEncoding),
+    /// A token wasn't exactly the number of characters the controller generates
+    WrongTokenLength {
+        expected: usize,
+        found: usize,
+    },
 }
 
 impl Error for PrefixedApiKeyError {}
 
 impl fmt::Display for PrefixedApiKeyError {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
-        // TODO: Display should use something other than debug display
-        write!(f, "{:?}", self)
+        match self {
+            PrefixedApiKeyError::WrongNumberOfParts(count) => write!(
+                f,
+                "key has {} underscore separated parts, which isn't a valid key layout",
+                count
+            ),
+            PrefixedApiKeyError::UnknownPrefix(prefix) => {
+                write!(f, "no key type uses the prefix '{}'", prefix)
+            }
+            PrefixedApiKeyError::WrongEnvironment { expected, found } => write!(
+                f,
+                "key is for {}, but {} was expected",
+                describe_environment(found),
+                describe_environment(expected)
+            ),
+            PrefixedApiKeyError::UnsupportedVersion(version) => {
+                write!(f, "key format version {} isn't supported", version)
+            }
+            PrefixedApiKeyError::InvalidEncoding(encoding) => {
+                write!(f, "key contains characters which aren't valid {}", encoding)
+            }
+            PrefixedApiKeyError::WrongTokenLength { expected, found } => write!(
+                f,
+                "key has a token of {} characters, but {} were expected",
+                found, expected
+            ),
+        }
+    }
+}
+
+fn describe_environment(environment: &Option<String>) -> String {
+    match environment {
+        Some(environment) => format!("the '{}' environment", environment),
+        None => "no environment".to_owned(),
     }
 }
 
@@ -23,8 +75,10 @@ impl fmt::Display for PrefixedApiKeyError {
 /// via the `new` method while generating a new key to be given to the user.
 pub struct PrefixedApiKey {
     prefix: String,
+    environment: Option<String>,
     short_token: String,
     long_token: String,
+    format: Option<Arc<dyn KeyFormat + Send + Sync>>,
 }
 
 impl PrefixedApiKey {
@@ -34,16 +88,51 @@ impl PrefixedApiKey {
     pub fn new(prefix: String, short_token: String, long_token: String) -> PrefixedApiKey {
         PrefixedApiKey {
             prefix,
+            environment: None,
             short_token,
             long_token,
+            format: None,
+        }
+    }
+
+    /// Sets the [KeyFormat] used by `to_string`, instead of the original version 1 format
+    pub fn with_format(mut self, format: Arc<dyn KeyFormat + Send + Sync>) -> PrefixedApiKey {
+        self.format = Some(format);
+        self
+    }
+
+    /// The version of the key's format
+    pub fn version(&self) -> u32 {
+        match &self.format {
+            Some(format) => format.version(),
+            None => KeyFormatV1.version(),
         }
     }
 
+    /// Sets the key's environment, like `test` or `live`, which is included as its own
+    /// segment between the prefix and the short token: `mycompany_live_<short>_<long>`.
+    pub fn with_environment(mut self, environment: String) -> PrefixedApiKey {
+        self.environment = Some(environment);
+        self
+    }
+
+    /// Replaces the key's tokens, keeping its prefix, environment and format
+    pub(crate) fn with_tokens(mut self, short_token: String, long_token: String) -> PrefixedApiKey {
+        self.short_token = short_token;
+        self.long_token = long_token;
+        self
+    }
+
     /// Getter method for accessing the key's prefix
     pub fn prefix(&self) -> &str {
         &self.prefix
     }
 
+    /// Getter method for accessing the key's environment, if it has one
+    pub fn environment(&self) -> Option<&str> {
+        self.environment.as_deref()
+    }
+
     /// Getter method for accessing the key's short token
     pub fn short_token(&self) -> &str {
         &self.short_token
@@ -79,14 +168,38 @@ impl PrefixedApiKey {
             parts[2].to_owned(),
         ))
     }
+
+    /// Like [from_string](PrefixedApiKey::from_string), but also accepts keys with an
+    /// environment segment. Use [PrefixedApiKeyController::parse_key](crate::PrefixedApiKeyController::parse_key)
+    /// to also check the environment is the expected one.
+    pub fn from_string_with_environment(
+        pak_string: &str,
+    ) -> Result<PrefixedApiKey, PrefixedApiKeyError> {
+        let parts: Vec<&str> = pak_string.split('_').collect();
+
+        match parts.len() {
+            3 => PrefixedApiKey::from_string(pak_string),
+            4 => Ok(PrefixedApiKey::new(
+                parts[0].to_owned(),
+                parts[2].to_owned(),
+                parts[3].to_owned(),
+            )
+            .with_environment(parts[1].to_owned())),
+            count => Err(PrefixedApiKeyError::WrongNumberOfParts(count)),
+        }
+    }
 }
 
 /// A custom implementation of Debug that masks the secret long token that way
 /// the struct can be debug printed without leaking sensitive info into logs
 impl Debug for PrefixedApiKey {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-        f.debug_struct("PrefixedApiKey")
-            .field("prefix", &self.prefix)
+        let mut debug = f.debug_struct("PrefixedApiKey");
+        debug.field("prefix", &self.prefix);
+        if let Some(environment) = &self.environment {
+            debug.field("environment", environment);
+        }
+        debug
             .field("short_token", &self.short_token)
             .field("long_token", &"***")
             .finish()
@@ -99,7 +212,10 @@ impl Debug for PrefixedApiKey {
 #[allow(clippy::to_string_trait_impl)]
 impl ToString for PrefixedApiKey {
     fn to_string(&self) -> String {
-        format!("{}_{}_{}", self.prefix, self.short_token, self.long_token)
+        match &self.format {
+            Some(format) => format_key(format.as_ref(), self),
+            None => format_key(&KeyFormatV1, self),
+        }
     }
 }
 
@@ -131,7 +247,7 @@ mod tests {
     fn self_from_string_works() {
         let pak_string = "mycompany_abcdefg_bacdegadsa";
         let pak_result = PrefixedApiKey::from_string(pak_string);
-        assert_eq!(pak_result.is_ok(), true);
+        assert!(pak_result.is_ok());
         assert_eq!(pak_result.unwrap().to_string(), pak_string);
     }
 
@@ -139,7 +255,7 @@ mod tests {
     fn str_into_pak() {
         let pak_string = "mycompany_abcdefg_bacdegadsa";
         let pak_result: Result<PrefixedApiKey, _> = pak_string.try_into();
-        assert_eq!(pak_result.is_ok(), true);
+        assert!(pak_result.is_ok());
         assert_eq!(pak_result.unwrap().to_string(), pak_string);
     }
 
@@ -147,7 +263,7 @@ mod tests {
     fn string_into_pak_via_as_ref() {
         let pak_string = "mycompany_abcdefg_bacdegadsa".to_owned();
         let pak_result: Result<PrefixedApiKey, _> = pak_string.as_str().try_into();
-        assert_eq!(pak_result.is_ok(), true);
+        assert!(pak_result.is_ok());
         assert_eq!(pak_result.unwrap().to_string(), pak_string);
     }
 
@@ -155,13 +271,37 @@ mod tests {
     fn str_into_pak_with_extra_parts() {
         let pak_string = "mycompany_abcd_efg_bacdegadsa";
         let pak_result: Result<PrefixedApiKey, _> = pak_string.try_into();
-        assert_eq!(pak_result.is_err(), true);
+        assert!(pak_result.is_err());
         assert_eq!(
             pak_result.unwrap_err(),
             PrefixedApiKeyError::WrongNumberOfParts(4)
         );
     }
 
+    #[test]
+    fn errors_display_readable_messages() {
+        assert_eq!(
+            PrefixedApiKeyError::WrongNumberOfParts(2).to_string(),
+            "key has 2 underscore separated parts, which isn't a valid key layout"
+        );
+        assert_eq!(
+            PrefixedApiKeyError::WrongEnvironment {
+                expected: Some("live".to_owned()),
+                found: None,
+            }
+            .to_string(),
+            "key is for no environment, but the 'live' environment was expected"
+        );
+        assert_eq!(
+            PrefixedApiKeyError::WrongTokenLength {
+                expected: 24,
+                found: 3,
+            }
+            .to_string(),
+            "key has a token of 3 characters, but 24 were expected"
+        );
+    }
+
     #[test]
     fn check_long_token() {
         let pak_string = "mycompany_CEUsS4psCmc_BddpcwWyCT3EkDjHSSTRaSK1dxtuQgbjb";
@@ -180,4 +320,23 @@ mod tests {
         let debug_string = format!("{:?}", pak);
         assert_eq!(debug_string, "PrefixedApiKey { prefix: \"mycompany\", short_token: \"CEUsS4psCmc\", long_token: \"***\" }");
     }
+
+    #[test]
+    fn environment_segment_round_trips() {
+        let pak_string = "mycompany_live_CEUsS4psCmc_BddpcwWyCT3EkDjHSSTRaSK1dxtuQgbjb";
+        let pak = PrefixedApiKey::from_string_with_environment(pak_string).unwrap();
+        assert_eq!(pak.prefix(), "mycompany");
+        assert_eq!(pak.environment(), Some("live"));
+        assert_eq!(pak.short_token(), "CEUsS4psCmc");
+        assert_eq!(pak.to_string(), pak_string);
+        assert_eq!(format!("{:?}", pak), "PrefixedApiKey { prefix: \"mycompany\", environment: \"live\", short_token: \"CEUsS4psCmc\", long_token: \"***\" }");
+
+        let pak = PrefixedApiKey::from_string_with_environment("mycompany_abc_def").unwrap();
+        assert_eq!(pak.environment(), None);
+
+        assert_eq!(
+            PrefixedApiKey::from_string_with_environment("a_b_c_d_e").unwrap_err(),
+            PrefixedApiKeyError::WrongNumberOfParts(5)
+        );
+    }
 }
@@ -0,0 +1,318 @@
+use std::fmt;
+
+use ed25519_dalek::{Signature, Signer, SigningKey, VerifyingKey};
+
+use crate::key_format::KeyFormats;
+use crate::prefixed_api_key::PrefixedApiKey;
+use crate::signed_key::{
+    verify_claims, KeySigner, SignatureVerifier, SignedClaims, StatelessError,
+};
+use crate::token_encoding::TokenEncoding;
+
+/// The algorithm identifier written into long tokens signed with Ed25519
+const ED25519_ALGORITHM: u8 = 2;
+
+/// Signs keys with an Ed25519 private key. Anyone holding the matching public key can
+/// verify keys with a [PublicKeyVerifier], but only the holder of the private key can
+/// issue them, so partners can validate keys without being able to forge them.
+///
+/// Requires the "ed25519" feature, which needs Rust 1.81 or newer
+#[derive(Clone)]
+pub struct Ed25519Signer {
+    key: SigningKey,
+}
+
+impl Ed25519Signer {
+    /// Creates the signer from a 32 byte Ed25519 private key
+    pub fn from_bytes(private_key: &[u8; 32]) -> Ed25519Signer {
+        Ed25519Signer {
+            key: SigningKey::from_bytes(private_key),
+        }
+    }
+
+    /// The public key to share with the services verifying keys
+    pub fn public_key(&self) -> [u8; 32] {
+        self.key.verifying_key().to_bytes()
+    }
+
+    /// Creates a [PublicKeyVerifier] for the keys this signer issues with the given
+    /// prefix and environment
+    pub fn verifier(&self, prefix: String, environment: Option<String>) -> PublicKeyVerifier {
+        PublicKeyVerifier::from_key(self.key.verifying_key(), prefix, environment)
+    }
+}
+
+impl SignatureVerifier for Ed25519Signer {
+    fn algorithm(&self) -> u8 {
+        ED25519_ALGORITHM
+    }
+
+    fn signature_length(&self) -> usize {
+        Signature::BYTE_SIZE
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        verify_ed25519(&self.key.verifying_key(), message, signature)
+    }
+}
+
+impl KeySigner for Ed25519Signer {
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        self.key.sign(message).to_bytes().to_vec()
+    }
+}
+
+/// A custom implementation of Debug that hides the private key
+impl fmt::Debug for Ed25519Signer {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("Ed25519Signer")
+            .field("key", &"***")
+            .field("public_key", &hex::encode(self.public_key()))
+            .finish()
+    }
+}
+
+/// Verifies keys signed by an [Ed25519Signer] with only its public key, checking the
+/// key's structure, signature and expiry. This is meant for third parties, so it needs
+/// no controller: configure the token encoding and key formats to match the issuer's.
+///
+/// Only keys with the prefix and environment the verifier was created with are accepted,
+/// so keys the issuer signed for its other products or environments are rejected.
+/// Revoked keys are still accepted until they expire, since revocations are only known
+/// to the issuer, and keys which never expire are rejected unless
+/// [allow_non_expiring](PublicKeyVerifier::allow_non_expiring) is set.
+///
+/// Requires the "ed25519" feature, which needs Rust 1.81 or newer
+#[derive(Clone, Debug)]
+pub struct PublicKeyVerifier {
+    key: VerifyingKey,
+    encoding: TokenEncoding,
+    formats: KeyFormats,
+    prefix: String,
+    environment: Option<String>,
+    allow_non_expiring: bool,
+}
+
+impl PublicKeyVerifier {
+    /// Creates the verifier from the issuer's 32 byte Ed25519 public key, accepting only
+    /// keys with the given prefix and environment
+    pub fn new(
+        public_key: &[u8; 32],
+        prefix: String,
+        environment: Option<String>,
+    ) -> Result<PublicKeyVerifier, StatelessError> {
+        match VerifyingKey::from_bytes(public_key) {
+            Ok(key) => Ok(PublicKeyVerifier::from_key(key, prefix, environment)),
+            Err(_) => Err(StatelessError::InvalidPublicKey),
+        }
+    }
+
+    fn from_key(
+        key: VerifyingKey,
+        prefix: String,
+        environment: Option<String>,
+    ) -> PublicKeyVerifier {
+        PublicKeyVerifier {
+            key,
+            encoding: TokenEncoding::default(),
+            formats: KeyFormats::new(),
+            prefix,
+            environment,
+            allow_non_expiring: false,
+        }
+    }
+
+    /// Sets the encoding the issuer's tokens use.
+    ///
+    /// Default: [TokenEncoding::Base58]
+    pub fn with_encoding(mut self, encoding: TokenEncoding) -> PublicKeyVerifier {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Sets the key formats the issuer's keys may be in.
+    ///
+    /// Default: [KeyFormats::new], which only accepts version 1
+    pub fn with_key_formats(mut self, formats: KeyFormats) -> PublicKeyVerifier {
+        self.formats = formats;
+        self
+    }
+
+    /// Also accepts keys which never expire. The verifier can't see revocations, so
+    /// once issued, such keys are accepted forever.
+    ///
+    /// Default: false
+    pub fn allow_non_expiring(mut self) -> PublicKeyVerifier {
+        self.allow_non_expiring = true;
+        self
+    }
+
+    /// Verifies the key's signature and expiry, returning its claims. Keys with a
+    /// different prefix or environment than the verifier's are rejected with
+    /// [WrongIssuer](StatelessError::WrongIssuer), and keys which never expire with
+    /// [NeverExpires](StatelessError::NeverExpires) unless they're allowed.
+    pub fn verify_key(&self, pak: &PrefixedApiKey) -> Result<SignedClaims, StatelessError> {
+        if pak.prefix() != self.prefix || pak.environment() != self.environment.as_deref() {
+            return Err(StatelessError::WrongIssuer);
+        }
+        verify_claims(self, pak, self.encoding, self.allow_non_expiring)
+    }
+
+    /// Parses the key string, rejecting it as [Malformed](StatelessError::Malformed) if it
+    /// isn't a key, then verifies it with [verify_key](PublicKeyVerifier::verify_key)
+    pub fn verify_str(&self, key: &str) -> Result<(PrefixedApiKey, SignedClaims), StatelessError> {
+        let pak = match self.formats.parse(key) {
+            Ok(pak) => pak,
+            Err(_) => return Err(StatelessError::Malformed),
+        };
+        let claims = self.verify_key(&pak)?;
+        Ok((pak, claims))
+    }
+}
+
+impl SignatureVerifier for PublicKeyVerifier {
+    fn algorithm(&self) -> u8 {
+        ED25519_ALGORITHM
+    }
+
+    fn signature_length(&self) -> usize {
+        Signature::BYTE_SIZE
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        verify_ed25519(&self.key, message, signature)
+    }
+}
+
+fn verify_ed25519(key: &VerifyingKey, message: &[u8], signature: &[u8]) -> bool {
+    match Signature::from_slice(signature) {
+        Ok(signature) => key.verify_strict(message, &signature).is_ok(),
+        Err(_) => false,
+    }
+}
+
+#[cfg(test)]
+mod public_key_tests {
+    use std::time::{Duration, SystemTime};
+
+    use rand::rngs::OsRng;
+    use sha2::Sha256;
+
+    use super::{Ed25519Signer, PublicKeyVerifier};
+    use crate::{HmacSigner, PrefixedApiKeyController, StatelessError, TokenEncoding};
+
+    const PRIVATE_KEY: [u8; 32] = [9; 32];
+
+    fn issuer(encoding: TokenEncoding) -> PrefixedApiKeyController<OsRng, Sha256> {
+        PrefixedApiKeyController::configure()
+            .prefix("mycompany".to_owned())
+            .rng(OsRng)
+            .short_token_length(8)
+            .token_encoding(encoding)
+            .sign_keys(Ed25519Signer::from_bytes(&PRIVATE_KEY))
+            .finalize()
+            .unwrap()
+    }
+
+    #[test]
+    fn verifies_with_only_the_public_key() {
+        let controller = issuer(TokenEncoding::Base62);
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        let pak = controller.generate_signed_key(expires_at).unwrap();
+        assert!(controller.verify_stateless(&pak).is_ok());
+
+        let public_key = Ed25519Signer::from_bytes(&PRIVATE_KEY).public_key();
+        let verifier = PublicKeyVerifier::new(&public_key, "mycompany".to_owned(), None)
+            .unwrap()
+            .with_encoding(TokenEncoding::Base62);
+        let (parsed, claims) = verifier.verify_str(&pak.to_string()).unwrap();
+        assert_eq!(parsed.short_token(), pak.short_token());
+        assert!(claims.expires_at.is_some());
+
+        // A verifier for another issuer rejects the key
+        let other = Ed25519Signer::from_bytes(&[8; 32])
+            .verifier("mycompany".to_owned(), None)
+            .with_encoding(TokenEncoding::Base62);
+        assert_eq!(
+            other.verify_key(&pak).unwrap_err(),
+            StatelessError::InvalidSignature
+        );
+    }
+
+    #[test]
+    fn rejects_malformed_and_expired_keys() {
+        let controller = issuer(TokenEncoding::Base58);
+        let verifier =
+            Ed25519Signer::from_bytes(&PRIVATE_KEY).verifier("mycompany".to_owned(), None);
+
+        assert_eq!(
+            verifier.verify_str("mycompany_short").unwrap_err(),
+            StatelessError::Malformed
+        );
+        let expired = controller
+            .generate_signed_key(SystemTime::now() - Duration::from_secs(60))
+            .unwrap();
+        assert!(matches!(
+            verifier.verify_key(&expired),
+            Err(StatelessError::Expired(_))
+        ));
+
+        // HMAC signed tokens are a different algorithm, and a different length
+        let hmac = PrefixedApiKeyController::<_, Sha256>::configure()
+            .prefix("mycompany".to_owned())
+            .rng(OsRng)
+            .short_token_length(8)
+            .sign_keys(HmacSigner::new(&[3; 32]).unwrap())
+            .finalize()
+            .unwrap();
+        assert_eq!(
+            verifier.verify_key(&hmac.generate_key()).unwrap_err(),
+            StatelessError::Malformed
+        );
+    }
+
+    #[test]
+    fn enforces_the_issuers_prefix_and_environment() {
+        let controller = PrefixedApiKeyController::<_, Sha256>::configure()
+            .prefix("mycompany".to_owned())
+            .rng(OsRng)
+            .short_token_length(8)
+            .environment("live".to_owned())
+            .sign_keys(Ed25519Signer::from_bytes(&PRIVATE_KEY))
+            .finalize()
+            .unwrap();
+        let pak = controller
+            .generate_signed_key(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        let signer = Ed25519Signer::from_bytes(&PRIVATE_KEY);
+
+        let accepted = signer.verifier("mycompany".to_owned(), Some("live".to_owned()));
+        assert!(accepted.verify_key(&pak).is_ok());
+
+        for rejected in [
+            signer.verifier("othercompany".to_owned(), Some("live".to_owned())),
+            signer.verifier("mycompany".to_owned(), Some("test".to_owned())),
+            signer.verifier("mycompany".to_owned(), None),
+        ] {
+            assert_eq!(
+                rejected.verify_key(&pak).unwrap_err(),
+                StatelessError::WrongIssuer
+            );
+        }
+    }
+
+    #[test]
+    fn non_expiring_keys_need_an_explicit_opt_in() {
+        let controller = issuer(TokenEncoding::Base58);
+        let pak = controller.generate_key();
+        let verifier =
+            Ed25519Signer::from_bytes(&PRIVATE_KEY).verifier("mycompany".to_owned(), None);
+
+        assert_eq!(
+            verifier.verify_key(&pak).unwrap_err(),
+            StatelessError::NeverExpires
+        );
+        let claims = verifier.allow_non_expiring().verify_key(&pak).unwrap();
+        assert_eq!(claims.expires_at, None);
+    }
+}
@@ -0,0 +1,707 @@
+use std::io::{self, BufRead, BufReader, Read};
+
+use crate::key_format::KeyFormats;
+use crate::prefixed_api_key::PrefixedApiKey;
+use crate::token_encoding::{LengthUnit, TokenEncoding};
+
+// Keys end at the first character which can't be part of a word, matching the
+// boundaries the scanner uses
+const BEFORE_SECRET: &str = r"\A|[^0-9A-Za-z_]";
+const AFTER_SECRET: &str = r"\z|[^0-9A-Za-z_]";
+
+fn is_word(byte: u8) -> bool {
+    byte.is_ascii_alphanumeric() || byte == b'_'
+}
+
+/// A key found by a [KeyScanner]
+#[derive(Debug)]
+pub struct ScanMatch {
+    /// The byte offset of the start of the key in the scanned input
+    pub offset: usize,
+    /// The length of the key in bytes
+    pub length: usize,
+    /// The key that was found. Its `Debug` output masks the long token, but it should
+    /// still be handled carefully since it's very likely a live secret.
+    pub key: PrefixedApiKey,
+    /// The key with its long token masked, which is safe to include in reports
+    pub preview: String,
+}
+
+/// A custom pattern for [GitHub secret scanning](https://docs.github.com/en/code-security/secret-scanning),
+/// with the fields GitHub asks for when defining one.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct GitHubPattern {
+    pub name: String,
+    /// The regex matching the key itself
+    pub secret_format: String,
+    /// The regex which must match immediately before the key
+    pub before_secret: String,
+    /// The regex which must match immediately after the key
+    pub after_secret: String,
+}
+
+/// Finds keys matching a controller's configuration in arbitrary text, like logs, git
+/// diffs or config files. Candidates must have the configured prefix, a short token
+/// with the configured short token prefix, and short and long tokens made of the
+/// configured [TokenEncoding]'s characters, with lengths that the configured byte
+/// lengths can encode to. Only tokens in the encoding's canonical case are found.
+///
+/// Keys are found in every configured [KeyFormats] version which has a
+/// [token_separator](crate::KeyFormat::token_separator), like `mycompany_v2_<short>_<long>`.
+///
+/// Scanners are usually created with
+/// [PrefixedApiKeyController::scanner](crate::PrefixedApiKeyController::scanner).
+#[derive(Clone, Debug)]
+pub struct KeyScanner {
+    prefix: String,
+    environment: Option<String>,
+    short_token_prefix: Option<String>,
+    short_token_length: usize,
+    long_token_length: usize,
+    encoding: TokenEncoding,
+    length_unit: LengthUnit,
+    formats: KeyFormats,
+}
+
+/// How keys in one of the scanner's formats start, and what separates their tokens
+struct Layout {
+    start: String,
+    separator: u8,
+}
+
+impl KeyScanner {
+    /// Creates a scanner for keys with the given prefixes, where the short and long
+    /// token lengths are the number of random bytes, as given to the controller. Tokens
+    /// are expected to be base58 encoded.
+    pub fn new(
+        prefix: String,
+        short_token_prefix: Option<String>,
+        short_token_length: usize,
+        long_token_length: usize,
+    ) -> KeyScanner {
+        KeyScanner {
+            prefix,
+            environment: None,
+            short_token_prefix,
+            short_token_length,
+            long_token_length,
+            encoding: TokenEncoding::Base58,
+            length_unit: LengthUnit::Bytes,
+            formats: KeyFormats::new(),
+        }
+    }
+
+    /// Finds keys in the given formats, rather than only version 1
+    pub fn with_key_formats(mut self, formats: KeyFormats) -> KeyScanner {
+        self.formats = formats;
+        self
+    }
+
+    /// Only finds keys with tokens in the given encoding
+    pub fn with_encoding(mut self, encoding: TokenEncoding) -> KeyScanner {
+        self.encoding = encoding;
+        self
+    }
+
+    /// Only finds keys with the given environment segment
+    pub fn with_environment(mut self, environment: String) -> KeyScanner {
+        self.environment = Some(environment);
+        self
+    }
+
+    /// Treats the short and long token lengths as the given unit
+    pub fn with_length_unit(mut self, length_unit: LengthUnit) -> KeyScanner {
+        self.length_unit = length_unit;
+        self
+    }
+
+    /// The shortest and longest short token
+    fn short_token_range(&self) -> (usize, usize) {
+        // A short token prefix is padded with random characters and then truncated to
+        // exactly the configured length
+        match (&self.short_token_prefix, self.length_unit) {
+            (Some(_), _) | (None, LengthUnit::Characters) => {
+                (self.short_token_length, self.short_token_length)
+            }
+            (None, LengthUnit::Bytes) => self.encoding.length_range(self.short_token_length),
+        }
+    }
+
+    /// The shortest and longest long token
+    fn long_token_range(&self) -> (usize, usize) {
+        match self.length_unit {
+            LengthUnit::Bytes => self.encoding.length_range(self.long_token_length),
+            LengthUnit::Characters => (self.long_token_length, self.long_token_length),
+        }
+    }
+
+    /// The layouts of the scannable formats, newest first so version markers are tried
+    /// before version 1 could read them as a short token
+    fn layouts(&self) -> Vec<Layout> {
+        let environment = match &self.environment {
+            Some(environment) => format!("{}_", environment),
+            None => String::new(),
+        };
+
+        self.formats
+            .iter()
+            .rev()
+            .filter_map(|format| {
+                let separator = format.token_separator()?;
+                if !separator.is_ascii() || separator.is_ascii_alphanumeric() {
+                    return None;
+                }
+                let marker = match format.version() {
+                    1 => String::new(),
+                    version => format!("v{}_", version),
+                };
+                Some(Layout {
+                    start: format!("{}_{}{}", self.prefix, marker, environment),
+                    separator: separator as u8,
+                })
+            })
+            .collect()
+    }
+
+    /// Finds all keys in the string. Offsets are byte offsets into the string.
+    pub fn scan_str(&self, text: &str) -> Vec<ScanMatch> {
+        self.scan_bytes(text.as_bytes())
+    }
+
+    /// Finds all keys in the bytes, which don't need to be valid utf-8.
+    pub fn scan_bytes(&self, bytes: &[u8]) -> Vec<ScanMatch> {
+        let layouts = self.layouts();
+        let needle = format!("{}_", self.prefix);
+        let needle = needle.as_bytes();
+
+        let mut matches = Vec::new();
+        let mut position = 0;
+        while let Some(found) = find(&bytes[position..], needle) {
+            let start = position + found;
+            position = start + 1;
+
+            // Skip prefixes which are only the tail of a longer word
+            if start > 0 && is_word(bytes[start - 1]) {
+                continue;
+            }
+
+            let found = layouts
+                .iter()
+                .filter(|layout| bytes[start..].starts_with(layout.start.as_bytes()))
+                .find_map(|layout| self.match_at(bytes, start, layout));
+            if let Some(found) = found {
+                position = start + found.length;
+                matches.push(found);
+            }
+        }
+        matches
+    }
+
+    /// Finds all keys in the reader, which is read a line at a time. Offsets are byte
+    /// offsets from the start of the stream.
+    pub fn scan_reader<R: Read>(&self, reader: R) -> io::Result<Vec<ScanMatch>> {
+        let mut reader = BufReader::new(reader);
+        let mut line = Vec::new();
+        let mut offset = 0;
+        let mut matches = Vec::new();
+
+        loop {
+            line.clear();
+            let read = reader.read_until(b'\n', &mut line)?;
+            if read == 0 {
+                return Ok(matches);
+            }
+
+            matches.extend(self.scan_bytes(&line).into_iter().map(|mut found| {
+                found.offset += offset;
+                found
+            }));
+            offset += read;
+        }
+    }
+
+    /// A regex matching exactly the keys this scanner finds. It doesn't check what
+    /// surrounds the key, so use it with boundaries like `(?:\A|[^0-9A-Za-z_])` to
+    /// avoid matching keys embedded in longer words.
+    pub fn regex(&self) -> String {
+        let class = self.encoding.regex_class();
+        let short_token = match &self.short_token_prefix {
+            Some(short_token_prefix) => {
+                let length = self.short_token_length;
+                let literal: String = short_token_prefix.chars().take(length).collect();
+                let random = length - literal.chars().count();
+                format!("{}{}", escape(&literal), repeat(class, (random, random)))
+            }
+            None => repeat(class, self.short_token_range()),
+        };
+        let long_token = repeat(class, self.long_token_range());
+
+        let patterns: Vec<String> = self
+            .layouts()
+            .iter()
+            .map(|layout| {
+                let separator = (layout.separator as char).to_string();
+                format!(
+                    "{}{}{}{}",
+                    escape(&layout.start),
+                    short_token,
+                    escape(&separator),
+                    long_token
+                )
+            })
+            .collect();
+        match patterns.len() {
+            1 => patterns[0].clone(),
+            _ => format!("(?:{})", patterns.join("|")),
+        }
+    }
+
+    /// A custom pattern to register with GitHub secret scanning, or to submit to the
+    /// secret scanning partner program.
+    pub fn github_pattern(&self, name: &str) -> GitHubPattern {
+        GitHubPattern {
+            name: name.to_owned(),
+            secret_format: self.regex(),
+            before_secret: BEFORE_SECRET.to_owned(),
+            after_secret: AFTER_SECRET.to_owned(),
+        }
+    }
+
+    /// A [gitleaks](https://github.com/gitleaks/gitleaks) rule, to add to a
+    /// `.gitleaks.toml` config file.
+    pub fn gitleaks_rule(&self, id: &str) -> String {
+        let toml_string = |value: &str| value.replace('\\', "\\\\").replace('"', "\\\"");
+        format!(
+            "[[rules]]\n\
+             id = \"{}\"\n\
+             description = \"{} api key\"\n\
+             regex = \"{}\"\n\
+             secretGroup = 1\n\
+             keywords = [\"{}_\"]\n",
+            toml_string(id),
+            toml_string(&self.prefix),
+            toml_string(&self.bounded_regex()),
+            toml_string(&self.prefix)
+        )
+    }
+
+    /// A [trufflehog](https://github.com/trufflesecurity/trufflehog) custom detector,
+    /// to use as the detector config file.
+    pub fn trufflehog_detector(&self, name: &str) -> String {
+        let yaml_string = |value: &str| format!("'{}'", value.replace('\'', "''"));
+        format!(
+            "detectors:\n  \
+             - name: {}\n    \
+             keywords:\n      \
+             - {}\n    \
+             regex:\n      \
+             api_key: {}\n",
+            yaml_string(name),
+            yaml_string(&format!("{}_", self.prefix)),
+            yaml_string(&self.bounded_regex())
+        )
+    }
+
+    /// The regex surrounded by boundaries, with the key in the first capture group
+    fn bounded_regex(&self) -> String {
+        format!(
+            "(?:{})({})(?:{})",
+            BEFORE_SECRET.replace(r"\A", "^"),
+            self.regex(),
+            AFTER_SECRET.replace(r"\z", "$")
+        )
+    }
+
+    /// Checks whether the bytes following the layout's start at `start` form a valid key
+    fn match_at(&self, bytes: &[u8], start: usize, layout: &Layout) -> Option<ScanMatch> {
+        let mut end = start + layout.start.len();
+
+        let short_start = end;
+        if let Some(short_token_prefix) = &self.short_token_prefix {
+            let expected: String = short_token_prefix
+                .chars()
+                .take(self.short_token_length)
+                .collect();
+            if !bytes[end..].starts_with(expected.as_bytes()) {
+                return None;
+            }
+            end += expected.len();
+        }
+        end += self.token_length(&bytes[end..]);
+        let short_end = end;
+
+        if bytes.get(end) != Some(&layout.separator) {
+            return None;
+        }
+        end += 1;
+
+        let long_start = end;
+        end += self.token_length(&bytes[end..]);
+        let long_end = end;
+
+        if matches!(bytes.get(end), Some(byte) if is_word(*byte)) {
+            return None;
+        }
+
+        let short_token = std::str::from_utf8(&bytes[short_start..short_end]).ok()?;
+        let long_token = std::str::from_utf8(&bytes[long_start..long_end]).ok()?;
+        let in_range = |token: &str, (min, max): (usize, usize)| {
+            let length = token.chars().count();
+            min <= length && length <= max
+        };
+        if !in_range(short_token, self.short_token_range())
+            || !in_range(long_token, self.long_token_range())
+        {
+            return None;
+        }
+
+        let key = std::str::from_utf8(&bytes[start..end]).ok()?;
+        Some(ScanMatch {
+            offset: start,
+            length: end - start,
+            preview: format!(
+                "{}{}{}***",
+                layout.start, short_token, layout.separator as char
+            ),
+            key: self.formats.parse(key).ok()?,
+        })
+    }
+
+    /// The number of leading token characters, or zero if the run of alphanumeric
+    /// characters contains any which aren't in the encoding's alphabet.
+    fn token_length(&self, bytes: &[u8]) -> usize {
+        let length = bytes
+            .iter()
+            .take_while(|byte| byte.is_ascii_alphanumeric())
+            .count();
+        if bytes[..length]
+            .iter()
+            .all(|byte| self.encoding.contains(*byte))
+        {
+            length
+        } else {
+            0
+        }
+    }
+}
+
+/// Escapes the characters which have special meaning in regexes
+fn escape(literal: &str) -> String {
+    let mut escaped = String::with_capacity(literal.len());
+    for c in literal.chars() {
+        if r"\.+*?()|[]{}^$#&-~".contains(c) {
+            escaped.push('\\');
+        }
+        escaped.push(c);
+    }
+    escaped
+}
+
+fn repeat(class: &str, (min, max): (usize, usize)) -> String {
+    match (min, max) {
+        (0, 0) => String::new(),
+        (1, 1) => class.to_owned(),
+        (min, max) if min == max => format!("{}{{{}}}", class, min),
+        (min, max) => format!("{}{{{},{}}}", class, min, max),
+    }
+}
+
+fn find(haystack: &[u8], needle: &[u8]) -> Option<usize> {
+    if needle.is_empty() || haystack.len() < needle.len() {
+        return None;
+    }
+    haystack
+        .windows(needle.len())
+        .position(|window| window == needle)
+}
+
+#[cfg(test)]
+mod scan_tests {
+    use rand::rngs::OsRng;
+    use regex::Regex;
+    use sha2::Sha256;
+
+    use super::KeyScanner;
+    use crate::{
+        KeyFormat, LengthUnit, PrefixedApiKey, PrefixedApiKeyController, PrefixedApiKeyError,
+        TokenEncoding,
+    };
+
+    #[test]
+    fn finds_keys_with_offsets() {
+        let controller =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        let first = controller.generate_key();
+        let second = controller.generate_key();
+        let text = format!(
+            "token={}\nAuthorization: Bearer \"{}\" ok",
+            first.to_string(),
+            second.to_string()
+        );
+
+        let matches = controller.scanner().scan_str(&text);
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].offset, 6);
+        assert_eq!(matches[0].key.to_string(), first.to_string());
+        assert_eq!(
+            &text[matches[1].offset..matches[1].offset + matches[1].length],
+            second.to_string()
+        );
+        assert_eq!(
+            matches[1].preview,
+            format!("mycompany_{}_***", second.short_token())
+        );
+    }
+
+    #[test]
+    fn skips_invalid_candidates() {
+        let scanner = KeyScanner::new("mycompany".to_owned(), None, 8, 24);
+        let long = "A".repeat(30);
+
+        for text in [
+            format!("mycompany_BRTRKFsL_{}0", &long[..29]),
+            format!("mycompany_BRTRKFsL_{}", &long[..10]),
+            format!("mycompany_BRT_{}", long),
+            format!("notmycompany_BRTRKFsL_{}", long),
+            format!("mycompany_BRTRKFsL_{}_extra", long),
+            "mycompany_BRTRKFsL".to_owned(),
+        ] {
+            assert!(scanner.scan_str(&text).is_empty(), "{}", text);
+        }
+
+        let text = format!("(mycompany_BRTRKFsL_{})", long);
+        assert_eq!(scanner.scan_str(&text).len(), 1);
+    }
+
+    #[test]
+    fn checks_short_token_prefix() {
+        let controller = PrefixedApiKeyController::<_, Sha256>::new(
+            "mycompany".to_owned(),
+            OsRng,
+            Some("abc".to_owned()),
+            8,
+            24,
+        );
+        let pak = controller.generate_key();
+        let scanner = controller.scanner();
+
+        assert_eq!(scanner.scan_str(&pak.to_string()).len(), 1);
+
+        let other = format!(
+            "mycompany_xyz{}_{}",
+            &pak.short_token()[3..],
+            pak.long_token()
+        );
+        assert!(scanner.scan_str(&other).is_empty());
+    }
+
+    #[test]
+    fn scans_readers() {
+        let controller =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        let pak = controller.generate_key();
+        let input = format!(
+            "first line\n\u{fffd}\u{0}binary\nkey: {}\n",
+            pak.to_string()
+        );
+
+        let matches = controller.scanner().scan_reader(input.as_bytes()).unwrap();
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].offset, input.find("mycompany").unwrap());
+        assert_eq!(matches[0].key.short_token(), pak.short_token());
+    }
+
+    #[test]
+    fn matches_environment_segment() {
+        let controller = PrefixedApiKeyController::<_, Sha256>::configure()
+            .prefix("mycompany".to_owned())
+            .rng(OsRng)
+            .default_lengths()
+            .environment("live".to_owned())
+            .finalize()
+            .unwrap();
+        let pak = controller.generate_key();
+        let other =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24)
+                .generate_key();
+        let text = format!("{} {}", other.to_string(), pak.to_string());
+
+        let matches = controller.scanner().scan_str(&text);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].key.to_string(), pak.to_string());
+        assert_eq!(
+            matches[0].preview,
+            format!("mycompany_live_{}_***", pak.short_token())
+        );
+        assert!(controller.scanning_regex().starts_with("mycompany_live_["));
+    }
+
+    #[test]
+    fn regex_matches_generated_keys() {
+        let controller =
+            PrefixedApiKeyController::<_, Sha256>::new("my.co".to_owned(), OsRng, None, 8, 24);
+        assert_eq!(
+            controller.scanning_regex(),
+            r"my\.co_[1-9A-HJ-NP-Za-km-z]{8,11}_[1-9A-HJ-NP-Za-km-z]{24,33}"
+        );
+
+        let regex = Regex::new(&format!("^{}$", controller.scanning_regex())).unwrap();
+        for _ in 0..100 {
+            assert!(regex.is_match(&controller.generate_key().to_string()));
+        }
+        assert!(!regex.is_match("myXco_BRTRKFsL_51FwqftsmMDHHbJAMEXXHCgG"));
+        assert!(!regex.is_match("my.co_BRTRKFsL_51FwqftsmMDHHbJAMEXXHCgO"));
+    }
+
+    #[test]
+    fn regex_includes_short_token_prefix() {
+        let scanner = KeyScanner::new("mycompany".to_owned(), Some("ab".to_owned()), 8, 24);
+        assert_eq!(
+            scanner.regex(),
+            "mycompany_ab[1-9A-HJ-NP-Za-km-z]{6}_[1-9A-HJ-NP-Za-km-z]{24,33}"
+        );
+
+        let scanner = KeyScanner::new("mycompany".to_owned(), Some("abcdefghij".to_owned()), 8, 24);
+        assert!(scanner.regex().starts_with("mycompany_abcdefgh_["));
+    }
+
+    #[test]
+    fn exports_patterns() {
+        let scanner = KeyScanner::new("mycompany".to_owned(), None, 8, 24);
+
+        let pattern = scanner.github_pattern("MyCompany API key");
+        assert_eq!(pattern.secret_format, scanner.regex());
+        let regex = Regex::new(&format!(
+            "(?:{})({})(?:{})",
+            pattern.before_secret, pattern.secret_format, pattern.after_secret
+        ))
+        .unwrap();
+        let key = "mycompany_BRTRKFsL_51FwqftsmMDHHbJAMEXXHCgG";
+        let text = format!("key=\"{}\"", key);
+        assert_eq!(&regex.captures(&text).unwrap()[1], key);
+        assert!(regex.captures(&format!("x{}", key)).is_none());
+
+        let rule = scanner.gitleaks_rule("mycompany-api-key");
+        assert!(rule.starts_with("[[rules]]\nid = \"mycompany-api-key\"\n"));
+        assert!(rule.contains(&format!(
+            "regex = \"(?:^|[^0-9A-Za-z_])({})(?:$|[^0-9A-Za-z_])\"",
+            scanner.regex()
+        )));
+        assert!(rule.contains("keywords = [\"mycompany_\"]"));
+
+        let detector = scanner.trufflehog_detector("mycompany");
+        assert!(detector.starts_with("detectors:\n  - name: 'mycompany'\n"));
+        assert!(detector.contains("      - 'mycompany_'\n"));
+        assert!(detector.contains(&format!(
+            "      api_key: '(?:^|[^0-9A-Za-z_])({})",
+            scanner.regex()
+        )));
+    }
+
+    #[test]
+    fn matches_token_encoding() {
+        let controller = PrefixedApiKeyController::<_, Sha256>::configure()
+            .prefix("mycompany".to_owned())
+            .rng(OsRng)
+            .default_lengths()
+            .token_encoding(TokenEncoding::Crockford32)
+            .finalize()
+            .unwrap();
+        assert_eq!(
+            controller.scanning_regex(),
+            "mycompany_[0-9A-HJKMNP-TV-Z]{13}_[0-9A-HJKMNP-TV-Z]{39}"
+        );
+
+        let pak = controller.generate_key();
+        let base58 =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24)
+                .generate_key();
+        let text = format!("{} {}", base58.to_string(), pak.to_string());
+        let matches = controller.scanner().scan_str(&text);
+        assert_eq!(matches.len(), 1);
+        assert_eq!(matches[0].key.to_string(), pak.to_string());
+    }
+
+    #[test]
+    fn character_lengths_are_exact() {
+        let scanner = KeyScanner::new("mycompany".to_owned(), None, 8, 24)
+            .with_encoding(TokenEncoding::Base62)
+            .with_length_unit(LengthUnit::Characters);
+        assert_eq!(scanner.regex(), "mycompany_[0-9A-Za-z]{8}_[0-9A-Za-z]{24}");
+
+        let long = "A".repeat(24);
+        assert_eq!(
+            scanner
+                .scan_str(&format!("mycompany_BRTRKFs0_{}", long))
+                .len(),
+            1
+        );
+        assert!(scanner
+            .scan_str(&format!("mycompany_BRTRKFs0_{}A", long))
+            .is_empty());
+    }
+    /// Version 1's layout with a different separator between the tokens
+    struct DashedFormat;
+
+    impl KeyFormat for DashedFormat {
+        fn version(&self) -> u32 {
+            2
+        }
+
+        fn format_body(&self, pak: &PrefixedApiKey) -> String {
+            format!("{}-{}", pak.short_token(), pak.long_token())
+        }
+
+        fn parse_body(
+            &self,
+            prefix: &str,
+            body: &str,
+        ) -> Result<PrefixedApiKey, PrefixedApiKeyError> {
+            let (short, long) = body
+                .split_once('-')
+                .ok_or(PrefixedApiKeyError::WrongNumberOfParts(2))?;
+            Ok(PrefixedApiKey::new(
+                prefix.to_owned(),
+                short.to_owned(),
+                long.to_owned(),
+            ))
+        }
+
+        fn token_separator(&self) -> Option<char> {
+            Some('-')
+        }
+    }
+
+    #[test]
+    fn finds_keys_in_every_format() {
+        let controller = PrefixedApiKeyController::<_, Sha256>::configure()
+            .prefix("mycompany".to_owned())
+            .rng(OsRng)
+            .default_lengths()
+            .key_format(DashedFormat)
+            .finalize()
+            .unwrap();
+        let v2 = controller.generate_key();
+        let v1 = PrefixedApiKey::new(
+            "mycompany".to_owned(),
+            v2.short_token().to_owned(),
+            v2.long_token().to_owned(),
+        );
+        assert!(v2.to_string().starts_with("mycompany_v2_"));
+
+        let text = format!("{} {}", v2.to_string(), v1.to_string());
+        let matches = controller.scanner().scan_str(&text);
+        assert_eq!(matches.len(), 2);
+        assert_eq!(matches[0].key.to_string(), v2.to_string());
+        assert_eq!(matches[0].key.version(), 2);
+        assert_eq!(
+            matches[0].preview,
+            format!("mycompany_v2_{}-***", v2.short_token())
+        );
+        assert_eq!(matches[1].key.to_string(), v1.to_string());
+
+        let regex = Regex::new(&format!("^{}$", controller.scanning_regex())).unwrap();
+        assert!(regex.is_match(&v2.to_string()));
+        assert!(regex.is_match(&v1.to_string()));
+    }
+}
@@ -0,0 +1,265 @@
+use std::collections::BTreeSet;
+use std::error::Error;
+use std::fmt;
+use std::str::FromStr;
+
+use crate::auth::AuthOutcome;
+use crate::store::ApiKeyRecord;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ScopeError {
+    /// The scope string was empty, contained whitespace or had an empty segment
+    InvalidScope(String),
+    /// Scopes were required from an outcome that wasn't [Authenticated](AuthOutcome::Authenticated)
+    /// or [Superseded](AuthOutcome::Superseded)
+    NotAuthenticated,
+    /// The key was authenticated, but isn't granted these scopes
+    MissingScopes(Vec<Scope>),
+}
+
+impl fmt::Display for ScopeError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            ScopeError::InvalidScope(scope) => write!(f, "invalid scope {:?}", scope),
+            ScopeError::NotAuthenticated => write!(f, "key was not authenticated"),
+            ScopeError::MissingScopes(scopes) => {
+                let scopes: Vec<&str> = scopes.iter().map(Scope::as_str).collect();
+                write!(f, "key is missing required scopes: {}", scopes.join(" "))
+            }
+        }
+    }
+}
+
+impl Error for ScopeError {}
+
+/// A permission granted to a key, made of `:` separated segments like `read:billing`.
+///
+/// A `*` segment is a wildcard. At the end of a scope it matches one or more
+/// remaining segments, so `billing:*` grants both `billing:read` and
+/// `billing:invoices:read`. Anywhere else it matches exactly one segment, so
+/// `*:billing` grants `read:billing` but not `billing`.
+#[derive(Debug, Clone, PartialEq, Eq, PartialOrd, Ord, Hash)]
+pub struct Scope(String);
+
+impl Scope {
+    /// Parses and validates a scope string
+    pub fn parse(scope: &str) -> Result<Scope, ScopeError> {
+        let valid = !scope.is_empty()
+            && !scope.chars().any(char::is_whitespace)
+            && scope.split(':').all(|segment| !segment.is_empty());
+
+        if !valid {
+            return Err(ScopeError::InvalidScope(scope.to_owned()));
+        }
+
+        Ok(Scope(scope.to_owned()))
+    }
+
+    pub fn as_str(&self) -> &str {
+        &self.0
+    }
+
+    /// Whether holding this scope grants the `required` scope
+    pub fn grants(&self, required: &Scope) -> bool {
+        let granted: Vec<&str> = self.0.split(':').collect();
+        let required: Vec<&str> = required.0.split(':').collect();
+
+        for (i, segment) in granted.iter().enumerate() {
+            let is_last = i == granted.len() - 1;
+            match required.get(i) {
+                None => return false,
+                Some(_) if *segment == "*" && is_last => return true,
+                Some(_) if *segment == "*" => continue,
+                Some(other) if other == segment => continue,
+                Some(_) => return false,
+            }
+        }
+
+        granted.len() == required.len()
+    }
+}
+
+impl fmt::Display for Scope {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        write!(f, "{}", self.0)
+    }
+}
+
+impl FromStr for Scope {
+    type Err = ScopeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        Scope::parse(s)
+    }
+}
+
+/// The set of [Scope]s granted to a key. It's displayed and parsed as a space
+/// separated list (like OAuth scopes) so it can be stored in a single column.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct ScopeSet(BTreeSet<Scope>);
+
+impl ScopeSet {
+    pub fn new() -> ScopeSet {
+        ScopeSet::default()
+    }
+
+    /// Parses a space separated list of scopes
+    pub fn parse(scopes: &str) -> Result<ScopeSet, ScopeError> {
+        scopes.split_whitespace().map(Scope::parse).collect()
+    }
+
+    pub fn insert(&mut self, scope: Scope) -> bool {
+        self.0.insert(scope)
+    }
+
+    pub fn remove(&mut self, scope: &Scope) -> bool {
+        self.0.remove(scope)
+    }
+
+    pub fn len(&self) -> usize {
+        self.0.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.0.is_empty()
+    }
+
+    pub fn iter(&self) -> impl Iterator<Item = &Scope> {
+        self.0.iter()
+    }
+
+    /// Whether any scope in the set grants the `required` scope
+    pub fn grants(&self, required: &Scope) -> bool {
+        self.0.iter().any(|scope| scope.grants(required))
+    }
+
+    /// Returns the scopes in `required` which this set doesn't grant
+    pub fn missing<'a, I>(&self, required: I) -> Vec<Scope>
+    where
+        I: IntoIterator<Item = &'a Scope>,
+    {
+        required
+            .into_iter()
+            .filter(|scope| !self.grants(scope))
+            .cloned()
+            .collect()
+    }
+}
+
+impl fmt::Display for ScopeSet {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let scopes: Vec<&str> = self.0.iter().map(Scope::as_str).collect();
+        write!(f, "{}", scopes.join(" "))
+    }
+}
+
+impl FromStr for ScopeSet {
+    type Err = ScopeError;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        ScopeSet::parse(s)
+    }
+}
+
+impl FromIterator<Scope> for ScopeSet {
+    fn from_iter<I: IntoIterator<Item = Scope>>(iter: I) -> Self {
+        ScopeSet(iter.into_iter().collect())
+    }
+}
+
+/// Checks that the outcome is [Authenticated](AuthOutcome::Authenticated) or
+/// [Superseded](AuthOutcome::Superseded), since superseded keys stay valid until their
+/// rotation grace period ends, and that the key was granted every one of the `required`
+/// scopes, returning the authenticated record if so.
+pub fn require_scopes<'a, S: AsRef<str>>(
+    outcome: &'a AuthOutcome,
+    required: &[S],
+) -> Result<&'a ApiKeyRecord, ScopeError> {
+    let required = required
+        .iter()
+        .map(|scope| Scope::parse(scope.as_ref()))
+        .collect::<Result<Vec<Scope>, ScopeError>>()?;
+
+    let record = outcome.record().ok_or(ScopeError::NotAuthenticated)?;
+
+    let missing = record.scopes.missing(&required);
+    if !missing.is_empty() {
+        return Err(ScopeError::MissingScopes(missing));
+    }
+
+    Ok(record)
+}
+
+#[cfg(test)]
+mod scope_tests {
+    use super::{require_scopes, Scope, ScopeError, ScopeSet};
+    use crate::{ApiKeyRecord, AuthOutcome};
+
+    fn grants(granted: &str, required: &str) -> bool {
+        Scope::parse(granted)
+            .unwrap()
+            .grants(&Scope::parse(required).unwrap())
+    }
+
+    #[test]
+    fn parse_rejects_invalid_scopes() {
+        assert!(Scope::parse("").is_err());
+        assert!(Scope::parse("read billing").is_err());
+        assert!(Scope::parse("read::billing").is_err());
+        assert!(Scope::parse("billing:").is_err());
+        assert!(Scope::parse("read:billing").is_ok());
+    }
+
+    #[test]
+    fn exact_and_wildcard_matching() {
+        assert!(grants("read:billing", "read:billing"));
+        assert!(!grants("read:billing", "read:users"));
+        assert!(!grants("billing", "billing:read"));
+        assert!(!grants("billing:read", "billing"));
+
+        assert!(grants("*", "anything:at:all"));
+        assert!(grants("billing:*", "billing:read"));
+        assert!(grants("billing:*", "billing:invoices:read"));
+        assert!(!grants("billing:*", "billing"));
+        assert!(!grants("billing:*", "users:read"));
+
+        assert!(grants("*:billing", "read:billing"));
+        assert!(!grants("*:billing", "billing"));
+        assert!(!grants("*:billing", "read:billing:all"));
+    }
+
+    #[test]
+    fn scope_set_round_trips_through_string() {
+        let scopes = ScopeSet::parse("write:users  read:billing").unwrap();
+        assert_eq!(scopes.len(), 2);
+        assert_eq!(scopes.to_string(), "read:billing write:users");
+        assert_eq!(scopes.to_string().parse::<ScopeSet>().unwrap(), scopes);
+    }
+
+    #[test]
+    fn require_scopes_reports_missing_scopes() {
+        let record = ApiKeyRecord::new("short".to_owned(), "hash".to_owned())
+            .with_scopes(ScopeSet::parse("billing:*").unwrap());
+        let outcome = AuthOutcome::Authenticated(record.clone());
+
+        assert_eq!(require_scopes(&outcome, &["billing:read"]), Ok(&record));
+        assert_eq!(
+            require_scopes(&outcome, &["billing:read", "read:users"]),
+            Err(ScopeError::MissingScopes(vec![
+                Scope::parse("read:users").unwrap()
+            ]))
+        );
+        assert_eq!(
+            require_scopes(&AuthOutcome::Superseded(record.clone()), &["billing:read"]),
+            Ok(&record)
+        );
+        assert_eq!(
+            require_scopes(&AuthOutcome::UnknownKey, &["billing:read"]),
+            Err(ScopeError::NotAuthenticated)
+        );
+        assert_eq!(
+            require_scopes(&outcome, &["bad scope"]),
+            Err(ScopeError::InvalidScope("bad scope".to_owned()))
+        );
+    }
+}
@@ -0,0 +1,491 @@
+use std::collections::HashMap;
+use std::error::Error;
+use std::fmt;
+
+use base64::engine::general_purpose::STANDARD;
+use base64::Engine;
+use digest::{Digest, FixedOutputReset};
+use p256::ecdsa::signature::Verifier;
+use p256::ecdsa::{Signature, VerifyingKey};
+use p256::pkcs8::DecodePublicKey;
+use rand::RngCore;
+use serde::{Deserialize, Serialize};
+
+use crate::blocklist::Blocklist;
+use crate::controller::PrefixedApiKeyController;
+use crate::store::KeyStore;
+
+/// The request header holding the identifier of the key GitHub signed the report with
+pub const GITHUB_KEY_IDENTIFIER_HEADER: &str = "Github-Public-Key-Identifier";
+
+/// The request header holding GitHub's base64 encoded signature of the report
+pub const GITHUB_SIGNATURE_HEADER: &str = "Github-Public-Key-Signature";
+
+const REVOKED_BY: &str = "github-secret-scanning";
+
+/// Errors raised while loading the public keys used to verify secret scanning reports
+#[derive(Debug)]
+pub enum PublicKeyError {
+    /// The public key listing wasn't valid JSON, or didn't have the expected shape
+    InvalidJson(String),
+    /// The key with the given identifier wasn't a PEM encoded P-256 public key
+    InvalidKey(String),
+}
+
+impl fmt::Display for PublicKeyError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            PublicKeyError::InvalidJson(err) => write!(f, "invalid public key listing: {}", err),
+            PublicKeyError::InvalidKey(id) => write!(f, "invalid public key: {}", id),
+        }
+    }
+}
+
+impl Error for PublicKeyError {}
+
+/// Errors raised while handling a secret scanning report with
+/// [SecretScanningHandler::handle].
+#[derive(Debug)]
+pub enum SecretScanningError<S, B> {
+    /// The report was signed with a key that isn't in the [PublicKeySet]
+    UnknownPublicKey(String),
+    /// The signature was malformed, or didn't match the report
+    InvalidSignature,
+    /// The report wasn't a valid JSON list of reported tokens
+    InvalidPayload(String),
+    /// The response couldn't be serialized
+    Serialization(String),
+    Store(S),
+    Blocklist(B),
+}
+
+impl<S, B> SecretScanningError<S, B> {
+    /// Whether the error was caused by the request, rather than the key store,
+    /// blocklist or response serialization failing
+    pub fn is_client_error(&self) -> bool {
+        !matches!(
+            self,
+            SecretScanningError::Serialization(_)
+                | SecretScanningError::Store(_)
+                | SecretScanningError::Blocklist(_)
+        )
+    }
+}
+
+impl<S: fmt::Display, B: fmt::Display> fmt::Display for SecretScanningError<S, B> {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            SecretScanningError::UnknownPublicKey(id) => write!(f, "unknown public key: {}", id),
+            SecretScanningError::InvalidSignature => write!(f, "invalid signature"),
+            SecretScanningError::InvalidPayload(err) => write!(f, "invalid payload: {}", err),
+            SecretScanningError::Serialization(err) => {
+                write!(f, "couldn't serialize response: {}", err)
+            }
+            SecretScanningError::Store(err) => write!(f, "key store error: {}", err),
+            SecretScanningError::Blocklist(err) => write!(f, "blocklist error: {}", err),
+        }
+    }
+}
+
+impl<S: fmt::Debug + fmt::Display, B: fmt::Debug + fmt::Display> Error
+    for SecretScanningError<S, B>
+{
+}
+
+/// The public keys GitHub signs secret scanning reports with, by key identifier.
+///
+/// Requires the "secret-scanning" feature
+#[derive(Debug, Clone, Default)]
+pub struct PublicKeySet {
+    keys: HashMap<String, VerifyingKey>,
+}
+
+#[derive(Deserialize)]
+struct PublicKeyListing {
+    public_keys: Vec<PublicKeyEntry>,
+}
+
+#[derive(Deserialize)]
+struct PublicKeyEntry {
+    key_identifier: String,
+    key: String,
+}
+
+impl PublicKeySet {
+    pub fn new() -> PublicKeySet {
+        PublicKeySet::default()
+    }
+
+    /// Loads the keys from GitHub's public key listing, as served by
+    /// `https://api.github.com/meta/public_keys/secret_scanning`.
+    pub fn from_github_json(json: &str) -> Result<PublicKeySet, PublicKeyError> {
+        let listing: PublicKeyListing = serde_json::from_str(json)
+            .map_err(|err| PublicKeyError::InvalidJson(err.to_string()))?;
+
+        let mut keys = PublicKeySet::new();
+        for entry in listing.public_keys {
+            keys.insert_pem(entry.key_identifier, &entry.key)?;
+        }
+        Ok(keys)
+    }
+
+    /// Adds a PEM encoded P-256 public key, replacing any key with the same identifier
+    pub fn insert_pem(&mut self, key_identifier: String, pem: &str) -> Result<(), PublicKeyError> {
+        let key = VerifyingKey::from_public_key_pem(pem)
+            .map_err(|_| PublicKeyError::InvalidKey(key_identifier.clone()))?;
+        self.keys.insert(key_identifier, key);
+        Ok(())
+    }
+
+    pub fn len(&self) -> usize {
+        self.keys.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.keys.is_empty()
+    }
+
+    /// Checks the base64 encoded, DER formatted ECDSA signature of the body, as sent in
+    /// the [GITHUB_SIGNATURE_HEADER].
+    fn verify<S, B>(
+        &self,
+        key_identifier: &str,
+        signature: &str,
+        body: &[u8],
+    ) -> Result<(), SecretScanningError<S, B>> {
+        let key = self
+            .keys
+            .get(key_identifier)
+            .ok_or_else(|| SecretScanningError::UnknownPublicKey(key_identifier.to_owned()))?;
+        let signature = STANDARD
+            .decode(signature.trim())
+            .ok()
+            .and_then(|der| Signature::from_der(&der).ok())
+            .ok_or(SecretScanningError::InvalidSignature)?;
+
+        key.verify(body, &signature)
+            .map_err(|_| SecretScanningError::InvalidSignature)
+    }
+}
+
+/// A token GitHub found in a public location
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct SecretScanningReport {
+    pub token: String,
+    #[serde(rename = "type")]
+    pub token_type: String,
+    /// Where the token was found
+    #[serde(default)]
+    pub url: Option<String>,
+    /// Which service the token was found on, like `content` or `gist`
+    #[serde(default)]
+    pub source: Option<String>,
+}
+
+/// Whether a reported token is one of ours
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SecretScanningLabel {
+    TruePositive,
+    FalsePositive,
+}
+
+/// The response GitHub expects for each reported token
+#[derive(Debug, Clone, PartialEq, Eq, Serialize)]
+pub struct SecretScanningVerdict {
+    pub token_raw: String,
+    pub token_type: String,
+    pub label: SecretScanningLabel,
+}
+
+/// Handles the reports GitHub's secret scanning partner program sends when it finds
+/// leaked keys, independent of any web framework.
+///
+/// Reports are only trusted once their signature has been verified. Each reported
+/// token that matches an issued key is revoked in the blocklist, and the response
+/// labels it a true positive. Everything else is labelled a false positive.
+///
+/// Requires the "secret-scanning" feature
+#[derive(Debug)]
+pub struct SecretScanningHandler<R, D, S, B>
+where
+    R: RngCore + Clone,
+    D: Digest + FixedOutputReset,
+{
+    controller: PrefixedApiKeyController<R, D>,
+    store: S,
+    blocklist: B,
+    public_keys: PublicKeySet,
+}
+
+impl<R, D, S, B> SecretScanningHandler<R, D, S, B>
+where
+    R: RngCore + Clone,
+    D: Digest + FixedOutputReset,
+    S: KeyStore,
+    B: Blocklist,
+{
+    pub fn new(
+        controller: PrefixedApiKeyController<R, D>,
+        store: S,
+        blocklist: B,
+        public_keys: PublicKeySet,
+    ) -> SecretScanningHandler<R, D, S, B> {
+        SecretScanningHandler {
+            controller,
+            store,
+            blocklist,
+            public_keys,
+        }
+    }
+
+    /// Replaces the public keys, for when GitHub rotates its signing keys
+    pub fn set_public_keys(&mut self, public_keys: PublicKeySet) {
+        self.public_keys = public_keys;
+    }
+
+    /// Verifies and handles the raw body of a report, along with the values of the
+    /// [GITHUB_KEY_IDENTIFIER_HEADER] and [GITHUB_SIGNATURE_HEADER] headers. Returns
+    /// the JSON body to respond with.
+    pub fn handle(
+        &self,
+        key_identifier: &str,
+        signature: &str,
+        body: &[u8],
+    ) -> Result<String, SecretScanningError<S::Error, B::Error>> {
+        self.public_keys.verify(key_identifier, signature, body)?;
+
+        let reports: Vec<SecretScanningReport> = serde_json::from_slice(body)
+            .map_err(|err| SecretScanningError::InvalidPayload(err.to_string()))?;
+        let verdicts = self.verdicts(&reports)?;
+
+        serde_json::to_string(&verdicts)
+            .map_err(|err| SecretScanningError::Serialization(err.to_string()))
+    }
+
+    /// Labels and revokes the reported tokens. The reports must already have been
+    /// verified, since true positives are revoked.
+    pub fn verdicts(
+        &self,
+        reports: &[SecretScanningReport],
+    ) -> Result<Vec<SecretScanningVerdict>, SecretScanningError<S::Error, B::Error>> {
+        reports
+            .iter()
+            .map(|report| {
+                let label = if self.revoke_if_issued(report)? {
+                    SecretScanningLabel::TruePositive
+                } else {
+                    SecretScanningLabel::FalsePositive
+                };
+                Ok(SecretScanningVerdict {
+                    token_raw: report.token.clone(),
+                    token_type: report.token_type.clone(),
+                    label,
+                })
+            })
+            .collect()
+    }
+
+    /// Revokes the reported token if it's a key we issued, returning whether it was
+    fn revoke_if_issued(
+        &self,
+        report: &SecretScanningReport,
+    ) -> Result<bool, SecretScanningError<S::Error, B::Error>> {
+        let pak = match self.controller.parse_key(&report.token) {
+            Ok(pak) if pak.prefix() == self.controller.prefix() => pak,
+            _ => return Ok(false),
+        };
+
+        let record = match self
+            .store
+            .get(pak.short_token())
+            .map_err(SecretScanningError::Store)?
+        {
+            Some(record) => record,
+            None => return Ok(false),
+        };
+        if !self.controller.check_hash(&pak, &record.hash) {
+            return Ok(false);
+        }
+
+        // Keep the original revocation if the key was already revoked
+        if !self
+            .blocklist
+            .is_blocked(pak.short_token())
+            .map_err(SecretScanningError::Blocklist)?
+        {
+            let reason = match &report.url {
+                Some(url) => format!("leak reported by GitHub secret scanning at {}", url),
+                None => "leak reported by GitHub secret scanning".to_owned(),
+            };
+            self.blocklist
+                .revoke(pak.short_token(), &reason, REVOKED_BY)
+                .map_err(SecretScanningError::Blocklist)?;
+        }
+        Ok(true)
+    }
+}
+
+#[cfg(test)]
+mod secret_scanning_tests {
+    use std::sync::Arc;
+
+    use base64::engine::general_purpose::STANDARD;
+    use base64::Engine;
+    use p256::ecdsa::signature::Signer;
+    use p256::ecdsa::{Signature, SigningKey};
+    use p256::pkcs8::{EncodePublicKey, LineEnding};
+    use rand::rngs::OsRng;
+    use sha2::Sha256;
+
+    use super::{PublicKeySet, SecretScanningError, SecretScanningHandler};
+    use crate::{
+        Blocklist, KeyStore, MemoryBlocklist, MemoryKeyStore, PrefixedApiKey,
+        PrefixedApiKeyController,
+    };
+
+    type Handler = SecretScanningHandler<OsRng, Sha256, MemoryKeyStore, Arc<MemoryBlocklist>>;
+
+    fn public_key_pem(signing_key: &SigningKey) -> String {
+        signing_key
+            .verifying_key()
+            .to_public_key_pem(LineEnding::LF)
+            .unwrap()
+    }
+
+    fn sign(signing_key: &SigningKey, body: &str) -> String {
+        let signature: Signature = signing_key.sign(body.as_bytes());
+        STANDARD.encode(signature.to_der())
+    }
+
+    fn setup() -> (Handler, SigningKey, PrefixedApiKey, Arc<MemoryBlocklist>) {
+        let signing_key = SigningKey::random(&mut OsRng);
+        let mut public_keys = PublicKeySet::new();
+        public_keys
+            .insert_pem("key-1".to_owned(), &public_key_pem(&signing_key))
+            .unwrap();
+
+        let controller =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        let (pak, record) = controller.generate_key_and_record();
+        let store = MemoryKeyStore::new();
+        store.put(record).unwrap();
+        let blocklist = Arc::new(MemoryBlocklist::new());
+
+        let handler = SecretScanningHandler::new(controller, store, blocklist.clone(), public_keys);
+        (handler, signing_key, pak, blocklist)
+    }
+
+    #[test]
+    fn revokes_reported_keys() {
+        let (handler, signing_key, pak, blocklist) = setup();
+        let wrong_secret = format!("mycompany_{}_51FwqftsmMDHHbJAMEXXHCgG", pak.short_token());
+        let body = format!(
+            r#"[
+                {{"token":"{}","type":"mycompany_api_key","url":"https://github.com/octocat/leaks/blob/main/.env","source":"content"}},
+                {{"token":"{}","type":"mycompany_api_key","url":"https://gist.github.com/1","source":"gist"}},
+                {{"token":"othercompany_BRTRKFsL_51FwqftsmMDHHbJAMEXXHCgG","type":"mycompany_api_key"}},
+                {{"token":"not-a-key","type":"mycompany_api_key"}}
+            ]"#,
+            pak.to_string(),
+            wrong_secret
+        );
+
+        let response = handler
+            .handle("key-1", &sign(&signing_key, &body), body.as_bytes())
+            .unwrap();
+        assert_eq!(
+            response,
+            format!(
+                concat!(
+                    r#"[{{"token_raw":"{}","token_type":"mycompany_api_key","label":"true_positive"}},"#,
+                    r#"{{"token_raw":"{}","token_type":"mycompany_api_key","label":"false_positive"}},"#,
+                    r#"{{"token_raw":"othercompany_BRTRKFsL_51FwqftsmMDHHbJAMEXXHCgG","token_type":"mycompany_api_key","label":"false_positive"}},"#,
+                    r#"{{"token_raw":"not-a-key","token_type":"mycompany_api_key","label":"false_positive"}}]"#
+                ),
+                pak.to_string(),
+                wrong_secret
+            )
+        );
+
+        let revocation = blocklist.lookup(pak.short_token()).unwrap().unwrap();
+        assert_eq!(
+            revocation.revoked_by.as_deref(),
+            Some("github-secret-scanning")
+        );
+        assert_eq!(
+            revocation.reason.as_deref(),
+            Some("leak reported by GitHub secret scanning at https://github.com/octocat/leaks/blob/main/.env")
+        );
+    }
+
+    #[test]
+    fn keeps_existing_revocations() {
+        let (handler, signing_key, pak, blocklist) = setup();
+        blocklist
+            .revoke(pak.short_token(), "rotated", "alice")
+            .unwrap();
+
+        let body = format!(r#"[{{"token":"{}","type":"t"}}]"#, pak.to_string());
+        let response = handler
+            .handle("key-1", &sign(&signing_key, &body), body.as_bytes())
+            .unwrap();
+        assert!(response.contains("true_positive"));
+
+        let revocation = blocklist.lookup(pak.short_token()).unwrap().unwrap();
+        assert_eq!(revocation.revoked_by.as_deref(), Some("alice"));
+    }
+
+    #[test]
+    fn rejects_unverified_reports() {
+        let (handler, signing_key, pak, blocklist) = setup();
+        let body = format!(r#"[{{"token":"{}","type":"t"}}]"#, pak.to_string());
+        let signature = sign(&signing_key, &body);
+
+        let err = handler
+            .handle("key-2", &signature, body.as_bytes())
+            .unwrap_err();
+        assert!(matches!(err, SecretScanningError::UnknownPublicKey(id) if id == "key-2"));
+
+        let tampered = body.replace("\"t\"", "\"u\"");
+        let err = handler
+            .handle("key-1", &signature, tampered.as_bytes())
+            .unwrap_err();
+        assert!(matches!(err, SecretScanningError::InvalidSignature));
+
+        let other_key = SigningKey::random(&mut OsRng);
+        let err = handler
+            .handle("key-1", &sign(&other_key, &body), body.as_bytes())
+            .unwrap_err();
+        assert!(matches!(err, SecretScanningError::InvalidSignature));
+
+        let err = handler
+            .handle("key-1", "not base64!", body.as_bytes())
+            .unwrap_err();
+        assert!(err.is_client_error());
+
+        assert!(!blocklist.is_blocked(pak.short_token()).unwrap());
+
+        let body = "{\"token\":\"x\"}";
+        let err = handler
+            .handle("key-1", &sign(&signing_key, body), body.as_bytes())
+            .unwrap_err();
+        assert!(matches!(err, SecretScanningError::InvalidPayload(_)));
+    }
+
+    #[test]
+    fn loads_github_key_listing() {
+        let signing_key = SigningKey::random(&mut OsRng);
+        let json = format!(
+            r#"{{"public_keys":[{{"key_identifier":"abc123","key":{:?},"is_current":true}}]}}"#,
+            public_key_pem(&signing_key)
+        );
+        let keys = PublicKeySet::from_github_json(&json).unwrap();
+        assert_eq!(keys.len(), 1);
+
+        let json =
+            r#"{"public_keys":[{"key_identifier":"abc123","key":"nope","is_current":true}]}"#;
+        assert!(PublicKeySet::from_github_json(json).is_err());
+        assert!(PublicKeySet::from_github_json("[]").is_err());
+    }
+}
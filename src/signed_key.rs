@@ -0,0 +1,532 @@
+use std::error::Error;
+use std::fmt;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+use digest::{Digest, FixedOutputReset};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use sha2::Sha256;
+
+use crate::controller::PrefixedApiKeyController;
+use crate::prefixed_api_key::PrefixedApiKey;
+use crate::store::ApiKeyRecord;
+use crate::token_encoding::TokenEncoding;
+
+/// The shortest HMAC secret accepted, matching the output of SHA-256
+const MINIMUM_SECRET_LENGTH: usize = 32;
+
+/// The bytes before the signature in a signed long token: the algorithm, followed by the
+/// expiry as big endian unix seconds, with 0 meaning the key never expires
+pub(crate) const SIGNED_HEADER_LENGTH: usize = 9;
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum StatelessError {
+    /// The controller wasn't configured with a [KeySigner]
+    NotConfigured,
+    /// The HMAC secret was shorter than 32 bytes
+    SecretTooShort(usize),
+    /// The bytes weren't a valid public key
+    InvalidPublicKey,
+    /// The long token wasn't a signed token
+    Malformed,
+    /// The long token was signed with a different algorithm than the verifier's
+    UnsupportedAlgorithm(u8),
+    /// The signature didn't match the key
+    InvalidSignature,
+    /// The signature was valid, but the key expired at the given time
+    Expired(SystemTime),
+    /// The key's prefix or environment isn't the one the verifier accepts
+    WrongIssuer,
+    /// The signature was valid, but the key never expires, so it can't be revoked once
+    /// accepted without a store
+    NeverExpires,
+}
+
+impl fmt::Display for StatelessError {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            StatelessError::NotConfigured => {
+                write!(f, "controller isn't configured to sign keys")
+            }
+            StatelessError::SecretTooShort(length) => write!(
+                f,
+                "secret must be at least {} bytes, got {}",
+                MINIMUM_SECRET_LENGTH, length
+            ),
+            StatelessError::InvalidPublicKey => write!(f, "public key is invalid"),
+            StatelessError::Malformed => write!(f, "long token isn't a signed token"),
+            StatelessError::UnsupportedAlgorithm(algorithm) => {
+                write!(
+                    f,
+                    "long token was signed with unsupported algorithm {}",
+                    algorithm
+                )
+            }
+            StatelessError::InvalidSignature => write!(f, "signature doesn't match the key"),
+            StatelessError::Expired(_) => write!(f, "key has expired"),
+            StatelessError::WrongIssuer => {
+                write!(f, "key was issued for another prefix or environment")
+            }
+            StatelessError::NeverExpires => {
+                write!(
+                    f,
+                    "key never expires, so it can't be verified without a store"
+                )
+            }
+        }
+    }
+}
+
+impl Error for StatelessError {}
+
+/// Verifies the signatures embedded in signed long tokens
+pub trait SignatureVerifier: fmt::Debug + Send + Sync {
+    /// The identifier written into signed tokens, so tokens signed with one algorithm are
+    /// never checked with another
+    fn algorithm(&self) -> u8;
+
+    /// The length of the signatures, in bytes
+    fn signature_length(&self) -> usize;
+
+    /// Checks the signature over the message, in constant time
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool;
+}
+
+/// Signs long tokens, so keys can be verified with [verify_stateless](PrefixedApiKeyController::verify_stateless)
+/// without looking them up in a [KeyStore](crate::KeyStore)
+pub trait KeySigner: SignatureVerifier {
+    /// Signs the message, returning exactly [signature_length](SignatureVerifier::signature_length) bytes
+    fn sign(&self, message: &[u8]) -> Vec<u8>;
+}
+
+/// Signs keys with HMAC-SHA256. The same secret signs and verifies keys, so every
+/// service verifying keys can also issue them.
+///
+/// Requires the "signed-keys" feature
+#[derive(Clone)]
+pub struct HmacSigner {
+    secret: Arc<[u8]>,
+}
+
+impl HmacSigner {
+    /// Creates the signer from a secret of at least 32 random bytes
+    pub fn new(secret: &[u8]) -> Result<HmacSigner, StatelessError> {
+        if secret.len() < MINIMUM_SECRET_LENGTH {
+            return Err(StatelessError::SecretTooShort(secret.len()));
+        }
+        Ok(HmacSigner {
+            secret: secret.into(),
+        })
+    }
+
+    fn mac(&self, message: &[u8]) -> Hmac<Sha256> {
+        let mut mac = Hmac::<Sha256>::new_from_slice(&self.secret)
+            .expect("HMAC accepts secrets of any length");
+        mac.update(message);
+        mac
+    }
+}
+
+impl SignatureVerifier for HmacSigner {
+    fn algorithm(&self) -> u8 {
+        1
+    }
+
+    fn signature_length(&self) -> usize {
+        32
+    }
+
+    fn verify(&self, message: &[u8], signature: &[u8]) -> bool {
+        self.mac(message).verify_slice(signature).is_ok()
+    }
+}
+
+impl KeySigner for HmacSigner {
+    fn sign(&self, message: &[u8]) -> Vec<u8> {
+        self.mac(message).finalize().into_bytes().to_vec()
+    }
+}
+
+/// A custom implementation of Debug that hides the secret
+impl fmt::Debug for HmacSigner {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        f.debug_struct("HmacSigner")
+            .field("secret", &"***")
+            .finish()
+    }
+}
+
+/// The claims carried by a signed key, returned once its signature is verified
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SignedClaims {
+    /// When the key stops being accepted, if ever
+    pub expires_at: Option<SystemTime>,
+}
+
+/// The message a signature covers: the key's prefix, environment and short token, then
+/// the signed token's header. Each part is length prefixed, so moving characters
+/// between parts changes the message.
+fn signed_message(
+    prefix: &str,
+    environment: Option<&str>,
+    short_token: &str,
+    header: &[u8],
+) -> Vec<u8> {
+    let mut message = Vec::new();
+    for part in [
+        prefix.as_bytes(),
+        environment.unwrap_or("").as_bytes(),
+        short_token.as_bytes(),
+        header,
+    ] {
+        message.extend_from_slice(&(part.len() as u32).to_be_bytes());
+        message.extend_from_slice(part);
+    }
+    message
+}
+
+/// Creates the signed long token for a key with the given parts
+pub(crate) fn sign_long_token(
+    signer: &dyn KeySigner,
+    prefix: &str,
+    environment: Option<&str>,
+    short_token: &str,
+    expires_at: Option<SystemTime>,
+    encoding: TokenEncoding,
+) -> String {
+    // Expiries are stored in whole seconds, with anything at or before the epoch
+    // clamped to 1 since 0 means the key never expires
+    let expiry = match expires_at {
+        Some(expires_at) => match expires_at.duration_since(UNIX_EPOCH) {
+            Ok(since_epoch) => since_epoch.as_secs().max(1),
+            Err(_) => 1,
+        },
+        None => 0,
+    };
+
+    let mut token = vec![signer.algorithm()];
+    token.extend_from_slice(&expiry.to_be_bytes());
+    let message = signed_message(prefix, environment, short_token, &token);
+    token.extend(signer.sign(&message));
+    encoding.encode(&token)
+}
+
+/// Verifies the signed long token of the key, without any configuration beyond the
+/// verifier and the encoding the key was generated with. Keys which never expire are
+/// rejected unless `allow_non_expiring` is set.
+pub(crate) fn verify_claims<V: SignatureVerifier + ?Sized>(
+    verifier: &V,
+    pak: &PrefixedApiKey,
+    encoding: TokenEncoding,
+    allow_non_expiring: bool,
+) -> Result<SignedClaims, StatelessError> {
+    let token = match encoding.decode(pak.long_token()) {
+        Some(token) => token,
+        None => return Err(StatelessError::Malformed),
+    };
+    if token.len() != SIGNED_HEADER_LENGTH + verifier.signature_length() {
+        return Err(StatelessError::Malformed);
+    }
+
+    let (header, signature) = token.split_at(SIGNED_HEADER_LENGTH);
+    if header[0] != verifier.algorithm() {
+        return Err(StatelessError::UnsupportedAlgorithm(header[0]));
+    }
+    let message = signed_message(pak.prefix(), pak.environment(), pak.short_token(), header);
+    if !verifier.verify(&message, signature) {
+        return Err(StatelessError::InvalidSignature);
+    }
+
+    let mut expiry = [0u8; 8];
+    expiry.copy_from_slice(&header[1..]);
+    let expires_at = match u64::from_be_bytes(expiry) {
+        0 => None,
+        seconds => Some(UNIX_EPOCH + Duration::from_secs(seconds)),
+    };
+    match expires_at {
+        Some(expires_at) if expires_at <= SystemTime::now() => {
+            return Err(StatelessError::Expired(expires_at))
+        }
+        None if !allow_non_expiring => return Err(StatelessError::NeverExpires),
+        _ => {}
+    }
+    Ok(SignedClaims { expires_at })
+}
+
+impl<R: RngCore + Clone, D: Digest + FixedOutputReset> PrefixedApiKeyController<R, D> {
+    /// Generates a signed key which stops being accepted by
+    /// [verify_stateless](PrefixedApiKeyController::verify_stateless) at `expires_at`.
+    /// Keys from [generate_key](PrefixedApiKeyController::generate_key) are signed too,
+    /// but never expire, so `verify_stateless` rejects them with
+    /// [NeverExpires](StatelessError::NeverExpires). Authenticate those against a
+    /// [KeyStore](crate::KeyStore) instead, where they can be revoked.
+    ///
+    /// Can potentially panic depending on the rng source's implementation of [fill_bytes](rand::RngCore::fill_bytes).
+    ///
+    /// Requires the "signed-keys" feature
+    pub fn generate_signed_key(
+        &self,
+        expires_at: SystemTime,
+    ) -> Result<PrefixedApiKey, StatelessError> {
+        let short_token = self.generate_short_token();
+        match self.signed_long_token(&short_token, Some(expires_at)) {
+            Some(long_token) => Ok(self.new_key(short_token, long_token)),
+            None => Err(StatelessError::NotConfigured),
+        }
+    }
+
+    /// Generates a signed key using [generate_signed_key](PrefixedApiKeyController::generate_signed_key),
+    /// along with an [ApiKeyRecord] holding the hash of the long token and the same
+    /// expiry, so the key can also be checked against a [KeyStore](crate::KeyStore).
+    ///
+    /// Can potentially panic depending on the rng source's implementation of [fill_bytes](rand::RngCore::fill_bytes).
+    ///
+    /// Requires the "signed-keys" feature
+    pub fn generate_signed_key_and_record(
+        &self,
+        expires_at: SystemTime,
+    ) -> Result<(PrefixedApiKey, ApiKeyRecord), StatelessError> {
+        let pak = self.generate_signed_key(expires_at)?;
+        let hash = self.long_token_hashed(&pak);
+        let record = Self::new_record(&pak, hash).with_expires_at(expires_at);
+        Ok((pak, record))
+    }
+
+    /// Verifies the key's signature and expiry without a database lookup, for services
+    /// which can't reach the [KeyStore](crate::KeyStore). Revoked keys are still accepted
+    /// until they expire, since revocations live in the store, so prefer short expiries
+    /// or pair this with a [Blocklist](crate::Blocklist).
+    ///
+    /// Keys with a different prefix or environment than the controller's are rejected
+    /// with [WrongIssuer](StatelessError::WrongIssuer) before their signature is checked.
+    /// Keys which never expire, like those from [generate_key](PrefixedApiKeyController::generate_key),
+    /// are rejected with [NeverExpires](StatelessError::NeverExpires), since they could
+    /// never be revoked.
+    ///
+    /// Requires the "signed-keys" feature
+    pub fn verify_stateless(&self, pak: &PrefixedApiKey) -> Result<SignedClaims, StatelessError> {
+        self.verify_signed_key(pak, false)
+    }
+
+    /// Like [verify_stateless](PrefixedApiKeyController::verify_stateless), but also
+    /// accepts keys which never expire. Only use this when every such key is also
+    /// checked against a [Blocklist](crate::Blocklist), or they can never be revoked.
+    ///
+    /// Requires the "signed-keys" feature
+    pub fn verify_stateless_allowing_non_expiring(
+        &self,
+        pak: &PrefixedApiKey,
+    ) -> Result<SignedClaims, StatelessError> {
+        self.verify_signed_key(pak, true)
+    }
+
+    fn verify_signed_key(
+        &self,
+        pak: &PrefixedApiKey,
+        allow_non_expiring: bool,
+    ) -> Result<SignedClaims, StatelessError> {
+        let signer = match &self.signer {
+            Some(signer) => signer,
+            None => return Err(StatelessError::NotConfigured),
+        };
+        if pak.prefix() != self.prefix() || pak.environment() != self.environment() {
+            return Err(StatelessError::WrongIssuer);
+        }
+        verify_claims(signer.as_ref(), pak, self.encoding, allow_non_expiring)
+    }
+}
+
+#[cfg(test)]
+mod signed_key_tests {
+    use std::time::{Duration, SystemTime};
+
+    use rand::rngs::OsRng;
+    use sha2::Sha256;
+
+    use super::{HmacSigner, SignedClaims, StatelessError};
+    use crate::{
+        AuthOutcome, BuilderError, KeyStore, LengthUnit, MemoryBlocklist, MemoryKeyStore,
+        PrefixedApiKeyController, TokenEncoding,
+    };
+
+    const SECRET: [u8; 32] = [3; 32];
+
+    fn signed_controller(secret: &[u8]) -> PrefixedApiKeyController<OsRng, Sha256> {
+        PrefixedApiKeyController::configure()
+            .prefix("mycompany".to_owned())
+            .rng(OsRng)
+            .short_token_length(8)
+            .environment("live".to_owned())
+            .sign_keys(HmacSigner::new(secret).unwrap())
+            .finalize()
+            .unwrap()
+    }
+
+    #[test]
+    fn rejects_short_secrets() {
+        assert_eq!(
+            HmacSigner::new(&[0; 16]).unwrap_err(),
+            StatelessError::SecretTooShort(16)
+        );
+        let debug = format!("{:?}", HmacSigner::new(&SECRET).unwrap());
+        assert!(!debug.contains('3'));
+    }
+
+    #[test]
+    fn verifies_signed_keys() {
+        let controller = signed_controller(&SECRET);
+        let pak = controller
+            .generate_signed_key(SystemTime::now() + Duration::from_secs(60))
+            .unwrap();
+        assert!(controller.verify_stateless(&pak).is_ok());
+
+        // Keys survive a round trip through their string form
+        let parsed = controller.parse_key(&pak.to_string()).unwrap();
+        assert!(controller.verify_stateless(&parsed).is_ok());
+
+        // Changing any signed part of the key breaks the signature
+        let other = controller.generate_key();
+        let swapped = controller
+            .parse_key(&format!(
+                "mycompany_live_{}_{}",
+                other.short_token(),
+                pak.long_token()
+            ))
+            .unwrap();
+        assert_eq!(
+            controller.verify_stateless(&swapped).unwrap_err(),
+            StatelessError::InvalidSignature
+        );
+        let other_secret = signed_controller(&[4; 32]);
+        assert_eq!(
+            other_secret.verify_stateless(&pak).unwrap_err(),
+            StatelessError::InvalidSignature
+        );
+
+        let random =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        assert_eq!(
+            random.verify_stateless(&pak).unwrap_err(),
+            StatelessError::NotConfigured
+        );
+        let unsigned = random.generate_key();
+        let unsigned = controller
+            .parse_key(&format!(
+                "mycompany_live_{}_{}",
+                unsigned.short_token(),
+                unsigned.long_token()
+            ))
+            .unwrap();
+        assert_eq!(
+            controller.verify_stateless(&unsigned).unwrap_err(),
+            StatelessError::Malformed
+        );
+    }
+
+    #[test]
+    fn non_expiring_keys_need_an_explicit_opt_in() {
+        let controller = signed_controller(&SECRET);
+        let pak = controller.generate_key();
+        assert_eq!(
+            controller.verify_stateless(&pak).unwrap_err(),
+            StatelessError::NeverExpires
+        );
+        assert_eq!(
+            controller
+                .verify_stateless_allowing_non_expiring(&pak)
+                .unwrap(),
+            SignedClaims { expires_at: None }
+        );
+
+        // The signature is still checked before the missing expiry is reported
+        let other_secret = signed_controller(&[4; 32]);
+        assert_eq!(
+            other_secret.verify_stateless(&pak).unwrap_err(),
+            StatelessError::InvalidSignature
+        );
+    }
+
+    #[test]
+    fn rejects_keys_from_other_issuers() {
+        let controller = signed_controller(&SECRET);
+        let pak = controller.generate_key();
+
+        // Controllers sharing a secret don't accept each other's keys
+        for (prefix, environment) in [("mycompany", "test"), ("othercompany", "live")] {
+            let other = PrefixedApiKeyController::<_, Sha256>::configure()
+                .prefix(prefix.to_owned())
+                .rng(OsRng)
+                .short_token_length(8)
+                .environment(environment.to_owned())
+                .sign_keys(HmacSigner::new(&SECRET).unwrap())
+                .finalize()
+                .unwrap();
+            assert_eq!(
+                other.verify_stateless(&pak).unwrap_err(),
+                StatelessError::WrongIssuer
+            );
+        }
+    }
+
+    #[test]
+    fn rejects_expired_keys() {
+        let controller = signed_controller(&SECRET);
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        let pak = controller.generate_signed_key(expires_at).unwrap();
+        let claims = controller.verify_stateless(&pak).unwrap();
+        let remaining = claims.expires_at.unwrap().duration_since(SystemTime::now());
+        assert!(remaining.unwrap() <= Duration::from_secs(60));
+
+        let expired_at = SystemTime::now() - Duration::from_secs(60);
+        let expired = controller.generate_signed_key(expired_at).unwrap();
+        assert!(matches!(
+            controller.verify_stateless(&expired),
+            Err(StatelessError::Expired(_))
+        ));
+    }
+
+    #[test]
+    fn signed_keys_still_authenticate_with_a_store() {
+        let controller = signed_controller(&SECRET);
+        let store = MemoryKeyStore::new();
+        let expires_at = SystemTime::now() + Duration::from_secs(60);
+        let (pak, record) = controller
+            .generate_signed_key_and_record(expires_at)
+            .unwrap();
+        assert_eq!(record.expires_at, Some(expires_at));
+        store.put(record).unwrap();
+
+        let outcome = controller
+            .authenticate(&pak, &store, &MemoryBlocklist::new())
+            .unwrap();
+        assert!(matches!(outcome, AuthOutcome::Authenticated(_)));
+    }
+
+    #[test]
+    fn signed_tokens_match_the_scanner() {
+        let controller = PrefixedApiKeyController::<_, Sha256>::configure()
+            .prefix("mycompany".to_owned())
+            .rng(OsRng)
+            .short_token_length(8)
+            .token_encoding(TokenEncoding::Hex)
+            .sign_keys(HmacSigner::new(&SECRET).unwrap())
+            .finalize()
+            .unwrap();
+        let pak = controller.generate_key();
+        assert_eq!(pak.long_token().len(), 2 * (9 + 32));
+        assert_eq!(controller.scanner().scan_str(&pak.to_string()).len(), 1);
+        assert!(controller
+            .verify_stateless_allowing_non_expiring(&pak)
+            .is_ok());
+
+        let result = PrefixedApiKeyController::<_, Sha256>::configure()
+            .prefix("mycompany".to_owned())
+            .rng(OsRng)
+            .short_token_length(8)
+            .length_unit(LengthUnit::Characters)
+            .sign_keys(HmacSigner::new(&SECRET).unwrap())
+            .finalize();
+        assert!(matches!(result, Err(BuilderError::IncompatibleOptions(_))));
+    }
+}
@@ -0,0 +1,303 @@
+use std::collections::HashMap;
+use std::convert::Infallible;
+use std::sync::{Arc, RwLock};
+use std::time::{Duration, SystemTime};
+
+use crate::blocklist::Revocation;
+use crate::scope::ScopeSet;
+
+/// The server-side record of an issued api key. This never holds the secret long
+/// token, only its hash, so it's safe to persist in a database and to display on
+/// dashboards.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ApiKeyRecord {
+    /// The short token of the key, used to look the record up
+    pub short_token: String,
+    /// The hash of the key's long token
+    pub hash: String,
+    /// The prefix of the key, which binds the record to the controller that issued it.
+    /// Records without one are accepted by any controller sharing the store
+    pub prefix: Option<String>,
+    /// The environment the key was issued for, like `test` or `live`
+    pub environment: Option<String>,
+    /// An identifier for the user or organization the key belongs to
+    pub owner_id: Option<String>,
+    /// A human friendly name for the key, for display on dashboards
+    pub label: Option<String>,
+    /// When the key was issued
+    pub created_at: SystemTime,
+    /// When the key was last successfully authenticated
+    pub last_used_at: Option<SystemTime>,
+    /// When the key stops being accepted, if ever
+    pub expires_at: Option<SystemTime>,
+    /// The short token of the key this one replaced when it was rotated
+    pub predecessor: Option<String>,
+    /// The short token of the key which replaced this one when it was rotated
+    pub superseded_by: Option<String>,
+    /// The scopes granted to the key when it was issued
+    pub scopes: ScopeSet,
+    /// Set once the key has been revoked
+    pub revocation: Option<Revocation>,
+}
+
+impl ApiKeyRecord {
+    /// Creates a new, unrevoked record for the given short token and long token hash
+    pub fn new(short_token: String, hash: String) -> ApiKeyRecord {
+        ApiKeyRecord {
+            short_token,
+            hash,
+            prefix: None,
+            environment: None,
+            owner_id: None,
+            label: None,
+            created_at: SystemTime::now(),
+            last_used_at: None,
+            expires_at: None,
+            predecessor: None,
+            superseded_by: None,
+            scopes: ScopeSet::new(),
+            revocation: None,
+        }
+    }
+
+    /// Sets the prefix of the key
+    pub fn with_prefix(mut self, prefix: String) -> ApiKeyRecord {
+        self.prefix = Some(prefix);
+        self
+    }
+
+    /// Sets the environment the key was issued for
+    pub fn with_environment(mut self, environment: String) -> ApiKeyRecord {
+        self.environment = Some(environment);
+        self
+    }
+
+    /// Sets the id of the key's owner
+    pub fn with_owner_id(mut self, owner_id: String) -> ApiKeyRecord {
+        self.owner_id = Some(owner_id);
+        self
+    }
+
+    /// Sets the human friendly label of the key
+    pub fn with_label(mut self, label: String) -> ApiKeyRecord {
+        self.label = Some(label);
+        self
+    }
+
+    /// Sets the scopes granted to the key
+    pub fn with_scopes(mut self, scopes: ScopeSet) -> ApiKeyRecord {
+        self.scopes = scopes;
+        self
+    }
+
+    /// Sets when the key stops being accepted
+    pub fn with_expires_at(mut self, expires_at: SystemTime) -> ApiKeyRecord {
+        self.expires_at = Some(expires_at);
+        self
+    }
+
+    /// Whether or not the key has expired as of `now`
+    pub fn is_expired(&self, now: SystemTime) -> bool {
+        match self.expires_at {
+            Some(expires_at) => expires_at <= now,
+            None => false,
+        }
+    }
+
+    /// Whether or not the key has been revoked
+    pub fn is_revoked(&self) -> bool {
+        self.revocation.is_some()
+    }
+
+    /// Updates `last_used_at` to `now`, unless it was already updated less than
+    /// `throttle` ago. Returns whether the record was changed and needs saving.
+    pub fn touch(&mut self, now: SystemTime, throttle: Duration) -> bool {
+        let stale = match self.last_used_at {
+            None => true,
+            Some(last_used_at) => match now.duration_since(last_used_at) {
+                Ok(elapsed) => elapsed >= throttle,
+                Err(_) => false,
+            },
+        };
+
+        if stale {
+            self.last_used_at = Some(now);
+        }
+        stale
+    }
+}
+
+/// Storage for [ApiKeyRecord]s, keyed by their short token. Implement this for your
+/// database to use it with [authenticate](crate::PrefixedApiKeyController::authenticate).
+pub trait KeyStore {
+    type Error;
+
+    /// Finds the record for the given short token, returning `Ok(None)` if no such
+    /// record exists.
+    fn get(&self, short_token: &str) -> Result<Option<ApiKeyRecord>, Self::Error>;
+
+    /// Inserts the record, replacing any existing record with the same short token.
+    fn put(&self, record: ApiKeyRecord) -> Result<(), Self::Error>;
+
+    /// Sets `last_used_at` on the record for the given short token, leaving every other
+    /// field as it is. This is called while authenticating, so it must not write back a
+    /// whole record, which could undo a revocation or rotation saved in the meantime.
+    /// Does nothing if no such record exists.
+    ///
+    /// The default implementation does nothing, so stores that don't override it never
+    /// record `last_used_at`.
+    fn touch_last_used(&self, _short_token: &str, _at: SystemTime) -> Result<(), Self::Error> {
+        Ok(())
+    }
+}
+
+impl<S: KeyStore + ?Sized> KeyStore for &S {
+    type Error = S::Error;
+
+    fn get(&self, short_token: &str) -> Result<Option<ApiKeyRecord>, Self::Error> {
+        (**self).get(short_token)
+    }
+
+    fn put(&self, record: ApiKeyRecord) -> Result<(), Self::Error> {
+        (**self).put(record)
+    }
+
+    fn touch_last_used(&self, short_token: &str, at: SystemTime) -> Result<(), Self::Error> {
+        (**self).touch_last_used(short_token, at)
+    }
+}
+
+impl<S: KeyStore + ?Sized> KeyStore for Arc<S> {
+    type Error = S::Error;
+
+    fn get(&self, short_token: &str) -> Result<Option<ApiKeyRecord>, Self::Error> {
+        (**self).get(short_token)
+    }
+
+    fn put(&self, record: ApiKeyRecord) -> Result<(), Self::Error> {
+        (**self).put(record)
+    }
+
+    fn touch_last_used(&self, short_token: &str, at: SystemTime) -> Result<(), Self::Error> {
+        (**self).touch_last_used(short_token, at)
+    }
+}
+
+/// A [KeyStore] that keeps records in memory. Useful for tests and prototyping,
+/// but records are lost when the process exits.
+#[derive(Debug, Default)]
+pub struct MemoryKeyStore {
+    records: RwLock<HashMap<String, ApiKeyRecord>>,
+}
+
+impl MemoryKeyStore {
+    pub fn new() -> MemoryKeyStore {
+        MemoryKeyStore::default()
+    }
+
+    /// The number of records in the store
+    pub fn len(&self) -> usize {
+        self.records.read().unwrap().len()
+    }
+
+    /// Whether the store holds no records
+    pub fn is_empty(&self) -> bool {
+        self.len() == 0
+    }
+}
+
+impl KeyStore for MemoryKeyStore {
+    type Error = Infallible;
+
+    fn get(&self, short_token: &str) -> Result<Option<ApiKeyRecord>, Self::Error> {
+        Ok(self.records.read().unwrap().get(short_token).cloned())
+    }
+
+    fn put(&self, record: ApiKeyRecord) -> Result<(), Self::Error> {
+        self.records
+            .write()
+            .unwrap()
+            .insert(record.short_token.clone(), record);
+        Ok(())
+    }
+
+    fn touch_last_used(&self, short_token: &str, at: SystemTime) -> Result<(), Self::Error> {
+        if let Some(record) = self.records.write().unwrap().get_mut(short_token) {
+            record.last_used_at = Some(at);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod store_tests {
+    use std::convert::Infallible;
+    use std::time::{Duration, SystemTime};
+
+    use super::{ApiKeyRecord, KeyStore, MemoryKeyStore};
+    use crate::{Blocklist, StoreBlocklist};
+
+    #[test]
+    fn memory_store_round_trips_records() {
+        let store = MemoryKeyStore::new();
+        let record = ApiKeyRecord::new("abc".to_owned(), "hash".to_owned());
+        store.put(record.clone()).unwrap();
+        assert_eq!(store.get("abc").unwrap(), Some(record));
+        assert_eq!(store.get("missing").unwrap(), None);
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn touch_is_throttled() {
+        let mut record = ApiKeyRecord::new("abc".to_owned(), "hash".to_owned());
+        let throttle = Duration::from_secs(60);
+        let now = SystemTime::now();
+
+        assert!(record.touch(now, throttle));
+        assert_eq!(record.last_used_at, Some(now));
+        assert!(!record.touch(now + Duration::from_secs(30), throttle));
+        assert_eq!(record.last_used_at, Some(now));
+        assert!(record.touch(now + Duration::from_secs(60), throttle));
+        assert_eq!(record.last_used_at, Some(now + Duration::from_secs(60)));
+    }
+
+    #[test]
+    fn touch_last_used_only_updates_last_used_at() {
+        let store = MemoryKeyStore::new();
+        store
+            .put(ApiKeyRecord::new("abc".to_owned(), "hash".to_owned()))
+            .unwrap();
+        StoreBlocklist::new(&store)
+            .revoke("abc", "leaked", "alice")
+            .unwrap();
+
+        let now = SystemTime::now();
+        store.touch_last_used("abc", now).unwrap();
+        store.touch_last_used("missing", now).unwrap();
+        let record = store.get("abc").unwrap().unwrap();
+        assert_eq!(record.last_used_at, Some(now));
+        assert!(record.is_revoked());
+        assert_eq!(store.len(), 1);
+    }
+
+    #[test]
+    fn touch_last_used_defaults_to_doing_nothing() {
+        struct ReadOnlyStore(ApiKeyRecord);
+
+        impl KeyStore for ReadOnlyStore {
+            type Error = Infallible;
+
+            fn get(&self, _short_token: &str) -> Result<Option<ApiKeyRecord>, Self::Error> {
+                Ok(Some(self.0.clone()))
+            }
+
+            fn put(&self, _record: ApiKeyRecord) -> Result<(), Self::Error> {
+                Ok(())
+            }
+        }
+
+        let store = ReadOnlyStore(ApiKeyRecord::new("abc".to_owned(), "hash".to_owned()));
+        store.touch_last_used("abc", SystemTime::now()).unwrap();
+        assert_eq!(store.get("abc").unwrap().unwrap().last_used_at, None);
+    }
+}
@@ -0,0 +1,187 @@
+use std::sync::{Arc, Mutex};
+
+use digest::{Digest, FixedOutputReset};
+use rand::{Error, RngCore, SeedableRng};
+use rand_chacha::ChaCha20Rng;
+
+use crate::controller::PrefixedApiKeyController;
+use crate::prefixed_api_key::PrefixedApiKey;
+
+/// An rng which replays the given bytes, starting over once they run out. Clones share
+/// their position, so a controller holding one generates different keys each time
+/// rather than replaying the same bytes for every key.
+///
+/// Requires the "test-util" feature
+#[derive(Clone, Debug)]
+pub struct FixedRng {
+    bytes: Arc<[u8]>,
+    position: Arc<Mutex<usize>>,
+}
+
+impl FixedRng {
+    /// Creates the rng, panicking if `bytes` is empty
+    pub fn new(bytes: Vec<u8>) -> FixedRng {
+        assert!(!bytes.is_empty(), "FixedRng needs at least one byte");
+        FixedRng {
+            bytes: bytes.into(),
+            position: Arc::new(Mutex::new(0)),
+        }
+    }
+}
+
+impl RngCore for FixedRng {
+    fn next_u32(&mut self) -> u32 {
+        let mut bytes = [0u8; 4];
+        self.fill_bytes(&mut bytes);
+        u32::from_le_bytes(bytes)
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        let mut bytes = [0u8; 8];
+        self.fill_bytes(&mut bytes);
+        u64::from_le_bytes(bytes)
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        let mut position = self.position.lock().unwrap();
+        for byte in dest {
+            *byte = self.bytes[*position];
+            *position = (*position + 1) % self.bytes.len();
+        }
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+/// A deterministic rng seeded from a number, producing the same sequence on every run
+/// and platform. Like [FixedRng], clones share their state, so the sequence advances
+/// between keys. Never use this outside of tests.
+///
+/// Requires the "test-util" feature
+#[derive(Clone, Debug)]
+pub struct SeededRng {
+    inner: Arc<Mutex<ChaCha20Rng>>,
+}
+
+impl SeededRng {
+    pub fn new(seed: u64) -> SeededRng {
+        SeededRng {
+            inner: Arc::new(Mutex::new(ChaCha20Rng::seed_from_u64(seed))),
+        }
+    }
+}
+
+impl RngCore for SeededRng {
+    fn next_u32(&mut self) -> u32 {
+        self.inner.lock().unwrap().next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.inner.lock().unwrap().next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.inner.lock().unwrap().fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.fill_bytes(dest);
+        Ok(())
+    }
+}
+
+impl<D: Digest + FixedOutputReset> PrefixedApiKeyController<SeededRng, D> {
+    /// Creates a controller with the default lengths whose keys are generated from the
+    /// seed, so the same keys are generated in the same order on every run. Useful for
+    /// golden-file tests, and never for real keys.
+    ///
+    /// Requires the "test-util" feature
+    pub fn seeded(prefix: String, seed: u64) -> PrefixedApiKeyController<SeededRng, D> {
+        PrefixedApiKeyController::new(prefix, SeededRng::new(seed), None, 8, 24)
+    }
+}
+
+impl<R: RngCore + Clone, D: Digest + FixedOutputReset> PrefixedApiKeyController<R, D> {
+    /// Generates `count` keys along with the hashes of their long tokens, in the order
+    /// they were generated.
+    ///
+    /// Requires the "test-util" feature
+    pub fn generate_fixtures(&self, count: usize) -> Vec<(PrefixedApiKey, String)> {
+        (0..count)
+            .map(|_| {
+                let pak = self.generate_key();
+                let hash = self.long_token_hashed(&pak);
+                (pak, hash)
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod test_util_tests {
+    use rand::RngCore;
+    use sha2::Sha256;
+
+    use super::FixedRng;
+    use crate::PrefixedApiKeyController;
+
+    #[test]
+    fn fixed_rng_replays_bytes_across_clones() {
+        let mut rng = FixedRng::new(vec![1, 2, 3]);
+        let mut clone = rng.clone();
+
+        let mut bytes = [0u8; 2];
+        rng.fill_bytes(&mut bytes);
+        assert_eq!(bytes, [1, 2]);
+        clone.fill_bytes(&mut bytes);
+        assert_eq!(bytes, [3, 1]);
+        assert_eq!(rng.next_u32(), u32::from_le_bytes([2, 3, 1, 2]));
+    }
+
+    #[test]
+    fn seeded_controllers_are_reproducible() {
+        let first = PrefixedApiKeyController::<_, Sha256>::seeded("mycompany".to_owned(), 42);
+        let second = PrefixedApiKeyController::<_, Sha256>::seeded("mycompany".to_owned(), 42);
+        let other = PrefixedApiKeyController::<_, Sha256>::seeded("mycompany".to_owned(), 7);
+
+        let fixtures = first.generate_fixtures(5);
+        let expected = second.generate_fixtures(5);
+        assert_eq!(fixtures.len(), 5);
+        for ((pak, hash), (expected_pak, expected_hash)) in fixtures.iter().zip(&expected) {
+            assert_eq!(pak.to_string(), expected_pak.to_string());
+            assert_eq!(hash, expected_hash);
+            assert!(first.check_hash(pak, hash));
+        }
+
+        // The sequence advances between keys and between calls
+        let mut keys: Vec<String> = fixtures.iter().map(|(pak, _)| pak.to_string()).collect();
+        keys.extend(
+            first
+                .generate_fixtures(5)
+                .iter()
+                .map(|(pak, _)| pak.to_string()),
+        );
+        keys.sort();
+        keys.dedup();
+        assert_eq!(keys.len(), 10);
+
+        assert_ne!(
+            other.generate_key().to_string(),
+            PrefixedApiKeyController::<_, Sha256>::seeded("mycompany".to_owned(), 42)
+                .generate_key()
+                .to_string()
+        );
+    }
+
+    #[test]
+    fn seeded_keys_are_stable() {
+        let controller = PrefixedApiKeyController::<_, Sha256>::seeded("mycompany".to_owned(), 0);
+        assert_eq!(
+            controller.generate_key().to_string(),
+            "mycompany_WwDjRUSxB3b_GL2SNdxn56XmTRvMqNPNAZYAFgAb4tDBL"
+        );
+    }
+}
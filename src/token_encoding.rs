@@ -0,0 +1,486 @@
+use std::fmt;
+
+const BASE58_BITCOIN: &str = "123456789ABCDEFGHJKLMNPQRSTUVWXYZabcdefghijkmnopqrstuvwxyz";
+const BASE58_FLICKR: &str = "123456789abcdefghijkmnopqrstuvwxyzABCDEFGHJKLMNPQRSTUVWXYZ";
+const BASE62: &str = "0123456789ABCDEFGHIJKLMNOPQRSTUVWXYZabcdefghijklmnopqrstuvwxyz";
+const CROCKFORD_BASE32: &str = "0123456789ABCDEFGHJKMNPQRSTVWXYZ";
+const HEX: &str = "0123456789abcdef";
+
+/// How the random bytes of a key's tokens are encoded into characters. None of the
+/// alphabets contain the `_` separator, so every encoding is safe to use in keys.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum TokenEncoding {
+    /// Base58 using the Bitcoin alphabet, which leaves out the easily confused `0`,
+    /// `O`, `I` and `l`. This is the original encoding, and the default.
+    Base58,
+    /// Base58 using the Flickr alphabet, which has the same characters as the Bitcoin
+    /// alphabet with the lowercase letters first
+    Base58Flickr,
+    /// Base62, using every ASCII letter and digit
+    Base62,
+    /// [Crockford's base32](https://www.crockford.com/base32.html), which is case
+    /// insensitive and treats `I` and `L` as `1`, and `O` as `0`, so keys survive being
+    /// read aloud over the phone
+    Crockford32,
+    /// Lowercase hex, with uppercase also accepted when parsing
+    Hex,
+}
+
+/// What the configured short and long token lengths count
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash)]
+pub enum LengthUnit {
+    /// Lengths are the number of random bytes, which are then encoded. This is the
+    /// original behavior, and the length of the encoded tokens can vary: 8 bytes of
+    /// base58 are 8 to 11 characters long.
+    Bytes,
+    /// Lengths are the exact number of characters in the tokens. Each character is
+    /// picked uniformly from the encoding's alphabet, and parsed keys must have tokens
+    /// of exactly the configured lengths.
+    Characters,
+}
+
+// Deriving needs `#[default]`, which is newer than the supported rust version
+#[allow(clippy::derivable_impls)]
+impl Default for LengthUnit {
+    fn default() -> Self {
+        LengthUnit::Bytes
+    }
+}
+
+impl TokenEncoding {
+    /// The characters generated tokens are made of
+    pub fn alphabet(&self) -> &'static str {
+        match self {
+            TokenEncoding::Base58 => BASE58_BITCOIN,
+            TokenEncoding::Base58Flickr => BASE58_FLICKR,
+            TokenEncoding::Base62 => BASE62,
+            TokenEncoding::Crockford32 => CROCKFORD_BASE32,
+            TokenEncoding::Hex => HEX,
+        }
+    }
+
+    /// A regex character class matching the alphabet
+    pub(crate) fn regex_class(&self) -> &'static str {
+        match self {
+            TokenEncoding::Base58 | TokenEncoding::Base58Flickr => "[1-9A-HJ-NP-Za-km-z]",
+            TokenEncoding::Base62 => "[0-9A-Za-z]",
+            TokenEncoding::Crockford32 => "[0-9A-HJKMNP-TV-Z]",
+            TokenEncoding::Hex => "[0-9a-f]",
+        }
+    }
+
+    pub(crate) fn contains(&self, byte: u8) -> bool {
+        self.alphabet().as_bytes().contains(&byte)
+    }
+
+    /// Encodes the random bytes of a token
+    pub fn encode(&self, bytes: &[u8]) -> String {
+        match self {
+            TokenEncoding::Base58 => bs58::encode(bytes).into_string(),
+            TokenEncoding::Base58Flickr => bs58::encode(bytes)
+                .with_alphabet(bs58::Alphabet::FLICKR)
+                .into_string(),
+            TokenEncoding::Base62 => encode_radix(bytes, BASE62.as_bytes()),
+            TokenEncoding::Crockford32 => encode_base32(bytes, CROCKFORD_BASE32.as_bytes()),
+            TokenEncoding::Hex => hex::encode(bytes),
+        }
+    }
+
+    /// The number of bits of entropy in each character picked uniformly from the alphabet
+    pub fn bits_per_character(&self) -> f64 {
+        (self.alphabet().len() as f64).log2()
+    }
+
+    /// The bits of entropy in a token of `length` units, of which the first `literal`
+    /// characters are replaced by a fixed prefix. With [LengthUnit::Bytes] and a literal
+    /// prefix this is an upper bound, since base58 and base62 characters aren't all
+    /// equally likely.
+    pub(crate) fn entropy_bits(&self, unit: LengthUnit, length: usize, literal: usize) -> f64 {
+        let entropy = match unit {
+            LengthUnit::Bytes => length as f64 * 8.0,
+            LengthUnit::Characters => length as f64 * self.bits_per_character(),
+        };
+        if literal == 0 {
+            return entropy;
+        }
+
+        let random = length.saturating_sub(literal) as f64;
+        entropy.min(random * self.bits_per_character())
+    }
+
+    /// The shortest length, in units, of a token with at least `bits` bits of entropy
+    /// after the first `literal` characters are replaced by a fixed prefix
+    pub(crate) fn length_for_entropy(&self, unit: LengthUnit, bits: u32, literal: usize) -> usize {
+        let characters = literal + (bits as f64 / self.bits_per_character()).ceil() as usize;
+        match unit {
+            LengthUnit::Bytes => {
+                let bytes = (bits as f64 / 8.0).ceil() as usize;
+                match literal {
+                    0 => bytes,
+                    _ => bytes.max(characters),
+                }
+            }
+            LengthUnit::Characters => characters,
+        }
+    }
+
+    /// Generates a token of exactly `length` characters picked uniformly from the
+    /// alphabet. Random bytes which would favor the start of the alphabet are rejected
+    /// and replaced, rather than wrapped around with a modulo.
+    pub(crate) fn sample<E>(
+        &self,
+        length: usize,
+        mut fill: impl FnMut(&mut [u8]) -> Result<(), E>,
+    ) -> Result<String, E> {
+        let alphabet = self.alphabet().as_bytes();
+        // The largest multiple of the alphabet's length that fits in a byte
+        let limit = 256 - 256 % alphabet.len();
+
+        let mut token = String::with_capacity(length);
+        let mut bytes = vec![0u8; length];
+        while token.len() < length {
+            let remaining = length - token.len();
+            fill(&mut bytes[..remaining])?;
+            for byte in &bytes[..remaining] {
+                if (*byte as usize) < limit {
+                    token.push(alphabet[*byte as usize % alphabet.len()] as char);
+                }
+            }
+        }
+        Ok(token)
+    }
+
+    /// Decodes a token back into the bytes it was encoded from, after normalizing it.
+    /// Returns None if the token isn't in the encoding.
+    pub fn decode(&self, token: &str) -> Option<Vec<u8>> {
+        let token = self.normalize(token)?;
+        match self {
+            TokenEncoding::Base58 => bs58::decode(token).into_vec().ok(),
+            TokenEncoding::Base58Flickr => bs58::decode(token)
+                .with_alphabet(bs58::Alphabet::FLICKR)
+                .into_vec()
+                .ok(),
+            TokenEncoding::Base62 => Some(decode_radix(&token, BASE62.as_bytes())),
+            TokenEncoding::Crockford32 => decode_base32(&token, CROCKFORD_BASE32.as_bytes()),
+            TokenEncoding::Hex => hex::decode(token).ok(),
+        }
+    }
+
+    /// Checks every character of the token is in the alphabet, returning the token in
+    /// the form it was generated in. Case insensitive encodings are converted to their
+    /// canonical case, and Crockford's base32 aliases are replaced.
+    pub fn normalize(&self, token: &str) -> Option<String> {
+        let normalized: String = match self {
+            TokenEncoding::Crockford32 => token
+                .chars()
+                .map(|c| match c.to_ascii_uppercase() {
+                    'I' | 'L' => '1',
+                    'O' => '0',
+                    c => c,
+                })
+                .collect(),
+            TokenEncoding::Hex => token.to_ascii_lowercase(),
+            _ => token.to_owned(),
+        };
+
+        if normalized.bytes().all(|byte| self.contains(byte)) {
+            Some(normalized)
+        } else {
+            None
+        }
+    }
+
+    /// The shortest and longest token `bytes` random bytes can encode to. The base58
+    /// and base62 encodings turn every leading zero byte into a single character, so
+    /// the shortest token is one character per byte, and the longest is when the bytes
+    /// hold the largest possible number. The other encodings have a fixed length.
+    pub(crate) fn length_range(&self, bytes: usize) -> (usize, usize) {
+        match self {
+            TokenEncoding::Base58 | TokenEncoding::Base58Flickr | TokenEncoding::Base62 => {
+                let base = self.alphabet().len() as f64;
+                let max = (bytes as f64 * 256f64.ln() / base.ln()).ceil() as usize;
+                (bytes, max)
+            }
+            TokenEncoding::Crockford32 => {
+                let length = (bytes as f64 * 8.0 / 5.0).ceil() as usize;
+                (length, length)
+            }
+            TokenEncoding::Hex => (bytes * 2, bytes * 2),
+        }
+    }
+}
+
+// Deriving needs `#[default]`, which is newer than the supported rust version
+#[allow(clippy::derivable_impls)]
+impl Default for TokenEncoding {
+    fn default() -> Self {
+        TokenEncoding::Base58
+    }
+}
+
+impl fmt::Display for TokenEncoding {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let name = match self {
+            TokenEncoding::Base58 => "base58",
+            TokenEncoding::Base58Flickr => "base58 (flickr)",
+            TokenEncoding::Base62 => "base62",
+            TokenEncoding::Crockford32 => "crockford base32",
+            TokenEncoding::Hex => "hex",
+        };
+        write!(f, "{}", name)
+    }
+}
+
+/// Encodes the bytes as a big-endian number in the alphabet's base, the same way as
+/// base58, so leading zero bytes each become the alphabet's first character.
+fn encode_radix(bytes: &[u8], alphabet: &[u8]) -> String {
+    let base = alphabet.len() as u32;
+    let zeros = bytes.iter().take_while(|byte| **byte == 0).count();
+
+    // Little-endian digits of the number, in the alphabet's base
+    let mut digits: Vec<u8> = Vec::new();
+    for byte in &bytes[zeros..] {
+        let mut carry = *byte as u32;
+        for digit in digits.iter_mut() {
+            carry += (*digit as u32) << 8;
+            *digit = (carry % base) as u8;
+            carry /= base;
+        }
+        while carry > 0 {
+            digits.push((carry % base) as u8);
+            carry /= base;
+        }
+    }
+
+    let mut encoded = String::with_capacity(zeros + digits.len());
+    encoded.push_str(&(alphabet[0] as char).to_string().repeat(zeros));
+    encoded.extend(
+        digits
+            .iter()
+            .rev()
+            .map(|digit| alphabet[*digit as usize] as char),
+    );
+    encoded
+}
+
+/// Decodes a token produced by [encode_radix]. Every character must be in the alphabet.
+fn decode_radix(token: &str, alphabet: &[u8]) -> Vec<u8> {
+    let base = alphabet.len() as u32;
+    let zeros = token
+        .bytes()
+        .take_while(|byte| *byte == alphabet[0])
+        .count();
+
+    // Little-endian bytes of the number
+    let mut bytes: Vec<u8> = Vec::new();
+    for c in token.bytes().skip(zeros) {
+        let mut carry = alphabet.iter().position(|a| *a == c).unwrap_or_default() as u32;
+        for byte in bytes.iter_mut() {
+            carry += *byte as u32 * base;
+            *byte = (carry & 0xff) as u8;
+            carry >>= 8;
+        }
+        while carry > 0 {
+            bytes.push((carry & 0xff) as u8);
+            carry >>= 8;
+        }
+    }
+
+    let mut decoded = vec![0u8; zeros];
+    decoded.extend(bytes.iter().rev());
+    decoded
+}
+
+/// Decodes a token produced by [encode_base32], rejecting tokens whose length or padding
+/// bits couldn't have come from it. Every character must be in the alphabet.
+fn decode_base32(token: &str, alphabet: &[u8]) -> Option<Vec<u8>> {
+    let mut decoded = Vec::with_capacity(token.len() * 5 / 8);
+    let mut buffer = 0u16;
+    let mut bits = 0;
+    for c in token.bytes() {
+        let value = alphabet.iter().position(|a| *a == c)? as u16;
+        buffer = (buffer << 5) | value;
+        bits += 5;
+        if bits >= 8 {
+            bits -= 8;
+            decoded.push((buffer >> bits) as u8);
+        }
+    }
+
+    // Anything left over is padding, which is always fewer than 5 zero bits
+    if bits >= 5 || buffer & ((1 << bits) - 1) != 0 {
+        return None;
+    }
+    Some(decoded)
+}
+
+/// Encodes the bytes five bits at a time, padding the last character with zero bits
+fn encode_base32(bytes: &[u8], alphabet: &[u8]) -> String {
+    let mut encoded = String::with_capacity(bytes.len() * 8 / 5 + 1);
+    let mut buffer = 0u16;
+    let mut bits = 0;
+    for byte in bytes {
+        buffer = (buffer << 8) | *byte as u16;
+        bits += 8;
+        while bits >= 5 {
+            bits -= 5;
+            encoded.push(alphabet[((buffer >> bits) & 0x1f) as usize] as char);
+        }
+    }
+    if bits > 0 {
+        encoded.push(alphabet[((buffer << (5 - bits)) & 0x1f) as usize] as char);
+    }
+    encoded
+}
+
+#[cfg(test)]
+mod token_encoding_tests {
+    use std::convert::Infallible;
+
+    use rand::rngs::OsRng;
+    use rand::RngCore;
+
+    use super::{LengthUnit, TokenEncoding};
+
+    const ALL: [TokenEncoding; 5] = [
+        TokenEncoding::Base58,
+        TokenEncoding::Base58Flickr,
+        TokenEncoding::Base62,
+        TokenEncoding::Crockford32,
+        TokenEncoding::Hex,
+    ];
+
+    #[test]
+    fn encodes_known_values() {
+        let bytes = [0u8, 0, 1, 255];
+        assert_eq!(TokenEncoding::Base58.encode(&bytes), "119p");
+        assert_eq!(TokenEncoding::Base58Flickr.encode(&bytes), "119P");
+        assert_eq!(TokenEncoding::Base62.encode(&bytes), "008F");
+        assert_eq!(TokenEncoding::Crockford32.encode(&bytes), "00003ZR");
+        assert_eq!(TokenEncoding::Hex.encode(&bytes), "000001ff");
+        assert_eq!(TokenEncoding::Base62.encode(&[]), "");
+    }
+
+    #[test]
+    fn length_range_covers_encoded_tokens() {
+        assert_eq!(TokenEncoding::Base58.length_range(0), (0, 0));
+        assert_eq!(TokenEncoding::Base58.length_range(8), (8, 11));
+        assert_eq!(TokenEncoding::Base58.length_range(24), (24, 33));
+        assert_eq!(TokenEncoding::Base62.length_range(24), (24, 33));
+        assert_eq!(TokenEncoding::Crockford32.length_range(8), (13, 13));
+        assert_eq!(TokenEncoding::Hex.length_range(8), (16, 16));
+
+        for encoding in ALL {
+            for bytes in [0, 1, 8, 24] {
+                let (min, max) = encoding.length_range(bytes);
+                assert_eq!(encoding.encode(&vec![0; bytes]).len(), min, "{}", encoding);
+                assert_eq!(
+                    encoding.encode(&vec![255; bytes]).len(),
+                    max,
+                    "{}",
+                    encoding
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn normalizes_to_alphabet() {
+        for encoding in ALL {
+            let token = encoding.encode(&[7, 99, 180, 255, 3]);
+            assert_eq!(encoding.normalize(&token), Some(token), "{}", encoding);
+            assert_eq!(encoding.normalize("ab_c"), None, "{}", encoding);
+        }
+
+        assert_eq!(TokenEncoding::Base58.normalize("0OIl"), None);
+        assert_eq!(
+            TokenEncoding::Crockford32.normalize("abo1-il"),
+            None,
+            "dashes aren't accepted"
+        );
+        assert_eq!(
+            TokenEncoding::Crockford32.normalize("7zoil"),
+            Some("7Z011".to_owned())
+        );
+        assert_eq!(TokenEncoding::Crockford32.normalize("U"), None);
+        assert_eq!(
+            TokenEncoding::Hex.normalize("00FF"),
+            Some("00ff".to_owned())
+        );
+        assert_eq!(TokenEncoding::Hex.normalize("0g"), None);
+    }
+
+    #[test]
+    fn samples_exact_lengths() {
+        for encoding in ALL {
+            for length in [0, 1, 8, 33] {
+                let token = encoding
+                    .sample(length, |bytes| {
+                        OsRng.fill_bytes(bytes);
+                        Ok::<_, Infallible>(())
+                    })
+                    .unwrap();
+                assert_eq!(token.len(), length, "{}", encoding);
+                assert_eq!(encoding.normalize(&token), Some(token), "{}", encoding);
+            }
+        }
+        assert_eq!(TokenEncoding::Hex.bits_per_character(), 4.0);
+        assert_eq!(TokenEncoding::Crockford32.bits_per_character(), 5.0);
+    }
+
+    #[test]
+    fn sampling_rejects_biased_bytes() {
+        // 232 is the first byte which would wrap around the base58 alphabet
+        let mut source = vec![vec![232, 0], vec![57]].into_iter();
+        let token = TokenEncoding::Base58
+            .sample(2, |bytes| {
+                let next = source.next().unwrap();
+                bytes.copy_from_slice(&next);
+                Ok::<_, Infallible>(())
+            })
+            .unwrap();
+        assert_eq!(token, "1z");
+    }
+
+    #[test]
+    fn length_for_entropy_reaches_entropy() {
+        for encoding in ALL {
+            for unit in [LengthUnit::Bytes, LengthUnit::Characters] {
+                for literal in [0, 3] {
+                    for bits in [1, 64, 128, 129] {
+                        let length = encoding.length_for_entropy(unit, bits, literal);
+                        assert!(encoding.entropy_bits(unit, length, literal) >= bits as f64);
+                        assert!(encoding.entropy_bits(unit, length - 1, literal) < bits as f64);
+                    }
+                }
+            }
+        }
+
+        assert_eq!(
+            TokenEncoding::Base58.length_for_entropy(LengthUnit::Characters, 128, 0),
+            22
+        );
+        assert_eq!(
+            TokenEncoding::Hex.length_for_entropy(LengthUnit::Bytes, 128, 2),
+            34
+        );
+    }
+
+    #[test]
+    fn decodes_encoded_tokens() {
+        for encoding in ALL {
+            for bytes in [vec![], vec![0, 0, 1, 255], vec![255; 24], vec![7, 0, 99]] {
+                let token = encoding.encode(&bytes);
+                assert_eq!(encoding.decode(&token), Some(bytes), "{}", encoding);
+            }
+            assert_eq!(encoding.decode("ab_c"), None, "{}", encoding);
+        }
+
+        assert_eq!(
+            TokenEncoding::Crockford32.decode("00003zr"),
+            Some(vec![0, 0, 1, 255])
+        );
+        assert_eq!(TokenEncoding::Crockford32.decode("00003ZS"), None);
+        assert_eq!(TokenEncoding::Hex.decode("abc"), None);
+    }
+}
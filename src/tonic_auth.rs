@@ -0,0 +1,159 @@
+use std::sync::Arc;
+
+use tonic::service::Interceptor;
+use tonic::{Request, Status};
+
+use crate::authenticator::{
+    authenticate_request, AuthRejection, KeyAuthenticator, SharedAuthenticator,
+};
+use crate::extract::CredentialExtractor;
+
+/// A [tonic](https://docs.rs/tonic) interceptor which authenticates the api key in
+/// each request's `authorization` metadata entry, using the `Bearer` scheme.
+///
+/// On success the authenticated [ApiKeyRecord](crate::ApiKeyRecord) is inserted into
+/// the request's extensions. Failures are returned as `Status::unauthenticated` with a
+/// generic message, so clients can't tell unknown keys apart from revoked ones.
+///
+/// Interceptors are synchronous, so the store and blocklist are queried on the
+/// executor's thread and mustn't block (see [KeyAuthenticator]).
+///
+/// Requires the "tonic" feature, which needs Rust 1.88 or newer
+#[derive(Clone)]
+pub struct PakAuthInterceptor {
+    authenticator: SharedAuthenticator,
+    extractor: CredentialExtractor,
+}
+
+impl PakAuthInterceptor {
+    pub fn new<A: KeyAuthenticator + Send + Sync + 'static>(
+        authenticator: A,
+    ) -> PakAuthInterceptor {
+        PakAuthInterceptor::from_arc(Arc::new(authenticator))
+    }
+
+    /// Creates the interceptor from an authenticator which is shared with other parts
+    /// of the application.
+    pub fn from_arc(authenticator: SharedAuthenticator) -> PakAuthInterceptor {
+        PakAuthInterceptor {
+            authenticator,
+            extractor: CredentialExtractor::bearer(),
+        }
+    }
+
+    /// Reads the key from the extractor's sources, like an `x-api-key` metadata entry,
+    /// instead of the `authorization` entry.
+    pub fn extractor(mut self, extractor: CredentialExtractor) -> Self {
+        self.extractor = extractor;
+        self
+    }
+}
+
+impl Interceptor for PakAuthInterceptor {
+    fn call(&mut self, mut request: Request<()>) -> Result<Request<()>, Status> {
+        let authenticator = &*self.authenticator;
+        match authenticate_request(authenticator, &self.extractor, request.metadata()) {
+            Ok(record) => {
+                request.extensions_mut().insert(record);
+                Ok(request)
+            }
+            Err(AuthRejection::MissingKey) => Err(Status::unauthenticated("missing api key")),
+            Err(AuthRejection::Internal) => Err(Status::internal("api key could not be verified")),
+            Err(_) => Err(Status::unauthenticated("invalid api key")),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tonic_auth_tests {
+    use rand::rngs::OsRng;
+    use sha2::Sha256;
+    use tonic::service::Interceptor;
+    use tonic::{Code, Request};
+
+    use super::PakAuthInterceptor;
+    use crate::{
+        ApiKeyRecord, Authenticator, Blocklist, CredentialExtractor, CredentialSource, KeyStore,
+        MemoryBlocklist, MemoryKeyStore, PrefixedApiKey, PrefixedApiKeyController,
+    };
+
+    fn setup() -> (PakAuthInterceptor, PrefixedApiKey, PrefixedApiKey) {
+        let controller =
+            PrefixedApiKeyController::<_, Sha256>::new("mycompany".to_owned(), OsRng, None, 8, 24);
+        let (pak, record) = controller.generate_key_and_record();
+        let (revoked_pak, revoked_record) = controller.generate_key_and_record();
+        let store = MemoryKeyStore::new();
+        store
+            .put(record.with_owner_id("owner-1".to_owned()))
+            .unwrap();
+        store.put(revoked_record).unwrap();
+        let blocklist = MemoryBlocklist::new();
+        blocklist
+            .revoke(revoked_pak.short_token(), "leaked", "alice")
+            .unwrap();
+        let authenticator = Authenticator::new(controller, store, blocklist);
+        (PakAuthInterceptor::new(authenticator), pak, revoked_pak)
+    }
+
+    fn request(authorization: Option<String>) -> Request<()> {
+        let mut request = Request::new(());
+        if let Some(value) = authorization {
+            request
+                .metadata_mut()
+                .insert("authorization", value.parse().unwrap());
+        }
+        request
+    }
+
+    #[test]
+    fn attaches_record_to_extensions() {
+        let (mut interceptor, pak, _) = setup();
+
+        let request = interceptor
+            .call(request(Some(format!("Bearer {}", pak.to_string()))))
+            .unwrap();
+        let record = request.extensions().get::<ApiKeyRecord>().unwrap();
+        assert_eq!(record.owner_id.as_deref(), Some("owner-1"));
+    }
+
+    #[test]
+    fn reads_key_from_configured_metadata() {
+        let (interceptor, pak, _) = setup();
+        let mut interceptor =
+            interceptor.extractor(CredentialExtractor::new(vec![CredentialSource::Header(
+                "x-api-key".to_owned(),
+            )]));
+
+        let mut request = Request::new(());
+        request
+            .metadata_mut()
+            .insert("x-api-key", pak.to_string().parse().unwrap());
+        let request = interceptor.call(request).unwrap();
+        let record = request.extensions().get::<ApiKeyRecord>().unwrap();
+        assert_eq!(record.owner_id.as_deref(), Some("owner-1"));
+
+        let status = interceptor
+            .call(self::request(Some(format!("Bearer {}", pak.to_string()))))
+            .unwrap_err();
+        assert_eq!(status.message(), "missing api key");
+    }
+
+    #[test]
+    fn rejects_without_leaking_details() {
+        let (mut interceptor, _, revoked_pak) = setup();
+
+        let status = interceptor.call(request(None)).unwrap_err();
+        assert_eq!(status.code(), Code::Unauthenticated);
+        assert_eq!(status.message(), "missing api key");
+
+        for value in [
+            "Bearer malformed".to_owned(),
+            "Bearer mycompany_unknown_token".to_owned(),
+            format!("Bearer {}", revoked_pak.to_string()),
+        ] {
+            let status = interceptor.call(request(Some(value))).unwrap_err();
+            assert_eq!(status.code(), Code::Unauthenticated);
+            assert_eq!(status.message(), "invalid api key");
+        }
+    }
+}
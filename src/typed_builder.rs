@@ -0,0 +1,301 @@
+use std::marker::PhantomData;
+use std::time::Duration;
+
+use digest::{Digest, FixedOutputReset};
+use rand::{
+    rngs::{OsRng, StdRng, ThreadRng},
+    RngCore, SeedableRng,
+};
+
+use crate::controller::PrefixedApiKeyController;
+use crate::controller_builder::{BuilderError, ControllerBuilder};
+#[cfg(feature = "derivation")]
+use crate::derivation::DerivationKey;
+use crate::key_format::KeyFormat;
+#[cfg(feature = "signed-keys")]
+use crate::signed_key::KeySigner;
+use crate::token_encoding::{LengthUnit, TokenEncoding};
+
+/// Marks a required [TypedControllerBuilder] field which hasn't been set yet
+#[derive(Debug, Clone, Copy)]
+pub struct Unconfigured;
+
+/// Marks a required [TypedControllerBuilder] field which has been set
+#[derive(Debug, Clone, Copy)]
+pub struct Configured;
+
+/// A variant of [ControllerBuilder] which tracks the required fields in its type, so
+/// `finalize` only exists once the prefix, rng, and short and long token lengths are
+/// set. Forgetting one is a compile error rather than a `BuilderError::Missing*` at
+/// runtime:
+///
+/// ```compile_fail
+/// use prefixed_api_key::rand::rngs::OsRng;
+/// use prefixed_api_key::TypedControllerBuilder;
+/// use sha2::Sha256;
+///
+/// // No rng was set, so there's no finalize method
+/// let controller = TypedControllerBuilder::<OsRng, Sha256>::new()
+///     .prefix("mycompany".to_owned())
+///     .default_lengths()
+///     .finalize();
+/// ```
+///
+/// `finalize` can still fail if the configuration is invalid, like an environment
+/// containing `_` or a long token with too little entropy. Use [ControllerBuilder] when
+/// the configuration is only known at runtime.
+pub struct TypedControllerBuilder<
+    R: RngCore + Clone,
+    D: Digest + FixedOutputReset,
+    Prefix = Unconfigured,
+    Rng = Unconfigured,
+    ShortLength = Unconfigured,
+    LongLength = Unconfigured,
+> {
+    inner: ControllerBuilder<R, D>,
+    state: PhantomData<(Prefix, Rng, ShortLength, LongLength)>,
+}
+
+impl<R: RngCore + Clone, D: Digest + FixedOutputReset> TypedControllerBuilder<R, D> {
+    pub fn new() -> TypedControllerBuilder<R, D> {
+        TypedControllerBuilder {
+            inner: ControllerBuilder::new(),
+            state: PhantomData,
+        }
+    }
+}
+
+impl<R: RngCore + Clone, D: Digest + FixedOutputReset> Default for TypedControllerBuilder<R, D> {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl<R: RngCore + Clone, D: Digest + FixedOutputReset>
+    TypedControllerBuilder<R, D, Configured, Configured, Configured, Configured>
+{
+    /// Finishes building the controller. Every required field is set, so this only fails
+    /// if the configuration is invalid, as described by [ControllerBuilder::finalize].
+    pub fn finalize(self) -> Result<PrefixedApiKeyController<R, D>, BuilderError> {
+        self.inner.finalize()
+    }
+}
+
+impl<R, D, Prefix, Rng, ShortLength, LongLength>
+    TypedControllerBuilder<R, D, Prefix, Rng, ShortLength, LongLength>
+where
+    R: RngCore + Clone,
+    D: Digest + FixedOutputReset,
+{
+    /// Applies a change to the inner builder, moving to a new state
+    fn transition<P, G, S, L>(
+        self,
+        change: impl FnOnce(ControllerBuilder<R, D>) -> ControllerBuilder<R, D>,
+    ) -> TypedControllerBuilder<R, D, P, G, S, L> {
+        TypedControllerBuilder {
+            inner: change(self.inner),
+            state: PhantomData,
+        }
+    }
+
+    /// Applies a change to the inner builder, staying in the same state
+    fn update(
+        self,
+        change: impl FnOnce(ControllerBuilder<R, D>) -> ControllerBuilder<R, D>,
+    ) -> Self {
+        self.transition(change)
+    }
+
+    /// See [ControllerBuilder::prefix]
+    pub fn prefix(
+        self,
+        prefix: String,
+    ) -> TypedControllerBuilder<R, D, Configured, Rng, ShortLength, LongLength> {
+        self.transition(|inner| inner.prefix(prefix))
+    }
+
+    /// See [ControllerBuilder::rng]
+    pub fn rng(
+        self,
+        rng: R,
+    ) -> TypedControllerBuilder<R, D, Prefix, Configured, ShortLength, LongLength> {
+        self.transition(|inner| inner.rng(rng))
+    }
+
+    /// See [ControllerBuilder::short_token_length]
+    pub fn short_token_length(
+        self,
+        short_token_length: usize,
+    ) -> TypedControllerBuilder<R, D, Prefix, Rng, Configured, LongLength> {
+        self.transition(|inner| inner.short_token_length(short_token_length))
+    }
+
+    /// See [ControllerBuilder::short_token_entropy_bits]
+    pub fn short_token_entropy_bits(
+        self,
+        bits: u32,
+    ) -> TypedControllerBuilder<R, D, Prefix, Rng, Configured, LongLength> {
+        self.transition(|inner| inner.short_token_entropy_bits(bits))
+    }
+
+    /// See [ControllerBuilder::long_token_length]
+    pub fn long_token_length(
+        self,
+        long_token_length: usize,
+    ) -> TypedControllerBuilder<R, D, Prefix, Rng, ShortLength, Configured> {
+        self.transition(|inner| inner.long_token_length(long_token_length))
+    }
+
+    /// See [ControllerBuilder::long_token_entropy_bits]
+    pub fn long_token_entropy_bits(
+        self,
+        bits: u32,
+    ) -> TypedControllerBuilder<R, D, Prefix, Rng, ShortLength, Configured> {
+        self.transition(|inner| inner.long_token_entropy_bits(bits))
+    }
+
+    /// See [ControllerBuilder::default_lengths]
+    pub fn default_lengths(
+        self,
+    ) -> TypedControllerBuilder<R, D, Prefix, Rng, Configured, Configured> {
+        self.transition(|inner| inner.default_lengths())
+    }
+
+    /// See [ControllerBuilder::short_token_prefix]
+    pub fn short_token_prefix(self, short_token_prefix: Option<String>) -> Self {
+        self.update(|inner| inner.short_token_prefix(short_token_prefix))
+    }
+
+    /// See [ControllerBuilder::minimum_short_token_entropy_bits]
+    pub fn minimum_short_token_entropy_bits(self, bits: u32) -> Self {
+        self.update(|inner| inner.minimum_short_token_entropy_bits(bits))
+    }
+
+    /// See [ControllerBuilder::minimum_long_token_entropy_bits]
+    pub fn minimum_long_token_entropy_bits(self, bits: u32) -> Self {
+        self.update(|inner| inner.minimum_long_token_entropy_bits(bits))
+    }
+
+    /// See [ControllerBuilder::token_encoding]
+    pub fn token_encoding(self, encoding: TokenEncoding) -> Self {
+        self.update(|inner| inner.token_encoding(encoding))
+    }
+
+    /// See [ControllerBuilder::length_unit]
+    pub fn length_unit(self, length_unit: LengthUnit) -> Self {
+        self.update(|inner| inner.length_unit(length_unit))
+    }
+
+    /// See [ControllerBuilder::environment]
+    pub fn environment(self, environment: String) -> Self {
+        self.update(|inner| inner.environment(environment))
+    }
+
+    /// See [ControllerBuilder::key_format]
+    pub fn key_format<F: KeyFormat + Send + Sync + 'static>(self, format: F) -> Self {
+        self.update(|inner| inner.key_format(format))
+    }
+
+    /// See [ControllerBuilder::touch_last_used]
+    pub fn touch_last_used(self, throttle: Duration) -> Self {
+        self.update(|inner| inner.touch_last_used(throttle))
+    }
+
+    /// See [ControllerBuilder::derive_long_tokens]
+    ///
+    /// Requires the "derivation" feature
+    #[cfg(feature = "derivation")]
+    pub fn derive_long_tokens(self, derivation: DerivationKey) -> Self {
+        self.update(|inner| inner.derive_long_tokens(derivation))
+    }
+
+    /// See [ControllerBuilder::sign_keys]. The long token length is set by the signer,
+    /// so it's marked as configured.
+    ///
+    /// Requires the "signed-keys" feature
+    #[cfg(feature = "signed-keys")]
+    pub fn sign_keys<S: KeySigner + 'static>(
+        self,
+        signer: S,
+    ) -> TypedControllerBuilder<R, D, Prefix, Rng, ShortLength, Configured> {
+        self.transition(|inner| inner.sign_keys(signer))
+    }
+}
+
+impl<D, Prefix, Rng, ShortLength, LongLength>
+    TypedControllerBuilder<OsRng, D, Prefix, Rng, ShortLength, LongLength>
+where
+    D: Digest + FixedOutputReset,
+{
+    /// See [ControllerBuilder::rng_osrng]
+    pub fn rng_osrng(
+        self,
+    ) -> TypedControllerBuilder<OsRng, D, Prefix, Configured, ShortLength, LongLength> {
+        self.rng(OsRng)
+    }
+}
+
+impl<D, Prefix, Rng, ShortLength, LongLength>
+    TypedControllerBuilder<ThreadRng, D, Prefix, Rng, ShortLength, LongLength>
+where
+    D: Digest + FixedOutputReset,
+{
+    /// See [ControllerBuilder::rng_threadrng]
+    pub fn rng_threadrng(
+        self,
+    ) -> TypedControllerBuilder<ThreadRng, D, Prefix, Configured, ShortLength, LongLength> {
+        self.rng(ThreadRng::default())
+    }
+}
+
+impl<D, Prefix, Rng, ShortLength, LongLength>
+    TypedControllerBuilder<StdRng, D, Prefix, Rng, ShortLength, LongLength>
+where
+    D: Digest + FixedOutputReset,
+{
+    /// See [ControllerBuilder::rng_stdrng]
+    pub fn rng_stdrng(
+        self,
+    ) -> TypedControllerBuilder<StdRng, D, Prefix, Configured, ShortLength, LongLength> {
+        self.rng(StdRng::from_entropy())
+    }
+}
+
+#[cfg(test)]
+mod typed_builder_tests {
+    use rand::rngs::OsRng;
+    use sha2::Sha256;
+
+    use super::TypedControllerBuilder;
+    use crate::{BuilderError, PrefixedApiKeyController, TokenEncoding};
+
+    #[test]
+    fn finalizes_once_required_fields_are_set() {
+        // The required fields can be set in any order
+        let controller = TypedControllerBuilder::<_, Sha256>::new()
+            .long_token_length(24)
+            .token_encoding(TokenEncoding::Base62)
+            .rng(OsRng)
+            .short_token_length(8)
+            .prefix("mycompany".to_owned())
+            .finalize()
+            .unwrap();
+        assert_eq!(controller.prefix(), "mycompany");
+        assert_eq!(controller.token_encoding(), TokenEncoding::Base62);
+
+        let pak = controller.generate_key();
+        let hash = controller.long_token_hashed(&pak);
+        assert!(controller.check_hash(&pak, &hash));
+    }
+
+    #[test]
+    fn still_validates_configuration() {
+        let result = PrefixedApiKeyController::<OsRng, Sha256>::configure_typed()
+            .prefix("mycompany".to_owned())
+            .rng_osrng()
+            .default_lengths()
+            .environment("live_eu".to_owned())
+            .finalize();
+        assert!(matches!(result, Err(BuilderError::InvalidEnvironment(_))));
+    }
+}